@@ -0,0 +1,193 @@
+use chrono::Utc;
+use log::warn;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditOrder {
+    pub side: String,
+    pub price: f64,
+    pub amount: f64,
+    pub order_id: Option<String>,
+    pub error: Option<String>,
+}
+
+// one evaluated position: what the strategy saw, what it computed and
+// what it did about it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub timestamp: u64,
+    pub strategy: String,
+    pub symbol: String,
+    pub price: f64,
+    pub amount: f64,
+    pub entry_price: f64,
+    pub values: BTreeMap<String, f64>,
+    pub decision: String,
+    pub orders: Vec<AuditOrder>,
+}
+
+impl AuditEntry {
+    pub fn new(strategy: &str, symbol: &str, timestamp: u64) -> Self {
+        AuditEntry {
+            timestamp: if timestamp > 0 {
+                timestamp
+            } else {
+                Utc::now().timestamp_millis() as u64
+            },
+            strategy: strategy.into(),
+            symbol: symbol.into(),
+            price: 0f64,
+            amount: 0f64,
+            entry_price: 0f64,
+            values: BTreeMap::new(),
+            decision: "hold".into(),
+            orders: vec![],
+        }
+    }
+
+    pub fn value(&mut self, name: &str, value: f64) -> &mut Self {
+        self.values.insert(name.into(), value);
+        self
+    }
+
+    pub fn order<E: std::fmt::Debug>(
+        &mut self,
+        side: &str,
+        price: f64,
+        amount: f64,
+        ret: &Result<String, E>,
+    ) -> &mut Self {
+        self.orders.push(AuditOrder {
+            side: side.into(),
+            price: price,
+            amount: amount,
+            order_id: ret.as_ref().ok().cloned(),
+            error: ret.as_ref().err().map(|err| format!("{:?}", err)),
+        });
+        self
+    }
+}
+
+// json lines file, one AuditEntry per line, disabled without "audit_file"
+#[derive(Debug, Default)]
+pub struct AuditLog {
+    file: Option<File>,
+}
+
+impl AuditLog {
+    pub fn from_config(config: &Value) -> Self {
+        let path = match config["audit_file"].as_str() {
+            Some(path) => path,
+            None => return AuditLog::default(),
+        };
+        match OpenOptions::new().create(true).append(true).open(path) {
+            Ok(file) => AuditLog { file: Some(file) },
+            Err(err) => {
+                warn!("open audit file {} error: {:?}", path, err);
+                AuditLog::default()
+            }
+        }
+    }
+
+    pub fn record(&mut self, entry: &AuditEntry) {
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return,
+        };
+        let line = serde_json::to_string(entry).unwrap();
+        if let Err(err) = writeln!(file, "{}", line) {
+            warn!("write audit log error: {:?}", err);
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Query {
+    pub symbol: Option<String>,
+    pub decision: Option<String>,
+    pub from: Option<u64>,
+    pub to: Option<u64>,
+}
+
+impl Query {
+    pub fn matches(&self, entry: &AuditEntry) -> bool {
+        if let Some(symbol) = &self.symbol {
+            if !entry.symbol.eq_ignore_ascii_case(symbol) {
+                return false;
+            }
+        }
+        if let Some(decision) = &self.decision {
+            if !entry.decision.split(',').any(|d| d == decision) {
+                return false;
+            }
+        }
+        self.from.map(|from| entry.timestamp >= from).unwrap_or(true)
+            && self.to.map(|to| entry.timestamp < to).unwrap_or(true)
+    }
+}
+
+// entries of the audit log at `path` matching `query`, lines that fail
+// to parse (e.g. a partial last line) are skipped
+pub fn read(path: &str, query: &Query) -> io::Result<Vec<AuditEntry>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut entries = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        match serde_json::from_str::<AuditEntry>(&line) {
+            Ok(entry) if query.matches(&entry) => entries.push(entry),
+            Ok(_) => {}
+            Err(err) => warn!("skip audit line: {:?}", err),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::{env, fs};
+
+    #[test]
+    fn test_write_and_read() {
+        let path = env::temp_dir().join(format!("rsquant-audit-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_file(path);
+        let mut log = AuditLog::from_config(&json!({ "audit_file": path }));
+
+        let mut hold = AuditEntry::new("move_stoploss", "BTCUSDT", 1000);
+        hold.value("diff_ratio", 0.1);
+        log.record(&hold);
+        let mut sell = AuditEntry::new("move_stoploss", "ETHUSDT", 2000);
+        sell.decision = "stoploss".into();
+        sell.value("diff_ratio", -0.06)
+            .order::<String>("SELL", 95f64, 1f64, &Ok("42".into()));
+        log.record(&sell);
+
+        let all = read(path, &Query::default()).unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].values["diff_ratio"], 0.1);
+
+        let query = Query {
+            decision: Some("stoploss".into()),
+            ..Query::default()
+        };
+        let sells = read(path, &query).unwrap();
+        assert_eq!(sells.len(), 1);
+        assert_eq!(sells[0].orders[0].order_id, Some("42".into()));
+
+        let query = Query {
+            symbol: Some("btcusdt".into()),
+            to: Some(1000),
+            ..Query::default()
+        };
+        assert!(read(path, &query).unwrap().is_empty());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+use rsex::models::{Kline, SymbolInfo};
+use serde_json::Value;
+use std::{fs, sync::Arc};
+
+pub type Dataset = Vec<(SymbolInfo, Arc<Vec<Kline>>)>;
+
+// klines csv: timestamp(ms),open,high,low,close,volume, an optional
+// header line and extra columns (binance exports) are ignored
+pub fn read_csv(path: &str) -> Result<Vec<Kline>, String> {
+    let content = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+    let mut klines = vec![];
+    for (i, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(|field| field.trim()).collect();
+        let timestamp = match fields[0].parse::<u64>() {
+            Ok(timestamp) => timestamp,
+            Err(_) if i == 0 => continue,
+            Err(err) => return Err(format!("{}:{}: {}", path, i + 1, err)),
+        };
+        if fields.len() < 6 {
+            return Err(format!("{}:{}: expect 6 columns", path, i + 1));
+        }
+        let mut values = [0f64; 5];
+        for (j, value) in values.iter_mut().enumerate() {
+            *value = fields[j + 1]
+                .parse()
+                .map_err(|err| format!("{}:{}: {}", path, i + 1, err))?;
+        }
+        klines.push(Kline {
+            timestamp: timestamp,
+            open: values[0],
+            high: values[1],
+            low: values[2],
+            close: values[3],
+            volume: values[4],
+        });
+    }
+    klines.sort_by_key(|kline| kline.timestamp);
+    Ok(klines)
+}
+
+pub fn symbol_info(base: &str, quote: &str) -> SymbolInfo {
+    SymbolInfo {
+        base: base.to_uppercase(),
+        quote: quote.to_uppercase(),
+        symbol: format!("{}{}", base, quote).to_uppercase(),
+        price_precision: 8,
+        amount_precision: 8,
+        min_amount: 0f64,
+        min_value: 0f64,
+    }
+}
+
+// load "backtest.symbols" (["BTC/USDT", ...]) from
+// "backtest.data_dir"/BTCUSDT.csv
+pub fn load(config: &Value) -> Result<Dataset, String> {
+    let backtest = &config["backtest"];
+    let dir = backtest["data_dir"].as_str().unwrap_or("./data");
+    let symbols = match backtest["symbols"].as_array() {
+        Some(symbols) => symbols,
+        None => return Err("backtest.symbols not found".into()),
+    };
+    let mut data = vec![];
+    for symbol in symbols {
+        let symbol = symbol.as_str().unwrap_or("");
+        let pair: Vec<&str> = symbol.split('/').collect();
+        if pair.len() != 2 {
+            return Err(format!("invalid symbol: {:?}, expect BASE/QUOTE", symbol));
+        }
+        let info = symbol_info(pair[0], pair[1]);
+        let klines = read_csv(&format!("{}/{}.csv", dir, info.symbol))?;
+        data.push((info, Arc::new(klines)));
+    }
+    Ok(data)
+}
+
+// klines with from <= timestamp < to, symbols without any are dropped
+pub fn slice(data: &Dataset, from: u64, to: u64) -> Dataset {
+    data.iter()
+        .map(|(info, klines)| {
+            let klines: Vec<Kline> = klines
+                .iter()
+                .filter(|kline| kline.timestamp >= from && kline.timestamp < to)
+                .cloned()
+                .collect();
+            (info.clone(), Arc::new(klines))
+        })
+        .filter(|(_, klines)| !klines.is_empty())
+        .collect()
+}
+
+// first and last kline timestamp over all symbols
+pub fn span(data: &Dataset) -> Option<(u64, u64)> {
+    let first = data.iter().filter_map(|(_, k)| k.first()).map(|k| k.timestamp).min()?;
+    let last = data.iter().filter_map(|(_, k)| k.last()).map(|k| k.timestamp).max()?;
+    Some((first, last))
+}
+
+// the strategy tests' market: flat bars on a simulated exchange, replayed
+// tick by tick the way the backtest engine drives a strategy
+#[cfg(test)]
+pub mod fixture {
+    use rsex::models::Kline;
+    use std::{collections::HashMap, sync::Arc};
+
+    use super::Dataset;
+    use crate::{exchange::SimExchange, traits::Strategy};
+
+    // bars closing at `closes`, `interval` ms apart from `start`
+    pub fn bars(closes: &[f64], start: u64, interval: u64) -> Arc<Vec<Kline>> {
+        Arc::new(
+            closes
+                .iter()
+                .enumerate()
+                .map(|(i, close)| Kline {
+                    timestamp: start + i as u64 * interval,
+                    open: *close,
+                    high: *close,
+                    low: *close,
+                    close: *close,
+                    volume: 1f64,
+                })
+                .collect(),
+        )
+    }
+
+    pub fn sim(data: Dataset, balances: &[(&str, f64)], fee: f64) -> SimExchange {
+        let balances: HashMap<String, f64> = balances
+            .iter()
+            .map(|(asset, amount)| (asset.to_string(), *amount))
+            .collect();
+        SimExchange::new(data, &balances, fee, 0f64)
+    }
+
+    // init on the first timestamp of `venues[0]`, then one tick per
+    // timestamp with every venue moved along
+    pub fn replay(venues: &[&SimExchange], robot: &mut dyn Strategy) {
+        for (i, timestamp) in venues[0].timestamps().into_iter().enumerate() {
+            for venue in venues {
+                venue.advance(timestamp);
+            }
+            if i == 0 {
+                robot.init();
+            }
+            robot.on_tick();
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use rsex::constant::ORDER_ACTION_SELL;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::exchange::Fill;
+
+const YEAR_MS: f64 = 365f64 * 86_400_000f64;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Metrics {
+    pub start_equity: f64,
+    pub end_equity: f64,
+    pub net_pnl: f64,
+    pub total_return: f64,
+    pub max_drawdown: f64,
+    pub sharpe: f64,
+    pub trades: usize,
+    pub win_rate: f64,
+    pub fees: f64,
+}
+
+impl Metrics {
+    pub fn new(equity: &[(u64, f64)], fills: &[Fill]) -> Self {
+        let start_equity = equity.first().map(|e| e.1).unwrap_or(0f64);
+        let end_equity = equity.last().map(|e| e.1).unwrap_or(0f64);
+        let trades: Vec<&Fill> = fills
+            .iter()
+            .filter(|fill| fill.side == ORDER_ACTION_SELL)
+            .collect();
+        let wins = trades.iter().filter(|fill| fill.pnl > 0f64).count();
+        Metrics {
+            start_equity: start_equity,
+            end_equity: end_equity,
+            net_pnl: end_equity - start_equity,
+            total_return: if start_equity > 0f64 {
+                end_equity / start_equity - 1f64
+            } else {
+                0f64
+            },
+            max_drawdown: max_drawdown(equity),
+            sharpe: sharpe(equity),
+            trades: trades.len(),
+            win_rate: if trades.is_empty() {
+                0f64
+            } else {
+                wins as f64 / trades.len() as f64
+            },
+            fees: fills.iter().map(|fill| fill.fee).sum(),
+        }
+    }
+}
+
+// largest peak to trough loss as a ratio of the peak
+pub fn max_drawdown(equity: &[(u64, f64)]) -> f64 {
+    let mut peak = f64::MIN;
+    let mut max = 0f64;
+    for (_, value) in equity {
+        peak = peak.max(*value);
+        if peak > 0f64 {
+            max = max.max((peak - value) / peak);
+        }
+    }
+    max
+}
+
+pub fn returns(equity: &[(u64, f64)]) -> Vec<f64> {
+    equity
+        .windows(2)
+        .filter(|w| w[0].1 > 0f64)
+        .map(|w| w[1].1 / w[0].1 - 1f64)
+        .collect()
+}
+
+// annualized by the average spacing of the equity samples
+pub fn sharpe(equity: &[(u64, f64)]) -> f64 {
+    let returns = returns(equity);
+    if returns.len() < 2 {
+        return 0f64;
+    }
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let var = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1f64);
+    if var == 0f64 {
+        return 0f64;
+    }
+    let span = (equity[equity.len() - 1].0 - equity[0].0) as f64;
+    let periods = if span > 0f64 { YEAR_MS * n / span } else { n };
+    mean / var.sqrt() * periods.sqrt()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_max_drawdown() {
+        let equity = vec![(0, 100f64), (1, 120f64), (2, 90f64), (3, 110f64), (4, 60f64)];
+        assert_eq!(max_drawdown(&equity), 0.5);
+        assert_eq!(max_drawdown(&[(0, 1f64), (1, 2f64)]), 0f64);
+    }
+
+    #[test]
+    fn test_sharpe() {
+        let flat = vec![(0, 100f64), (1, 100f64), (2, 100f64)];
+        assert_eq!(sharpe(&flat), 0f64);
+        let up = vec![(0, 100f64), (1, 101f64), (2, 103f64), (3, 104f64)];
+        assert!(sharpe(&up) > 0f64);
+    }
+}
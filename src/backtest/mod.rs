@@ -0,0 +1,105 @@
+use chrono::{TimeZone, Utc};
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+pub mod data;
+pub use data::Dataset;
+
+pub mod metrics;
+pub use metrics::Metrics;
+
+pub mod monte_carlo;
+pub use monte_carlo::MonteCarlo;
+
+pub mod optimize;
+pub use optimize::Optimizer;
+
+pub mod report;
+
+pub mod walk_forward;
+pub use walk_forward::WalkForward;
+
+use crate::{
+    exchange::{Fill, SimExchange},
+    scheduler::Schedule,
+    strategies,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BacktestResult {
+    pub strategy: String,
+    pub currency: String,
+    pub fills: Vec<Fill>,
+    pub equity: Vec<(u64, f64)>,
+    pub metrics: Metrics,
+}
+
+pub fn balances(config: &Value) -> HashMap<String, f64> {
+    match config.as_object() {
+        Some(balances) => balances
+            .iter()
+            .map(|(asset, amount)| (asset.to_uppercase(), amount.as_f64().unwrap_or(0f64)))
+            .collect(),
+        None => HashMap::new(),
+    }
+}
+
+// replay `data` through the strategy configured in `config`, ticking on
+// the first bar at or after each scheduled time
+pub fn run(config: &Value, data: &Dataset) -> Result<BacktestResult, String> {
+    // never write into the live audit log
+    let mut config = config.clone();
+    config["audit_file"] = config["backtest"]["audit_file"].clone();
+    let config = &config;
+    let backtest = &config["backtest"];
+    let currency = backtest["currency"].as_str().unwrap_or("USDT").to_uppercase();
+    let fee = backtest["fee"].as_f64().unwrap_or(0.001);
+    let spread = backtest["spread"].as_f64().unwrap_or(0f64);
+    let exchange = SimExchange::new(data.clone(), &balances(&backtest["balances"]), fee, spread);
+
+    let timestamps = exchange.timestamps();
+    let first = match timestamps.first() {
+        Some(first) => *first,
+        None => return Err("no market data".into()),
+    };
+    exchange.advance(first);
+    if let Some(strategy) = config["strategy"].as_str() {
+        if strategies::MULTI_VENUE.contains(&strategy) {
+            return Err(format!("{} trades several venues, backtests replay one", strategy));
+        }
+    }
+    let mut robot = match strategies::build(config, Box::new(exchange.clone())) {
+        Some(robot) => robot,
+        None => return Err(format!("strategy not found: {:?}", config["strategy"])),
+    };
+    robot.init();
+    let schedule = Schedule::from_config(&config["schedule"]).unwrap_or_else(|err| {
+        warn!("schedule config error: {}, tick every 60s", err);
+        Schedule::default()
+    });
+
+    let mut equity = vec![];
+    let mut next = 0;
+    for timestamp in timestamps {
+        exchange.advance(timestamp);
+        if timestamp >= next {
+            robot.on_tick();
+            let now = Utc.timestamp_millis_opt(timestamp as i64).unwrap();
+            next = schedule.next(now, now).timestamp_millis() as u64;
+        }
+        equity.push((timestamp, exchange.equity(&currency)));
+    }
+
+    let fills = exchange.fills();
+    let metrics = Metrics::new(&equity, &fills);
+    info!("backtest {}: {:?}", robot.name(), metrics);
+    Ok(BacktestResult {
+        strategy: robot.name(),
+        currency: currency,
+        fills: fills,
+        equity: equity,
+        metrics: metrics,
+    })
+}
@@ -0,0 +1,253 @@
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+use rsex::constant::ORDER_ACTION_SELL;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::metrics::max_drawdown;
+use crate::exchange::Fill;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Trade {
+    pub pnl: f64,
+    // exit value, slippage is charged on it
+    pub value: f64,
+}
+
+// closed trades of a backtest
+pub fn from_fills(fills: &[Fill]) -> Vec<Trade> {
+    fills
+        .iter()
+        .filter(|fill| fill.side == ORDER_ACTION_SELL)
+        .map(|fill| Trade {
+            pnl: fill.pnl,
+            value: fill.price * fill.amount,
+        })
+        .collect()
+}
+
+// closed trades of a strategy state, e.g. the "history" of move_stoploss
+pub fn from_history(history: &Value) -> Vec<Trade> {
+    history
+        .as_array()
+        .map(|records| {
+            records
+                .iter()
+                .map(|record| Trade {
+                    pnl: record["profit"].as_f64().unwrap_or(0f64),
+                    value: record["sell_price"].as_f64().unwrap_or(0f64)
+                        * record["amount"].as_f64().unwrap_or(0f64),
+                })
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Distribution {
+    pub mean: f64,
+    pub min: f64,
+    pub p5: f64,
+    pub p25: f64,
+    pub p50: f64,
+    pub p75: f64,
+    pub p95: f64,
+    pub max: f64,
+}
+
+impl Distribution {
+    pub fn new(mut values: Vec<f64>) -> Self {
+        if values.is_empty() {
+            return Distribution::default();
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let n = values.len();
+        // nearest rank
+        let at = |p: f64| values[((p * n as f64).ceil() as usize).clamp(1, n) - 1];
+        Distribution {
+            mean: values.iter().sum::<f64>() / n as f64,
+            min: values[0],
+            p5: at(0.05),
+            p25: at(0.25),
+            p50: at(0.5),
+            p75: at(0.75),
+            p95: at(0.95),
+            max: values[n - 1],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Report {
+    pub runs: usize,
+    pub trades: usize,
+    pub capital: f64,
+    pub final_pnl: Distribution,
+    pub max_drawdown: Distribution,
+    // share of runs whose equity fell to capital * (1 - ruin)
+    pub risk_of_ruin: f64,
+}
+
+// "monte_carlo" config:
+//   {"runs": 1000, "method": "shuffle" | "bootstrap", "seed": 1,
+//    "slippage": 0.001, "skip": 0.05, "capital": 1000, "ruin": 0.5}
+// shuffle reorders the trades, bootstrap draws them with replacement.
+// every trade additionally loses up to `slippage` of its exit value and
+// is missed with probability `skip`
+#[derive(Debug, Clone)]
+pub struct MonteCarlo {
+    runs: usize,
+    bootstrap: bool,
+    seed: u64,
+    slippage: f64,
+    skip: f64,
+    capital: Option<f64>,
+    ruin: f64,
+}
+
+impl MonteCarlo {
+    pub fn from_config(config: &Value) -> Result<Self, String> {
+        let bootstrap = match config["method"].as_str().unwrap_or("shuffle") {
+            "shuffle" => false,
+            "bootstrap" => true,
+            other => return Err(format!("unknown monte carlo method: {}", other)),
+        };
+        let skip = config["skip"].as_f64().unwrap_or(0f64);
+        if !(0f64..1f64).contains(&skip) {
+            return Err(format!("skip should be in [0, 1): {}", skip));
+        }
+        let ruin = config["ruin"].as_f64().unwrap_or(0.5);
+        if ruin <= 0f64 || ruin > 1f64 {
+            return Err(format!("ruin should be in (0, 1]: {}", ruin));
+        }
+        Ok(MonteCarlo {
+            runs: config["runs"].as_u64().unwrap_or(1000).max(1) as usize,
+            bootstrap: bootstrap,
+            seed: config["seed"].as_u64().unwrap_or(1),
+            slippage: config["slippage"].as_f64().unwrap_or(0f64).max(0f64),
+            skip: skip,
+            capital: config["capital"].as_f64(),
+            ruin: ruin,
+        })
+    }
+
+    // `capital` is used unless the config sets one
+    pub fn run(&self, trades: &[Trade], capital: f64) -> Result<Report, String> {
+        if trades.is_empty() {
+            return Err("no trades".into());
+        }
+        let capital = self.capital.unwrap_or(capital);
+        if capital <= 0f64 {
+            return Err("capital should be positive".into());
+        }
+        let floor = capital * (1f64 - self.ruin);
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut pnls = Vec::with_capacity(self.runs);
+        let mut drawdowns = Vec::with_capacity(self.runs);
+        let mut ruined = 0;
+        let mut sequence = trades.to_vec();
+        for _ in 0..self.runs {
+            if self.bootstrap {
+                for trade in sequence.iter_mut() {
+                    *trade = trades[rng.gen_range(0, trades.len())];
+                }
+            } else {
+                sequence.shuffle(&mut rng);
+            }
+            let mut equity = Vec::with_capacity(sequence.len() + 1);
+            let mut value = capital;
+            equity.push((0, value));
+            for (i, trade) in sequence.iter().enumerate() {
+                if self.skip > 0f64 && rng.gen::<f64>() < self.skip {
+                    continue;
+                }
+                let slippage = if self.slippage > 0f64 {
+                    trade.value * rng.gen_range(0f64, self.slippage)
+                } else {
+                    0f64
+                };
+                value += trade.pnl - slippage;
+                equity.push((i as u64 + 1, value));
+            }
+            if equity.iter().any(|(_, value)| *value <= floor) {
+                ruined += 1;
+            }
+            pnls.push(value - capital);
+            drawdowns.push(max_drawdown(&equity));
+        }
+        Ok(Report {
+            runs: self.runs,
+            trades: trades.len(),
+            capital: capital,
+            final_pnl: Distribution::new(pnls),
+            max_drawdown: Distribution::new(drawdowns),
+            risk_of_ruin: ruined as f64 / self.runs as f64,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn trades(pnls: &[f64]) -> Vec<Trade> {
+        pnls.iter()
+            .map(|pnl| Trade {
+                pnl: *pnl,
+                value: 100f64,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_distribution() {
+        let d = Distribution::new((1..=100).rev().map(|i| i as f64).collect());
+        assert_eq!(d.min, 1f64);
+        assert_eq!(d.p5, 5f64);
+        assert_eq!(d.p50, 50f64);
+        assert_eq!(d.p95, 95f64);
+        assert_eq!(d.mean, 50.5);
+    }
+
+    #[test]
+    fn test_monte_carlo() {
+        let trades = trades(&[30f64, -20f64, 10f64, -40f64, 25f64]);
+        let mc = MonteCarlo::from_config(&json!({"runs": 200})).unwrap();
+        let report = mc.run(&trades, 100f64).unwrap();
+        // shuffling keeps the total, only the path changes
+        assert!((report.final_pnl.min - 5f64).abs() < 1e-9);
+        assert!((report.final_pnl.max - 5f64).abs() < 1e-9);
+        assert!(report.max_drawdown.max > report.max_drawdown.min);
+        // -20 then -40 first takes 60% off 100
+        assert!(report.risk_of_ruin > 0f64);
+
+        let mc = MonteCarlo::from_config(&json!({
+            "runs": 200,
+            "method": "bootstrap",
+            "slippage": 0.01,
+            "capital": 1000,
+        }))
+        .unwrap();
+        let report = mc.run(&trades, 100f64).unwrap();
+        assert_eq!(report.capital, 1000f64);
+        assert!(report.final_pnl.p5 < report.final_pnl.p95);
+        assert_eq!(report.risk_of_ruin, 0f64);
+
+        assert!(mc.run(&[], 100f64).is_err());
+        assert!(MonteCarlo::from_config(&json!({"skip": 1})).is_err());
+    }
+
+    #[test]
+    fn test_from_history() {
+        let history = json!([
+            {"symbol": "ETHUSDT", "buy_price": 100, "sell_price": 110, "amount": 2, "profit": 20},
+        ]);
+        assert_eq!(
+            from_history(&history),
+            vec![Trade {
+                pnl: 20f64,
+                value: 220f64
+            }]
+        );
+    }
+}
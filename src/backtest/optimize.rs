@@ -0,0 +1,344 @@
+use log::{info, warn};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    fs,
+    io,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+};
+
+use super::{run, Dataset, Metrics};
+
+#[derive(Debug, Clone)]
+enum Range {
+    Values(Vec<f64>),
+    Step { min: f64, max: f64, step: f64 },
+    Uniform { min: f64, max: f64 },
+}
+
+impl Range {
+    fn from_config(name: &str, config: &Value) -> Result<Self, String> {
+        if let Some(values) = config["values"].as_array() {
+            let values: Vec<f64> = values.iter().filter_map(|v| v.as_f64()).collect();
+            if values.is_empty() {
+                return Err(format!("{}: empty values", name));
+            }
+            return Ok(Range::Values(values));
+        }
+        let (min, max) = match (config["min"].as_f64(), config["max"].as_f64()) {
+            (Some(min), Some(max)) if min <= max => (min, max),
+            _ => return Err(format!("{}: expect values or min <= max", name)),
+        };
+        match config["step"].as_f64() {
+            Some(step) if step > 0f64 => Ok(Range::Step {
+                min: min,
+                max: max,
+                step: step,
+            }),
+            Some(_) => Err(format!("{}: step should be positive", name)),
+            None => Ok(Range::Uniform { min: min, max: max }),
+        }
+    }
+
+    fn grid(&self) -> Result<Vec<f64>, String> {
+        match self {
+            Range::Values(values) => Ok(values.clone()),
+            Range::Step { min, max, step } => {
+                let n = ((max - min) / step + 1e-9).floor() as usize;
+                Ok((0..=n).map(|i| min + step * i as f64).collect())
+            }
+            Range::Uniform { .. } => Err("grid search needs values or a step".into()),
+        }
+    }
+
+    fn sample<R: Rng>(&self, rng: &mut R) -> f64 {
+        match self {
+            Range::Values(values) => values[rng.gen_range(0, values.len())],
+            Range::Step { min, max, step } => {
+                let n = ((max - min) / step + 1e-9).floor() as usize;
+                min + step * rng.gen_range(0, n + 1) as f64
+            }
+            Range::Uniform { min, max } if min == max => *min,
+            Range::Uniform { min, max } => rng.gen_range(*min, *max),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Trial {
+    pub params: BTreeMap<String, f64>,
+    pub metrics: Metrics,
+    pub score: f64,
+    pub feasible: bool,
+}
+
+// "optimize" config:
+//   {"method": "grid" | "random", "samples": 100, "seed": 1, "threads": 0,
+//    "objective": "net_pnl" | "total_return" | "sharpe", "max_drawdown": 0.3,
+//    "params": {"stoploss": {"min": -0.1, "max": -0.02, "step": 0.01},
+//               "start_threshold": {"values": [0.2, 0.3, 0.5]}}}
+// param names are config keys, "a.b" for nested ones
+#[derive(Debug, Clone)]
+pub struct Optimizer {
+    params: Vec<(String, Range)>,
+    random: bool,
+    samples: usize,
+    seed: u64,
+    threads: usize,
+    objective: String,
+    max_drawdown: Option<f64>,
+}
+
+impl Optimizer {
+    pub fn from_config(config: &Value) -> Result<Self, String> {
+        let params = match config["params"].as_object() {
+            Some(params) if !params.is_empty() => params,
+            _ => return Err("optimize.params not found".into()),
+        };
+        let mut ranges = vec![];
+        for (name, range) in params {
+            ranges.push((name.clone(), Range::from_config(name, range)?));
+        }
+        let random = match config["method"].as_str().unwrap_or("grid") {
+            "grid" => false,
+            "random" => true,
+            other => return Err(format!("unknown optimize method: {}", other)),
+        };
+        let objective = config["objective"].as_str().unwrap_or("net_pnl");
+        if !["net_pnl", "total_return", "sharpe"].contains(&objective) {
+            return Err(format!("unknown objective: {}", objective));
+        }
+        let threads = match config["threads"].as_u64().unwrap_or(0) as usize {
+            0 => thread::available_parallelism()
+                .map(|n| n.get())
+                .unwrap_or(1),
+            n => n,
+        };
+        Ok(Optimizer {
+            params: ranges,
+            random: random,
+            samples: config["samples"].as_u64().unwrap_or(100) as usize,
+            seed: config["seed"].as_u64().unwrap_or(1),
+            threads: threads,
+            objective: objective.into(),
+            max_drawdown: config["max_drawdown"].as_f64(),
+        })
+    }
+
+    pub fn set_threads(&mut self, threads: usize) {
+        self.threads = threads.max(1);
+    }
+
+    pub fn candidates(&self) -> Result<Vec<BTreeMap<String, f64>>, String> {
+        if self.random {
+            let mut rng = StdRng::seed_from_u64(self.seed);
+            return Ok((0..self.samples)
+                .map(|_| {
+                    self.params
+                        .iter()
+                        .map(|(name, range)| (name.clone(), range.sample(&mut rng)))
+                        .collect()
+                })
+                .collect());
+        }
+        let mut candidates = vec![BTreeMap::new()];
+        for (name, range) in &self.params {
+            let values = range.grid().map_err(|err| format!("{}: {}", name, err))?;
+            candidates = candidates
+                .into_iter()
+                .flat_map(|candidate| {
+                    values.iter().map(move |value| {
+                        let mut candidate = candidate.clone();
+                        candidate.insert(name.clone(), *value);
+                        candidate
+                    })
+                })
+                .collect();
+        }
+        Ok(candidates)
+    }
+
+    pub fn score(&self, metrics: &Metrics) -> f64 {
+        match self.objective.as_str() {
+            "total_return" => metrics.total_return,
+            "sharpe" => metrics.sharpe,
+            _ => metrics.net_pnl,
+        }
+    }
+
+    // backtest every candidate over `data` in parallel, best trial first,
+    // trials breaking the drawdown constraint rank after all others
+    pub fn run(&self, config: &Value, data: &Dataset) -> Result<Vec<Trial>, String> {
+        let candidates = self.candidates()?;
+        let mut config = config.clone();
+        config["backtest"]["audit_file"] = Value::Null;
+        let config = &config;
+        info!(
+            "optimize {} candidates on {} threads",
+            candidates.len(),
+            self.threads
+        );
+
+        let next = AtomicUsize::new(0);
+        let trials = Mutex::new(vec![]);
+        thread::scope(|scope| {
+            for _ in 0..self.threads.min(candidates.len()) {
+                scope.spawn(|| loop {
+                    let i = next.fetch_add(1, Ordering::SeqCst);
+                    let params = match candidates.get(i) {
+                        Some(params) => params,
+                        None => break,
+                    };
+                    let mut config = config.clone();
+                    for (name, value) in params {
+                        set_param(&mut config, name, *value);
+                    }
+                    match run(&config, data) {
+                        Ok(result) => {
+                            let trial = Trial {
+                                params: params.clone(),
+                                score: self.score(&result.metrics),
+                                feasible: self
+                                    .max_drawdown
+                                    .map(|max| result.metrics.max_drawdown <= max)
+                                    .unwrap_or(true),
+                                metrics: result.metrics,
+                            };
+                            trials.lock().unwrap().push(trial);
+                        }
+                        Err(err) => warn!("backtest {:?} error: {}", params, err),
+                    }
+                });
+            }
+        });
+
+        let mut trials = trials.into_inner().unwrap();
+        trials.sort_by(|a, b| {
+            b.feasible
+                .cmp(&a.feasible)
+                .then(b.score.partial_cmp(&a.score).unwrap_or(std::cmp::Ordering::Equal))
+        });
+        Ok(trials)
+    }
+}
+
+pub fn set_param(config: &mut Value, name: &str, value: f64) {
+    let mut target = config;
+    for key in name.split('.') {
+        target = &mut target[key];
+    }
+    *target = value.into();
+}
+
+pub fn write_csv(path: &str, trials: &[Trial]) -> io::Result<()> {
+    let names: Vec<&String> = match trials.first() {
+        Some(trial) => trial.params.keys().collect(),
+        None => vec![],
+    };
+    let mut out = String::from("rank,");
+    for name in &names {
+        out += &format!("{},", name);
+    }
+    out += "score,feasible,net_pnl,total_return,max_drawdown,sharpe,trades,win_rate,fees\n";
+    for (i, trial) in trials.iter().enumerate() {
+        out += &format!("{},", i + 1);
+        for name in &names {
+            out += &format!("{},", trial.params[*name]);
+        }
+        let m = &trial.metrics;
+        out += &format!(
+            "{},{},{},{},{},{},{},{},{}\n",
+            trial.score,
+            trial.feasible,
+            m.net_pnl,
+            m.total_return,
+            m.max_drawdown,
+            m.sharpe,
+            m.trades,
+            m.win_rate,
+            m.fees
+        );
+    }
+    fs::write(path, out)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backtest::data::{fixture::bars, symbol_info};
+    use serde_json::json;
+
+    #[test]
+    fn test_candidates() {
+        let optimizer = Optimizer::from_config(&json!({
+            "params": {
+                "stoploss": {"min": -0.1, "max": -0.05, "step": 0.025},
+                "start_threshold": {"values": [0.2, 0.3]},
+            }
+        }))
+        .unwrap();
+        let candidates = optimizer.candidates().unwrap();
+        assert_eq!(candidates.len(), 6);
+        assert!((candidates[5]["stoploss"] + 0.05).abs() < 1e-9);
+        assert_eq!(candidates[5]["start_threshold"], 0.3);
+
+        let optimizer = Optimizer::from_config(&json!({
+            "method": "random",
+            "samples": 10,
+            "params": {"stoploss": {"min": -0.1, "max": -0.05}},
+        }))
+        .unwrap();
+        let candidates = optimizer.candidates().unwrap();
+        assert_eq!(candidates.len(), 10);
+        assert!(candidates
+            .iter()
+            .all(|c| c["stoploss"] >= -0.1 && c["stoploss"] < -0.05));
+        assert!(Optimizer::from_config(&json!({"params": {"x": {"min": 1}}})).is_err());
+    }
+
+    #[test]
+    fn test_set_param() {
+        let mut config = json!({"a": 1, "backtest": {"fee": 0.001}});
+        set_param(&mut config, "a", 2f64);
+        set_param(&mut config, "backtest.fee", 0f64);
+        assert_eq!(config, json!({"a": 2.0, "backtest": {"fee": 0.0}}));
+    }
+
+    #[test]
+    fn test_optimize_move_stoploss() {
+        // rises 50%, then falls back
+        let closes: Vec<f64> = (0..40)
+            .map(|i| {
+                let ratio = if i < 20 { i } else { 40 - i };
+                100f64 + 2.5 * ratio as f64
+            })
+            .collect();
+        let data = vec![(symbol_info("ETH", "USDT"), bars(&closes, 0, 60_000))];
+        let config = json!({
+            "strategy": "move_stoploss",
+            "quote": "usdt",
+            "ignore": [],
+            "min_value": 10,
+            "stoploss": -0.05,
+            "start_threshold": 0.3,
+            "withdraw_ratio": 0.5,
+            "backtest": {"balances": {"USDT": 0, "ETH": 1}, "fee": 0},
+        });
+        let optimizer = Optimizer::from_config(&json!({
+            "threads": 2,
+            "params": {"withdraw_ratio": {"values": [0.2, 0.9]}},
+        }))
+        .unwrap();
+        let trials = optimizer.run(&config, &data).unwrap();
+        assert_eq!(trials.len(), 2);
+        // keeping 90% of the high sells earlier
+        assert_eq!(trials[0].params["withdraw_ratio"], 0.9);
+        assert!(trials[0].score > trials[1].score);
+    }
+}
@@ -0,0 +1,284 @@
+use chrono::{Datelike, TimeZone, Utc};
+use std::{collections::BTreeMap, fs, io};
+
+use super::BacktestResult;
+
+const WIDTH: f64 = 800f64;
+const HEIGHT: f64 = 240f64;
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+fn date(timestamp: u64) -> String {
+    Utc.timestamp_millis_opt(timestamp as i64)
+        .unwrap()
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+pub fn trades_csv(result: &BacktestResult) -> String {
+    let mut out = String::from("time,symbol,side,price,amount,fee,pnl\n");
+    for fill in &result.fills {
+        out += &format!(
+            "{},{},{},{},{},{},{}\n",
+            date(fill.timestamp),
+            fill.symbol,
+            fill.side,
+            fill.price,
+            fill.amount,
+            fill.fee,
+            fill.pnl
+        );
+    }
+    out
+}
+
+pub fn equity_csv(result: &BacktestResult) -> String {
+    let mut out = String::from("time,equity,drawdown\n");
+    for ((timestamp, value), drawdown) in result.equity.iter().zip(drawdowns(&result.equity)) {
+        out += &format!("{},{},{}\n", date(*timestamp), value, drawdown);
+    }
+    out
+}
+
+// writes <prefix>_trades.csv and <prefix>_equity.csv
+pub fn write_csv(prefix: &str, result: &BacktestResult) -> io::Result<()> {
+    fs::write(format!("{}_trades.csv", prefix), trades_csv(result))?;
+    fs::write(format!("{}_equity.csv", prefix), equity_csv(result))
+}
+
+fn drawdowns(equity: &[(u64, f64)]) -> Vec<f64> {
+    let mut peak = f64::MIN;
+    equity
+        .iter()
+        .map(|(_, value)| {
+            peak = peak.max(*value);
+            if peak > 0f64 {
+                (peak - value) / peak
+            } else {
+                0f64
+            }
+        })
+        .collect()
+}
+
+// (year, month) -> return over the month, measured from the last value
+// of the previous month
+pub fn monthly_returns(equity: &[(u64, f64)]) -> BTreeMap<(i32, u32), f64> {
+    let mut closes: BTreeMap<(i32, u32), f64> = BTreeMap::new();
+    for (timestamp, value) in equity {
+        let t = Utc.timestamp_millis_opt(*timestamp as i64).unwrap();
+        closes.insert((t.year(), t.month()), *value);
+    }
+    let mut prev = match equity.first() {
+        Some((_, value)) => *value,
+        None => return BTreeMap::new(),
+    };
+    closes
+        .into_iter()
+        .map(|(month, close)| {
+            let ret = if prev > 0f64 { close / prev - 1f64 } else { 0f64 };
+            prev = close;
+            (month, ret)
+        })
+        .collect()
+}
+
+// svg polyline of `points` scaled into the chart, `fill` closes the
+// area down to the baseline
+fn chart(points: &[(u64, f64)], color: &str, fill: bool) -> String {
+    if points.is_empty() {
+        return String::new();
+    }
+    let (x0, x1) = (points[0].0 as f64, points[points.len() - 1].0 as f64);
+    let (mut y0, mut y1) = (f64::MAX, f64::MIN);
+    for (_, y) in points {
+        y0 = y0.min(*y);
+        y1 = y1.max(*y);
+    }
+    if fill {
+        y0 = y0.min(0f64);
+    }
+    let sx = if x1 > x0 { WIDTH / (x1 - x0) } else { 0f64 };
+    let sy = if y1 > y0 { HEIGHT / (y1 - y0) } else { 0f64 };
+    let mut path: Vec<String> = points
+        .iter()
+        .map(|(x, y)| {
+            format!(
+                "{:.1},{:.1}",
+                (*x as f64 - x0) * sx,
+                HEIGHT - (y - y0) * sy
+            )
+        })
+        .collect();
+    if fill {
+        path.push(format!("{:.1},{:.1}", (x1 - x0) * sx, HEIGHT));
+        path.push(format!("0,{:.1}", HEIGHT));
+    }
+    format!(
+        "<svg viewBox=\"0 0 {w} {h}\" width=\"{w}\" height=\"{h}\">\
+         <polyline fill=\"{fill}\" stroke=\"{color}\" stroke-width=\"1.5\" points=\"{points}\"/>\
+         <text x=\"4\" y=\"12\">{max:.4}</text><text x=\"4\" y=\"{bottom}\">{min:.4}</text></svg>",
+        w = WIDTH,
+        h = HEIGHT,
+        fill = if fill { color } else { "none" },
+        color = color,
+        points = path.join(" "),
+        max = y1,
+        min = y0,
+        bottom = HEIGHT - 4f64
+    )
+}
+
+pub fn html(result: &BacktestResult) -> String {
+    let m = &result.metrics;
+    let mut out = String::new();
+    out += "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">";
+    out += &format!("<title>Backtest {}</title>", escape(&result.strategy));
+    out += "<style>body{font-family:sans-serif;margin:2em}\
+            table{border-collapse:collapse;margin-bottom:2em}\
+            td,th{border:1px solid #ccc;padding:2px 8px;text-align:right}\
+            svg{border:1px solid #ccc;margin-bottom:2em}\
+            .neg{color:#c00}.pos{color:#080}</style></head><body>\n";
+    out += &format!("<h1>Backtest {}</h1>\n", escape(&result.strategy));
+    if let (Some(first), Some(last)) = (result.equity.first(), result.equity.last()) {
+        out += &format!("<p>{} - {}</p>\n", date(first.0), date(last.0));
+    }
+
+    out += "<h2>Summary</h2>\n<table>\n";
+    let currency = escape(&result.currency);
+    let rows = [
+        ("Start equity", format!("{:.2} {}", m.start_equity, currency)),
+        ("End equity", format!("{:.2} {}", m.end_equity, currency)),
+        ("Net PnL", format!("{:.2} {}", m.net_pnl, currency)),
+        ("Total return", format!("{:.2}%", m.total_return * 100f64)),
+        ("Max drawdown", format!("{:.2}%", m.max_drawdown * 100f64)),
+        ("Sharpe", format!("{:.2}", m.sharpe)),
+        ("Trades", m.trades.to_string()),
+        ("Win rate", format!("{:.2}%", m.win_rate * 100f64)),
+        ("Fees", format!("{:.2} {}", m.fees, currency)),
+    ];
+    for (name, value) in rows.iter() {
+        out += &format!("<tr><th>{}</th><td>{}</td></tr>\n", name, value);
+    }
+    out += "</table>\n";
+
+    out += "<h2>Equity</h2>\n";
+    out += &chart(&result.equity, "#36c", false);
+    out += "\n<h2>Drawdown</h2>\n";
+    let drawdown: Vec<(u64, f64)> = result
+        .equity
+        .iter()
+        .zip(drawdowns(&result.equity))
+        .map(|((timestamp, _), drawdown)| (*timestamp, -drawdown))
+        .collect();
+    out += &chart(&drawdown, "#c33", true);
+
+    out += "\n<h2>Monthly returns</h2>\n<table>\n<tr><th>Year</th>";
+    for month in MONTHS.iter() {
+        out += &format!("<th>{}</th>", month);
+    }
+    out += "</tr>\n";
+    let monthly = monthly_returns(&result.equity);
+    let mut years: Vec<i32> = monthly.keys().map(|(year, _)| *year).collect();
+    years.dedup();
+    for year in years {
+        out += &format!("<tr><th>{}</th>", year);
+        for month in 1..=12 {
+            match monthly.get(&(year, month)) {
+                Some(ret) => {
+                    let class = if *ret < 0f64 { "neg" } else { "pos" };
+                    out += &format!("<td class=\"{}\">{:.2}%</td>", class, ret * 100f64);
+                }
+                None => out += "<td></td>",
+            }
+        }
+        out += "</tr>\n";
+    }
+    out += "</table>\n";
+
+    out += "<h2>Trades</h2>\n<table>\n\
+            <tr><th>Time</th><th>Symbol</th><th>Side</th><th>Price</th>\
+            <th>Amount</th><th>Fee</th><th>PnL</th></tr>\n";
+    for fill in &result.fills {
+        let class = if fill.pnl < 0f64 { "neg" } else { "pos" };
+        out += &format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.4}</td>\
+             <td class=\"{}\">{:.4}</td></tr>\n",
+            date(fill.timestamp),
+            escape(&fill.symbol),
+            escape(&fill.side),
+            fill.price,
+            fill.amount,
+            fill.fee,
+            class,
+            fill.pnl
+        );
+    }
+    out += "</table>\n</body></html>\n";
+    out
+}
+
+pub fn write_html(path: &str, result: &BacktestResult) -> io::Result<()> {
+    fs::write(path, html(result))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{backtest::Metrics, exchange::Fill};
+
+    fn ts(s: &str) -> u64 {
+        s.parse::<chrono::DateTime<Utc>>().unwrap().timestamp_millis() as u64
+    }
+
+    #[test]
+    fn test_report() {
+        let equity = vec![
+            (ts("2020-01-01T00:00:00Z"), 100f64),
+            (ts("2020-01-31T00:00:00Z"), 110f64),
+            (ts("2020-02-15T00:00:00Z"), 99f64),
+            (ts("2020-03-01T00:00:00Z"), 120f64),
+        ];
+        let monthly = monthly_returns(&equity);
+        assert!((monthly[&(2020, 1)] - 0.1).abs() < 1e-9);
+        assert!((monthly[&(2020, 2)] + 0.1).abs() < 1e-9);
+
+        let fills = vec![Fill {
+            timestamp: ts("2020-02-15T00:00:00Z"),
+            symbol: "ETHUSDT".into(),
+            side: "SELL".into(),
+            price: 99f64,
+            amount: 1f64,
+            fee: 0.1,
+            pnl: -1f64,
+        }];
+        let result = BacktestResult {
+            strategy: "move_stoploss".into(),
+            currency: "USDT".into(),
+            metrics: Metrics::new(&equity, &fills),
+            fills: fills,
+            equity: equity,
+        };
+        let html = html(&result);
+        assert!(html.contains("<polyline"));
+        assert!(html.contains("<td class=\"neg\">-10.00%</td>"));
+        assert!(html.contains("ETHUSDT"));
+
+        assert_eq!(
+            trades_csv(&result).lines().nth(1),
+            Some("2020-02-15 00:00,ETHUSDT,SELL,99,1,0.1,-1")
+        );
+        assert_eq!(
+            equity_csv(&result).lines().nth(3),
+            Some("2020-02-15 00:00,99,0.1")
+        );
+    }
+}
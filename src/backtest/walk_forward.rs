@@ -0,0 +1,206 @@
+use log::info;
+use serde_derive::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use super::{
+    data::{slice, span},
+    optimize::set_param,
+    run, BacktestResult, Dataset, Metrics, Optimizer,
+};
+use crate::utils::period_ms;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Window {
+    pub in_sample: (u64, u64),
+    pub out_of_sample: (u64, u64),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WindowResult {
+    pub window: Window,
+    pub params: BTreeMap<String, f64>,
+    pub in_sample: Metrics,
+    pub out_of_sample: Metrics,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WalkForwardResult {
+    pub windows: Vec<WindowResult>,
+    // out-of-sample runs chained into one curve
+    pub result: BacktestResult,
+}
+
+// "walk_forward" config:
+//   {"in_sample": "90d", "out_of_sample": "30d", "step": "30d", "anchored": false}
+// step defaults to out_of_sample and can't be shorter, overlapping
+// out-of-sample runs would chain into a curve that goes back in time.
+// Anchored windows all start at the beginning of the data
+#[derive(Debug, Clone)]
+pub struct WalkForward {
+    in_sample: u64,
+    out_of_sample: u64,
+    step: u64,
+    anchored: bool,
+}
+
+fn duration(config: &Value, key: &str) -> Result<Option<u64>, String> {
+    match config[key].as_str() {
+        Some(value) => match period_ms(value) {
+            Some(ms) if ms > 0 => Ok(Some(ms)),
+            _ => Err(format!(
+                "walk_forward.{}: invalid duration {:?}",
+                key, value
+            )),
+        },
+        None => Ok(None),
+    }
+}
+
+impl WalkForward {
+    pub fn from_config(config: &Value) -> Result<Self, String> {
+        let in_sample = duration(config, "in_sample")?.ok_or("walk_forward.in_sample not found")?;
+        let out_of_sample =
+            duration(config, "out_of_sample")?.ok_or("walk_forward.out_of_sample not found")?;
+        let step = duration(config, "step")?.unwrap_or(out_of_sample);
+        if step < out_of_sample {
+            return Err("walk_forward.step: expect at least out_of_sample".into());
+        }
+        Ok(WalkForward {
+            in_sample: in_sample,
+            out_of_sample: out_of_sample,
+            step: step,
+            anchored: config["anchored"].as_bool().unwrap_or(false),
+        })
+    }
+
+    pub fn windows(&self, start: u64, end: u64) -> Vec<Window> {
+        let mut windows = vec![];
+        let mut offset = 0;
+        loop {
+            let is_start = if self.anchored { start } else { start + offset };
+            let is_end = start + offset + self.in_sample;
+            if is_end > end {
+                break;
+            }
+            windows.push(Window {
+                in_sample: (is_start, is_end),
+                out_of_sample: (is_end, (is_end + self.out_of_sample).min(end + 1)),
+            });
+            offset += self.step;
+        }
+        windows
+    }
+
+    pub fn run(
+        &self,
+        optimizer: &Optimizer,
+        config: &Value,
+        data: &Dataset,
+    ) -> Result<WalkForwardResult, String> {
+        let (start, end) = span(data).ok_or("no market data")?;
+        let windows = self.windows(start, end);
+        if windows.is_empty() {
+            return Err("data shorter than one in-sample window".into());
+        }
+
+        let mut results = vec![];
+        let mut equity: Vec<(u64, f64)> = vec![];
+        let mut fills = vec![];
+        let mut strategy = String::new();
+        let mut currency = String::new();
+        for window in windows {
+            let in_sample = slice(data, window.in_sample.0, window.in_sample.1);
+            let out_of_sample = slice(data, window.out_of_sample.0, window.out_of_sample.1);
+            if out_of_sample.is_empty() {
+                continue;
+            }
+            let trials = optimizer.run(config, &in_sample)?;
+            let best = trials
+                .into_iter()
+                .next()
+                .ok_or("no successful in-sample trial")?;
+
+            let mut config = config.clone();
+            config["backtest"]["audit_file"] = Value::Null;
+            for (name, value) in &best.params {
+                set_param(&mut config, name, *value);
+            }
+            let result = run(&config, &out_of_sample)?;
+            info!(
+                "window {:?}: params {:?}, in-sample {:.4}, out-of-sample {:.4}",
+                window,
+                best.params,
+                best.score,
+                optimizer.score(&result.metrics)
+            );
+
+            // every window restarts from the configured balances, chain
+            // them by compounding the window returns
+            let base = equity.last().map(|e| e.1);
+            let first = result.equity.first().map(|e| e.1).unwrap_or(0f64);
+            for (timestamp, value) in &result.equity {
+                let value = match base {
+                    Some(base) if first > 0f64 => base * value / first,
+                    _ => *value,
+                };
+                equity.push((*timestamp, value));
+            }
+            fills.extend(result.fills);
+            strategy = result.strategy;
+            currency = result.currency;
+            results.push(WindowResult {
+                window: window,
+                params: best.params,
+                in_sample: best.metrics,
+                out_of_sample: result.metrics,
+            });
+        }
+
+        let metrics = Metrics::new(&equity, &fills);
+        Ok(WalkForwardResult {
+            windows: results,
+            result: BacktestResult {
+                strategy: strategy,
+                currency: currency,
+                fills: fills,
+                equity: equity,
+                metrics: metrics,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_windows() {
+        let day = 86_400_000;
+        let wf =
+            WalkForward::from_config(&json!({"in_sample": "3d", "out_of_sample": "1d"})).unwrap();
+        let windows = wf.windows(0, 6 * day - 1);
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows[0].in_sample, (0, 3 * day));
+        assert_eq!(windows[0].out_of_sample, (3 * day, 4 * day));
+        assert_eq!(windows[2].in_sample, (2 * day, 5 * day));
+        assert_eq!(windows[2].out_of_sample, (5 * day, 6 * day));
+
+        let wf = WalkForward::from_config(
+            &json!({"in_sample": "3d", "out_of_sample": "2d", "anchored": true}),
+        )
+        .unwrap();
+        let windows = wf.windows(0, 7 * day);
+        assert_eq!(windows.len(), 3);
+        assert_eq!(windows[2].in_sample, (0, 7 * day));
+        assert_eq!(windows[2].out_of_sample, (7 * day, 7 * day + 1));
+
+        assert!(
+            WalkForward::from_config(&json!({"in_sample": "3x", "out_of_sample": "1d"})).is_err()
+        );
+        let overlapping = json!({"in_sample": "3d", "out_of_sample": "2d", "step": "1d"});
+        assert!(WalkForward::from_config(&overlapping).is_err());
+    }
+}
@@ -0,0 +1,436 @@
+use rsex::{
+    constant::{ORDER_ACTION_BUY, ORDER_ACTION_SELL, ORDER_TYPE_LIMIT, ORDER_TYPE_MARKET},
+    models::{Balance, Order, SymbolInfo},
+};
+use serde_derive::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fill {
+    pub timestamp: u64,
+    pub symbol: String,
+    pub side: String,
+    pub price: f64,
+    pub amount: f64,
+    pub fee: f64,
+    pub pnl: f64,
+}
+
+// simulated spot account shared by the backtest and paper exchanges,
+// fees are charged in the quote currency
+#[derive(Debug)]
+pub struct Account {
+    symbols: HashMap<String, SymbolInfo>,
+    balances: HashMap<String, (f64, f64)>,
+    orders: Vec<Order>,
+    costs: HashMap<String, (f64, f64)>,
+    // base assets whose initial holding was already booked
+    seeded: HashSet<String>,
+    fills: Vec<Fill>,
+    fee: f64,
+    next_id: u64,
+}
+
+impl Account {
+    pub fn new(symbols: Vec<SymbolInfo>, balances: &HashMap<String, f64>, fee: f64) -> Self {
+        Account {
+            symbols: symbols
+                .into_iter()
+                .map(|info| (info.symbol.to_uppercase(), info))
+                .collect(),
+            balances: balances
+                .iter()
+                .map(|(asset, amount)| (asset.to_uppercase(), (*amount, 0f64)))
+                .collect(),
+            orders: vec![],
+            costs: HashMap::new(),
+            seeded: HashSet::new(),
+            fills: vec![],
+            fee: fee,
+            next_id: 1,
+        }
+    }
+
+    pub fn symbols(&self) -> Vec<SymbolInfo> {
+        self.symbols.values().cloned().collect()
+    }
+
+    pub fn symbol(&self, symbol: &str) -> Option<&SymbolInfo> {
+        self.symbols.get(&symbol.to_uppercase())
+    }
+
+    pub fn balance(&self, asset: &str) -> Balance {
+        let asset = asset.to_uppercase();
+        let (free, locked) = self.balances.get(&asset).cloned().unwrap_or((0f64, 0f64));
+        Balance {
+            asset: asset,
+            free: free,
+            locked: locked,
+        }
+    }
+
+    pub fn balances(&self) -> Vec<Balance> {
+        self.balances
+            .keys()
+            .map(|asset| self.balance(asset))
+            .collect()
+    }
+
+    pub fn fills(&self) -> &[Fill] {
+        &self.fills
+    }
+
+    // record an initial holding as a filled buy so that strategies can
+    // derive an entry price from the order history, only against the
+    // first symbol seeded for its base so the cost is booked once
+    pub fn seed(&mut self, symbol: &str, price: f64, timestamp: u64) {
+        let info = match self.symbol(symbol) {
+            Some(info) => info.clone(),
+            None => return,
+        };
+        if !self.seeded.insert(info.base.to_uppercase()) {
+            return;
+        }
+        let amount = self.balance(&info.base).free;
+        if amount <= 0f64 {
+            return;
+        }
+        let id = self.next_order_id();
+        self.orders.push(Order {
+            symbol: info.symbol.to_uppercase(),
+            order_id: id,
+            price: price,
+            amount: amount,
+            filled: amount,
+            status: "FILLED".into(),
+            side: ORDER_ACTION_BUY.into(),
+            order_type: ORDER_TYPE_LIMIT.into(),
+            create_time: timestamp,
+        });
+        self.costs
+            .insert(info.symbol.to_uppercase(), (amount, price));
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    pub fn place(
+        &mut self,
+        symbol: &str,
+        price: f64,
+        amount: f64,
+        side: &str,
+        order_type: &str,
+        bid: f64,
+        ask: f64,
+        timestamp: u64,
+    ) -> Result<String, String> {
+        let info = match self.symbol(symbol) {
+            Some(info) => info.clone(),
+            None => return Err(format!("unknown symbol: {}", symbol)),
+        };
+        if amount <= 0f64 {
+            return Err(format!("invalid amount: {}", amount));
+        }
+        let market = order_type == ORDER_TYPE_MARKET;
+        let price = match (market, side == ORDER_ACTION_BUY) {
+            (true, true) => ask,
+            (true, false) => bid,
+            _ => price,
+        };
+        let (lock_asset, lock_amount) = if side == ORDER_ACTION_BUY {
+            (info.quote.clone(), price * amount * (1f64 + self.fee))
+        } else if side == ORDER_ACTION_SELL {
+            (info.base.clone(), amount)
+        } else {
+            return Err(format!("invalid side: {}", side));
+        };
+        if !self.lock(&lock_asset, lock_amount) {
+            return Err(format!(
+                "insufficient balance: {} {}, need {}",
+                lock_asset,
+                self.balance(&lock_asset).free,
+                lock_amount
+            ));
+        }
+
+        let id = self.next_order_id();
+        self.orders.push(Order {
+            symbol: info.symbol.to_uppercase(),
+            order_id: id.clone(),
+            price: price,
+            amount: amount,
+            filled: 0f64,
+            status: "NEW".into(),
+            side: side.into(),
+            order_type: order_type.into(),
+            create_time: timestamp,
+        });
+        // marketable orders fill immediately at the touch
+        if side == ORDER_ACTION_BUY && price >= ask {
+            self.fill(&id, ask, timestamp);
+        } else if side == ORDER_ACTION_SELL && price <= bid {
+            self.fill(&id, bid, timestamp);
+        }
+        Ok(id)
+    }
+
+    pub fn cancel(&mut self, id: &str) -> bool {
+        let order = match self.orders.iter_mut().find(|order| order.order_id == id) {
+            Some(order) => order,
+            None => return false,
+        };
+        if order.status != "NEW" {
+            return false;
+        }
+        order.status = "CANCELED".into();
+        let order = order.clone();
+        let info = self.symbol(&order.symbol).unwrap().clone();
+        if order.side == ORDER_ACTION_BUY {
+            let locked = order.price * order.amount * (1f64 + self.fee);
+            self.unlock(&info.quote, locked);
+        } else {
+            self.unlock(&info.base, order.amount);
+        }
+        true
+    }
+
+    pub fn cancel_all(&mut self, symbol: &str) -> bool {
+        let ids: Vec<String> = self
+            .open_orders(symbol)
+            .into_iter()
+            .map(|order| order.order_id)
+            .collect();
+        ids.iter().all(|id| self.cancel(id))
+    }
+
+    pub fn order(&self, id: &str) -> Option<Order> {
+        self.orders
+            .iter()
+            .find(|order| order.order_id == id)
+            .cloned()
+    }
+
+    pub fn open_orders(&self, symbol: &str) -> Vec<Order> {
+        let symbol = symbol.to_uppercase();
+        self.orders
+            .iter()
+            .filter(|order| order.symbol == symbol && order.status == "NEW")
+            .cloned()
+            .collect()
+    }
+
+    pub fn history_orders(&self, symbol: &str) -> Vec<Order> {
+        let symbol = symbol.to_uppercase();
+        self.orders
+            .iter()
+            .filter(|order| order.symbol == symbol && order.status != "NEW")
+            .cloned()
+            .collect()
+    }
+
+    // fill resting limit orders whose price was touched by [low, high]
+    pub fn match_orders(&mut self, symbol: &str, low: f64, high: f64, timestamp: u64) {
+        let filled: Vec<(String, f64)> = self
+            .open_orders(symbol)
+            .into_iter()
+            .filter(|order| {
+                (order.side == ORDER_ACTION_BUY && low <= order.price)
+                    || (order.side == ORDER_ACTION_SELL && high >= order.price)
+            })
+            .map(|order| (order.order_id, order.price))
+            .collect();
+        for (id, price) in filled {
+            self.fill(&id, price, timestamp);
+        }
+    }
+
+    // total value of all balances in `currency`, `price` returns the
+    // price of a symbol or None if unknown
+    pub fn value<F>(&self, currency: &str, price: F) -> f64
+    where
+        F: Fn(&str) -> Option<f64>,
+    {
+        let currency = currency.to_uppercase();
+        self.balances
+            .iter()
+            .map(|(asset, (free, locked))| {
+                let amount = free + locked;
+                if *asset == currency || amount == 0f64 {
+                    return amount;
+                }
+                let symbol = format!("{}{}", asset, currency);
+                match price(&symbol) {
+                    Some(price) => amount * price,
+                    None => 0f64,
+                }
+            })
+            .sum()
+    }
+
+    fn fill(&mut self, id: &str, price: f64, timestamp: u64) {
+        let index = self
+            .orders
+            .iter()
+            .position(|order| order.order_id == id)
+            .unwrap();
+        let order = self.orders[index].clone();
+        let info = self.symbol(&order.symbol).unwrap().clone();
+        let amount = order.amount;
+        let fee = price * amount * self.fee;
+        let (held, avg) = self
+            .costs
+            .get(&order.symbol)
+            .cloned()
+            .unwrap_or((0f64, 0f64));
+
+        let pnl = if order.side == ORDER_ACTION_BUY {
+            let locked = order.price * amount * (1f64 + self.fee);
+            self.unlock(&info.quote, locked);
+            self.add(&info.quote, -(price * amount + fee));
+            self.add(&info.base, amount);
+            let cost = (held * avg + price * amount + fee) / (held + amount);
+            self.costs.insert(order.symbol.clone(), (held + amount, cost));
+            0f64
+        } else {
+            self.unlock(&info.base, amount);
+            self.add(&info.base, -amount);
+            self.add(&info.quote, price * amount - fee);
+            let sold = amount.min(held);
+            self.costs
+                .insert(order.symbol.clone(), (held - sold, avg));
+            (price - avg) * sold - fee
+        };
+
+        let order = &mut self.orders[index];
+        order.filled = amount;
+        order.price = price;
+        order.status = "FILLED".into();
+        self.fills.push(Fill {
+            timestamp: timestamp,
+            symbol: order.symbol.clone(),
+            side: order.side.clone(),
+            price: price,
+            amount: amount,
+            fee: fee,
+            pnl: pnl,
+        });
+    }
+
+    fn next_order_id(&mut self) -> String {
+        let id = self.next_id;
+        self.next_id += 1;
+        id.to_string()
+    }
+
+    fn add(&mut self, asset: &str, amount: f64) {
+        let entry = self
+            .balances
+            .entry(asset.to_uppercase())
+            .or_insert((0f64, 0f64));
+        entry.0 += amount;
+    }
+
+    fn lock(&mut self, asset: &str, amount: f64) -> bool {
+        let entry = self
+            .balances
+            .entry(asset.to_uppercase())
+            .or_insert((0f64, 0f64));
+        if entry.0 < amount {
+            return false;
+        }
+        entry.0 -= amount;
+        entry.1 += amount;
+        true
+    }
+
+    fn unlock(&mut self, asset: &str, amount: f64) {
+        let entry = self
+            .balances
+            .entry(asset.to_uppercase())
+            .or_insert((0f64, 0f64));
+        let amount = amount.min(entry.1);
+        entry.1 -= amount;
+        entry.0 += amount;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn account() -> Account {
+        let symbols = vec![SymbolInfo {
+            base: "BTC".into(),
+            quote: "USDT".into(),
+            symbol: "BTCUSDT".into(),
+            price_precision: 2,
+            amount_precision: 6,
+            min_amount: 0.000001,
+            min_value: 10f64,
+        }];
+        let mut balances = HashMap::new();
+        balances.insert("USDT".to_string(), 1000f64);
+        Account::new(symbols, &balances, 0.001)
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut account = account();
+        account
+            .place("BTCUSDT", 100f64, 1f64, ORDER_ACTION_BUY, ORDER_TYPE_MARKET, 99f64, 100f64, 1)
+            .unwrap();
+        assert_eq!(account.balance("BTC").free, 1f64);
+        assert!((account.balance("USDT").free - 899.9).abs() < 1e-9);
+
+        let id = account
+            .place("BTCUSDT", 110f64, 1f64, ORDER_ACTION_SELL, ORDER_TYPE_LIMIT, 100f64, 101f64, 2)
+            .unwrap();
+        assert_eq!(account.open_orders("BTCUSDT").len(), 1);
+        assert_eq!(account.balance("BTC").locked, 1f64);
+
+        account.match_orders("BTCUSDT", 105f64, 111f64, 3);
+        assert_eq!(account.order(&id).unwrap().status, "FILLED");
+        let pnl = account.fills().last().unwrap().pnl;
+        assert!((pnl - (110f64 - 100.1 - 0.11)).abs() < 1e-9);
+        assert!((account.balance("USDT").free - 1009.79).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_cancel_unlocks() {
+        let mut account = account();
+        let id = account
+            .place("BTCUSDT", 90f64, 1f64, ORDER_ACTION_BUY, ORDER_TYPE_LIMIT, 99f64, 100f64, 1)
+            .unwrap();
+        assert!(account.balance("USDT").free < 1000f64);
+        assert!(account.cancel(&id));
+        assert_eq!(account.balance("USDT").free, 1000f64);
+        assert!(account
+            .place("BTCUSDT", 90f64, 100f64, ORDER_ACTION_BUY, ORDER_TYPE_LIMIT, 99f64, 100f64, 1)
+            .is_err());
+    }
+
+    #[test]
+    fn test_seed_once_per_base() {
+        let info = |quote: &str| SymbolInfo {
+            base: "ETH".into(),
+            quote: quote.into(),
+            symbol: format!("ETH{}", quote),
+            price_precision: 2,
+            amount_precision: 4,
+            min_amount: 0.0001,
+            min_value: 0f64,
+        };
+        let mut balances = HashMap::new();
+        balances.insert("ETH".to_string(), 2f64);
+        let mut account = Account::new(vec![info("USDT"), info("BTC")], &balances, 0f64);
+        account.seed("ETHUSDT", 100f64, 1);
+        account.seed("ETHBTC", 0.05, 1);
+        assert_eq!(account.history_orders("ETHUSDT").len(), 1);
+        assert_eq!(account.history_orders("ETHBTC").len(), 0);
+
+        // the cost basis holds the seeded amount once
+        account
+            .place("ETHUSDT", 110f64, 2f64, ORDER_ACTION_SELL, ORDER_TYPE_MARKET, 110f64, 111f64, 2)
+            .unwrap();
+        assert_eq!(account.fills().last().unwrap().pnl, 20f64);
+    }
+}
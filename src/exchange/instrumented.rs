@@ -0,0 +1,99 @@
+use rsex::{
+    errors::APIResult,
+    models::{Balance, Kline, Order, Orderbook, SymbolInfo, Ticker},
+    traits::SpotRest,
+};
+use std::time::Instant;
+
+use crate::metrics;
+
+pub const API_ERRORS: &str = "rsquant_api_errors_total";
+
+// records latency and errors of every call to the wrapped client
+#[derive(Debug)]
+pub struct Instrumented<C> {
+    client: C,
+}
+
+impl<C: SpotRest> Instrumented<C> {
+    pub fn new(client: C) -> Self {
+        Instrumented { client: client }
+    }
+
+    fn call<T, F>(&self, method: &str, f: F) -> APIResult<T>
+    where
+        F: FnOnce(&C) -> APIResult<T>,
+    {
+        let start = Instant::now();
+        let ret = f(&self.client);
+        metrics::observe(
+            "rsquant_api_latency_seconds",
+            "Exchange api call latency",
+            &[("method", method)],
+            start.elapsed().as_secs_f64(),
+        );
+        if ret.is_err() {
+            metrics::inc_counter(API_ERRORS, "Exchange api errors", &[("method", method)]);
+        }
+        ret
+    }
+}
+
+impl<C: SpotRest> SpotRest for Instrumented<C> {
+    fn get_orderbook(&self, symbol: &str, depth: u8) -> APIResult<Orderbook> {
+        self.call("get_orderbook", |c| c.get_orderbook(symbol, depth))
+    }
+
+    fn get_ticker(&self, symbol: &str) -> APIResult<Ticker> {
+        self.call("get_ticker", |c| c.get_ticker(symbol))
+    }
+
+    fn get_kline(&self, symbol: &str, period: &str, limit: u16) -> APIResult<Vec<Kline>> {
+        self.call("get_kline", |c| c.get_kline(symbol, period, limit))
+    }
+
+    fn get_balance(&self, asset: &str) -> APIResult<Balance> {
+        self.call("get_balance", |c| c.get_balance(asset))
+    }
+
+    fn get_all_balances(&self) -> APIResult<Vec<Balance>> {
+        self.call("get_all_balances", |c| c.get_all_balances())
+    }
+
+    fn create_order(
+        &self,
+        symbol: &str,
+        price: f64,
+        amount: f64,
+        action: &str,
+        order_type: &str,
+    ) -> APIResult<String> {
+        self.call("create_order", |c| {
+            c.create_order(symbol, price, amount, action, order_type)
+        })
+    }
+
+    fn cancel(&self, id: &str) -> APIResult<bool> {
+        self.call("cancel", |c| c.cancel(id))
+    }
+
+    fn cancel_all(&self, symbol: &str) -> APIResult<bool> {
+        self.call("cancel_all", |c| c.cancel_all(symbol))
+    }
+
+    fn get_order(&self, id: &str) -> APIResult<Order> {
+        self.call("get_order", |c| c.get_order(id))
+    }
+
+    fn get_open_orders(&self, symbol: &str) -> APIResult<Vec<Order>> {
+        self.call("get_open_orders", |c| c.get_open_orders(symbol))
+    }
+
+    fn get_history_orders(&self, symbol: &str) -> APIResult<Vec<Order>> {
+        self.call("get_history_orders", |c| c.get_history_orders(symbol))
+    }
+
+    fn get_symbols(&self) -> APIResult<Vec<SymbolInfo>> {
+        self.call("get_symbols", |c| c.get_symbols())
+    }
+}
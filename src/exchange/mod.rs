@@ -0,0 +1,33 @@
+use rsex::binance::spot_rest::Binance;
+use serde_json::Value;
+
+mod account;
+pub use account::{Account, Fill};
+
+mod instrumented;
+pub use instrumented::{Instrumented, API_ERRORS};
+
+mod sim;
+pub use sim::SimExchange;
+
+mod paper;
+pub use paper::PaperExchange;
+
+pub fn binance(config: &Value) -> Binance {
+    let apikey = config["apikey"].as_str().unwrap();
+    let secret_key = config["secret_key"].as_str().unwrap();
+    let host = config["host"].as_str().unwrap();
+    Binance::new(Some(apikey.into()), Some(secret_key.into()), host.into())
+}
+
+// "venues": {"name": {"host": .., "apikey": .., "secret_key": ..}}, each a
+// binance compatible spot api, sorted by name
+pub fn venues(config: &Value) -> Vec<(String, Binance)> {
+    match config["venues"].as_object() {
+        Some(venues) => venues
+            .iter()
+            .map(|(name, venue)| (name.clone(), binance(venue)))
+            .collect(),
+        None => vec![],
+    }
+}
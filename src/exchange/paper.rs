@@ -0,0 +1,113 @@
+use chrono::Utc;
+use rsex::{
+    errors::APIResult,
+    models::{Balance, Kline, Order, Orderbook, SymbolInfo, Ticker},
+    traits::SpotRest,
+};
+use std::{cell::RefCell, collections::HashMap};
+
+use super::{Account, Fill};
+
+// live market data from the exchange, orders and balances simulated
+#[derive(Debug)]
+pub struct PaperExchange<C> {
+    client: C,
+    account: RefCell<Account>,
+}
+
+impl<C: SpotRest> PaperExchange<C> {
+    pub fn new(client: C, balances: &HashMap<String, f64>, fee: f64) -> APIResult<Self> {
+        let symbols = client.get_symbols()?;
+        Ok(PaperExchange {
+            client: client,
+            account: RefCell::new(Account::new(symbols, balances, fee)),
+        })
+    }
+
+    pub fn fills(&self) -> Vec<Fill> {
+        self.account.borrow().fills().to_vec()
+    }
+
+    fn now() -> u64 {
+        Utc::now().timestamp_millis() as u64
+    }
+}
+
+impl<C: SpotRest> SpotRest for PaperExchange<C> {
+    fn get_orderbook(&self, symbol: &str, depth: u8) -> APIResult<Orderbook> {
+        self.client.get_orderbook(symbol, depth)
+    }
+
+    // every ticker poll also matches resting orders of the symbol
+    fn get_ticker(&self, symbol: &str) -> APIResult<Ticker> {
+        let ticker = self.client.get_ticker(symbol)?;
+        self.account.borrow_mut().match_orders(
+            symbol,
+            ticker.ask.price,
+            ticker.bid.price,
+            Self::now(),
+        );
+        Ok(ticker)
+    }
+
+    fn get_kline(&self, symbol: &str, period: &str, limit: u16) -> APIResult<Vec<Kline>> {
+        self.client.get_kline(symbol, period, limit)
+    }
+
+    fn get_balance(&self, asset: &str) -> APIResult<Balance> {
+        Ok(self.account.borrow().balance(asset))
+    }
+
+    fn get_all_balances(&self) -> APIResult<Vec<Balance>> {
+        Ok(self.account.borrow().balances())
+    }
+
+    fn create_order(
+        &self,
+        symbol: &str,
+        price: f64,
+        amount: f64,
+        action: &str,
+        order_type: &str,
+    ) -> APIResult<String> {
+        let ticker = self.get_ticker(symbol)?;
+        let ret = self.account.borrow_mut().place(
+            symbol,
+            price,
+            amount,
+            action,
+            order_type,
+            ticker.bid.price,
+            ticker.ask.price,
+            Self::now(),
+        );
+        Ok(ret?)
+    }
+
+    fn cancel(&self, id: &str) -> APIResult<bool> {
+        Ok(self.account.borrow_mut().cancel(id))
+    }
+
+    fn cancel_all(&self, symbol: &str) -> APIResult<bool> {
+        Ok(self.account.borrow_mut().cancel_all(symbol))
+    }
+
+    fn get_order(&self, id: &str) -> APIResult<Order> {
+        match self.account.borrow().order(id) {
+            Some(order) => Ok(order),
+            None => Err(format!("order not found: {}", id).into()),
+        }
+    }
+
+    fn get_open_orders(&self, symbol: &str) -> APIResult<Vec<Order>> {
+        Ok(self.account.borrow().open_orders(symbol))
+    }
+
+    fn get_history_orders(&self, symbol: &str) -> APIResult<Vec<Order>> {
+        Ok(self.account.borrow().history_orders(symbol))
+    }
+
+    fn get_symbols(&self) -> APIResult<Vec<SymbolInfo>> {
+        Ok(self.account.borrow().symbols())
+    }
+}
@@ -0,0 +1,254 @@
+use log::warn;
+use rsex::{
+    errors::APIResult,
+    models::{Ask, Balance, Bid, Kline, Order, Orderbook, SymbolInfo, Ticker},
+    traits::SpotRest,
+};
+use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Arc};
+
+use super::{Account, Fill};
+use crate::utils::period_ms;
+
+#[derive(Debug)]
+struct SimState {
+    klines: HashMap<String, Arc<Vec<Kline>>>,
+    cursor: HashMap<String, usize>,
+    now: u64,
+    spread: f64,
+    account: Account,
+}
+
+// replays historical klines, every clone shares the same market and
+// account so the backtest runner can drive the clock of the client it
+// handed to a strategy
+#[derive(Debug, Clone)]
+pub struct SimExchange {
+    state: Rc<RefCell<SimState>>,
+}
+
+impl SimExchange {
+    pub fn new(
+        data: Vec<(SymbolInfo, Arc<Vec<Kline>>)>,
+        balances: &HashMap<String, f64>,
+        fee: f64,
+        spread: f64,
+    ) -> Self {
+        let symbols = data.iter().map(|(info, _)| info.clone()).collect();
+        let mut account = Account::new(symbols, balances, fee);
+        for (info, klines) in &data {
+            if let Some(first) = klines.first() {
+                account.seed(&info.symbol, first.close, first.timestamp);
+            }
+        }
+        let klines = data
+            .into_iter()
+            .map(|(info, klines)| (info.symbol.to_uppercase(), klines))
+            .collect();
+        SimExchange {
+            state: Rc::new(RefCell::new(SimState {
+                klines: klines,
+                cursor: HashMap::new(),
+                now: 0,
+                spread: spread,
+                account: account,
+            })),
+        }
+    }
+
+    // sorted union of all bar timestamps
+    pub fn timestamps(&self) -> Vec<u64> {
+        let state = self.state.borrow();
+        let mut timestamps: Vec<u64> = state
+            .klines
+            .values()
+            .flat_map(|klines| klines.iter().map(|kline| kline.timestamp))
+            .collect();
+        timestamps.sort();
+        timestamps.dedup();
+        timestamps
+    }
+
+    pub fn now(&self) -> u64 {
+        self.state.borrow().now
+    }
+
+    // move the clock to `timestamp` and match resting orders against
+    // every bar passed on the way
+    pub fn advance(&self, timestamp: u64) {
+        let mut state = self.state.borrow_mut();
+        let state = &mut *state;
+        state.now = timestamp;
+        for (symbol, klines) in &state.klines {
+            let start = state.cursor.get(symbol).map(|i| i + 1).unwrap_or(0);
+            let mut end = start;
+            while end < klines.len() && klines[end].timestamp <= timestamp {
+                let kline = &klines[end];
+                state
+                    .account
+                    .match_orders(symbol, kline.low, kline.high, kline.timestamp);
+                end += 1;
+            }
+            if end > start {
+                state.cursor.insert(symbol.clone(), end - 1);
+            }
+        }
+    }
+
+    pub fn price(&self, symbol: &str) -> Option<f64> {
+        let state = self.state.borrow();
+        Self::current(&state, symbol).map(|kline| kline.close)
+    }
+
+    pub fn equity(&self, currency: &str) -> f64 {
+        let state = self.state.borrow();
+        state
+            .account
+            .value(currency, |symbol| Self::current(&state, symbol).map(|k| k.close))
+    }
+
+    pub fn fills(&self) -> Vec<Fill> {
+        self.state.borrow().account.fills().to_vec()
+    }
+
+    fn current<'a>(state: &'a SimState, symbol: &str) -> Option<&'a Kline> {
+        let symbol = symbol.to_uppercase();
+        let index = state.cursor.get(&symbol)?;
+        state.klines.get(&symbol).map(|klines| &klines[*index])
+    }
+
+    fn quote(&self, symbol: &str) -> APIResult<(f64, f64)> {
+        let state = self.state.borrow();
+        match Self::current(&state, symbol) {
+            Some(kline) => Ok((
+                kline.close * (1f64 - state.spread / 2f64),
+                kline.close * (1f64 + state.spread / 2f64),
+            )),
+            None => Err(format!("no market data for {} at {}", symbol, state.now).into()),
+        }
+    }
+}
+
+// merge consecutive bars into bars of `period` milliseconds
+fn resample(klines: &[Kline], period: u64) -> Vec<Kline> {
+    let mut ret: Vec<Kline> = vec![];
+    for kline in klines {
+        let bucket = kline.timestamp / period * period;
+        match ret.last_mut() {
+            Some(last) if last.timestamp == bucket => {
+                last.high = last.high.max(kline.high);
+                last.low = last.low.min(kline.low);
+                last.close = kline.close;
+                last.volume += kline.volume;
+            }
+            _ => {
+                let mut kline = kline.clone();
+                kline.timestamp = bucket;
+                ret.push(kline);
+            }
+        }
+    }
+    ret
+}
+
+impl SpotRest for SimExchange {
+    fn get_orderbook(&self, symbol: &str, _depth: u8) -> APIResult<Orderbook> {
+        let (bid, ask) = self.quote(symbol)?;
+        Ok(Orderbook {
+            timestamp: self.now(),
+            bids: vec![Bid {
+                price: bid,
+                amount: f64::MAX,
+            }],
+            asks: vec![Ask {
+                price: ask,
+                amount: f64::MAX,
+            }],
+        })
+    }
+
+    fn get_ticker(&self, symbol: &str) -> APIResult<Ticker> {
+        let (bid, ask) = self.quote(symbol)?;
+        Ok(Ticker {
+            timestamp: self.now(),
+            bid: Bid {
+                price: bid,
+                amount: f64::MAX,
+            },
+            ask: Ask {
+                price: ask,
+                amount: f64::MAX,
+            },
+        })
+    }
+
+    fn get_kline(&self, symbol: &str, period: &str, limit: u16) -> APIResult<Vec<Kline>> {
+        let state = self.state.borrow();
+        let symbol = symbol.to_uppercase();
+        let klines = match (state.klines.get(&symbol), state.cursor.get(&symbol)) {
+            (Some(klines), Some(index)) => &klines[..=*index],
+            _ => return Ok(vec![]),
+        };
+        let klines = match period_ms(period) {
+            Some(period) => resample(klines, period),
+            None => {
+                warn!("unknown kline period: {}, using raw data", period);
+                klines.to_vec()
+            }
+        };
+        let start = klines.len().saturating_sub(limit as usize);
+        Ok(klines[start..].to_vec())
+    }
+
+    fn get_balance(&self, asset: &str) -> APIResult<Balance> {
+        Ok(self.state.borrow().account.balance(asset))
+    }
+
+    fn get_all_balances(&self) -> APIResult<Vec<Balance>> {
+        Ok(self.state.borrow().account.balances())
+    }
+
+    fn create_order(
+        &self,
+        symbol: &str,
+        price: f64,
+        amount: f64,
+        action: &str,
+        order_type: &str,
+    ) -> APIResult<String> {
+        let (bid, ask) = self.quote(symbol)?;
+        let now = self.now();
+        let ret = self
+            .state
+            .borrow_mut()
+            .account
+            .place(symbol, price, amount, action, order_type, bid, ask, now);
+        Ok(ret?)
+    }
+
+    fn cancel(&self, id: &str) -> APIResult<bool> {
+        Ok(self.state.borrow_mut().account.cancel(id))
+    }
+
+    fn cancel_all(&self, symbol: &str) -> APIResult<bool> {
+        Ok(self.state.borrow_mut().account.cancel_all(symbol))
+    }
+
+    fn get_order(&self, id: &str) -> APIResult<Order> {
+        match self.state.borrow().account.order(id) {
+            Some(order) => Ok(order),
+            None => Err(format!("order not found: {}", id).into()),
+        }
+    }
+
+    fn get_open_orders(&self, symbol: &str) -> APIResult<Vec<Order>> {
+        Ok(self.state.borrow().account.open_orders(symbol))
+    }
+
+    fn get_history_orders(&self, symbol: &str) -> APIResult<Vec<Order>> {
+        Ok(self.state.borrow().account.history_orders(symbol))
+    }
+
+    fn get_symbols(&self) -> APIResult<Vec<SymbolInfo>> {
+        Ok(self.state.borrow().account.symbols())
+    }
+}
@@ -0,0 +1,109 @@
+use rsex::models::Kline;
+
+// average true range over the last `length` bars, Wilder smoothed,
+// None without length + 1 bars
+pub fn atr(klines: &[Kline], length: usize) -> Option<f64> {
+    if length == 0 || klines.len() < length + 1 {
+        return None;
+    }
+    let ranges: Vec<f64> = klines
+        .windows(2)
+        .map(|w| {
+            let prev = w[0].close;
+            let k = &w[1];
+            (k.high - k.low)
+                .max((k.high - prev).abs())
+                .max((k.low - prev).abs())
+        })
+        .collect();
+    let mut atr = ranges[..length].iter().sum::<f64>() / length as f64;
+    for range in &ranges[length..] {
+        atr = (atr * (length - 1) as f64 + range) / length as f64;
+    }
+    Some(atr)
+}
+
+// simple moving average of the last `length` values
+pub fn sma(values: &[f64], length: usize) -> Option<f64> {
+    if length == 0 || values.len() < length {
+        return None;
+    }
+    Some(values[values.len() - length..].iter().sum::<f64>() / length as f64)
+}
+
+// exponential moving average seeded with the sma of the first `length`
+// values, None without `length` values
+pub fn ema(values: &[f64], length: usize) -> Option<f64> {
+    let mut ema = sma(&values[..length.min(values.len())], length)?;
+    let k = 2f64 / (length as f64 + 1f64);
+    for value in &values[length..] {
+        ema = value * k + ema * (1f64 - k);
+    }
+    Some(ema)
+}
+
+// relative strength index over `length` changes, Wilder smoothed, None
+// without length + 1 values
+pub fn rsi(values: &[f64], length: usize) -> Option<f64> {
+    if length == 0 || values.len() < length + 1 {
+        return None;
+    }
+    let changes: Vec<f64> = values.windows(2).map(|w| w[1] - w[0]).collect();
+    let mut gain = changes[..length].iter().map(|c| c.max(0f64)).sum::<f64>() / length as f64;
+    let mut loss = changes[..length].iter().map(|c| (-c).max(0f64)).sum::<f64>() / length as f64;
+    for change in &changes[length..] {
+        gain = (gain * (length - 1) as f64 + change.max(0f64)) / length as f64;
+        loss = (loss * (length - 1) as f64 + (-change).max(0f64)) / length as f64;
+    }
+    if loss == 0f64 {
+        return Some(100f64);
+    }
+    Some(100f64 - 100f64 / (1f64 + gain / loss))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kline(high: f64, low: f64, close: f64) -> Kline {
+        Kline {
+            timestamp: 0,
+            open: close,
+            high: high,
+            low: low,
+            close: close,
+            volume: 0f64,
+        }
+    }
+
+    #[test]
+    fn test_atr() {
+        let klines = vec![
+            kline(11f64, 9f64, 10f64),
+            kline(12f64, 10f64, 11f64),
+            // gap up, true range from the previous close
+            kline(15f64, 14f64, 14f64),
+            kline(15f64, 13f64, 14f64),
+        ];
+        assert_eq!(atr(&klines, 4), None);
+        assert_eq!(atr(&klines, 3), Some((2f64 + 4f64 + 2f64) / 3f64));
+        // (3 * 1 + 2) / 2
+        assert_eq!(atr(&klines, 2), Some(2.5));
+    }
+
+    #[test]
+    fn test_averages() {
+        let values = [1f64, 2f64, 3f64, 4f64, 5f64];
+        assert_eq!(sma(&values, 2), Some(4.5));
+        assert_eq!(sma(&values, 6), None);
+        // seeded with (1 + 2 + 3) / 3, then k = 0.5
+        assert_eq!(ema(&values, 3), Some(4f64));
+        assert_eq!(ema(&values[..2], 3), None);
+
+        assert_eq!(rsi(&values, 3), Some(100f64));
+        // gains 2 and 0, losses 0 and 1 over 2, then a loss of 1
+        let values = [10f64, 12f64, 11f64, 10f64];
+        let (gain, loss) = (1f64 / 2f64, (0.5 + 1f64) / 2f64);
+        assert_eq!(rsi(&values, 2), Some(100f64 - 100f64 / (1f64 + gain / loss)));
+    }
+}
@@ -3,6 +3,20 @@ extern crate log;
 extern crate rsex;
 extern crate serde_json;
 
+pub mod audit;
+pub mod backtest;
+pub mod exchange;
+pub mod indicators;
+pub mod metrics;
+pub mod notify;
+pub mod rules;
+pub mod runner;
+pub mod scheduler;
+pub mod signal;
+pub mod state;
+pub mod stops;
 pub mod strategies;
 pub mod traits;
+pub mod trailing;
+pub mod universe;
 pub mod utils;
@@ -1,37 +1,641 @@
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use log::{info, warn};
 use rsquant::{
-    strategies::{Dummy, MoveStopLoss},
-    traits::Strategy,
+    audit, backtest,
+    exchange::{self, Instrumented, PaperExchange},
+    state,
+    strategies::{self, MoveStopLoss},
+    traits::{Client, Strategy, Venues},
 };
 use serde_json::Value;
-use std::{env, fs};
+use std::{fs, process};
 
-fn construct_robot(config_path: &str) -> Box<dyn Strategy> {
+fn load_config(config_path: &str) -> Value {
     let file = fs::File::open(config_path).expect("file should open read only");
-    let config: Value = serde_json::from_reader(file).expect("file should be proper json");
-    let strategy = config["strategy"].as_str().unwrap();
+    serde_json::from_reader(file).expect("file should be proper json")
+}
 
-    match strategy {
-        "move_stoploss" => MoveStopLoss::new(config_path),
-        _ => Dummy::new(config_path),
+fn construct_robot(config: &Value, client: Box<dyn Client>) -> Box<dyn Strategy> {
+    match strategies::build(config, client) {
+        Some(robot) => robot,
+        None => {
+            warn!("strategy not found!");
+            process::exit(1);
+        }
     }
 }
 
-fn main() {
-    env_logger::init();
-    let args: Vec<String> = env::args().collect();
-    let config_path = if args.len() > 1 {
-        &args[1]
+fn multi_venue(config: &Value) -> bool {
+    let strategy = config["strategy"].as_str().unwrap_or("");
+    strategies::MULTI_VENUE.contains(&strategy)
+}
+
+fn construct_venues_robot(config: &Value, venues: Venues) -> Box<dyn Strategy> {
+    match strategies::build_venues(config, venues) {
+        Some(robot) => robot,
+        None => {
+            warn!("strategy not found!");
+            process::exit(1);
+        }
+    }
+}
+
+fn run(config: &Value) {
+    let mut robot = if multi_venue(config) {
+        let venues = exchange::venues(config)
+            .into_iter()
+            .map(|(name, client)| (name, Box::new(Instrumented::new(client)) as Box<dyn Client>))
+            .collect();
+        construct_venues_robot(config, venues)
     } else {
-        "./config.json"
+        let client = Instrumented::new(exchange::binance(config));
+        construct_robot(config, Box::new(client))
     };
-    info!("config file: {}", config_path);
+    info!("robot: {:?}", robot.stringify());
+    robot.run_forever();
+}
 
-    let mut robot = construct_robot(&config_path);
-    if robot.name() != "dummy" {
-        info!("robot: {:?}", robot.stringify());
-        robot.run_forever();
+fn paper(config: &mut Value) {
+    // keep paper trades out of the live state file
+    let state_file = config["paper"]["state_file"]
+        .as_str()
+        .unwrap_or("./paper_state.json")
+        .to_string();
+    config["state_file"] = state_file.into();
+    let paper = &config["paper"];
+    let fee = paper["fee"].as_f64().unwrap_or(0.001);
+    let mut clients = if multi_venue(config) {
+        exchange::venues(config)
     } else {
-        warn!("strategy not found!");
+        vec![("default".into(), exchange::binance(config))]
+    };
+    let mut venues: Venues = vec![];
+    for (name, client) in clients.drain(..) {
+        // per venue balances under paper.venues, paper.balances otherwise
+        let balances = match paper["venues"][&name]["balances"].as_object() {
+            Some(_) => backtest::balances(&paper["venues"][&name]["balances"]),
+            None => backtest::balances(&paper["balances"]),
+        };
+        match PaperExchange::new(Instrumented::new(client), &balances, fee) {
+            Ok(client) => venues.push((name, Box::new(client))),
+            Err(err) => {
+                warn!("paper exchange {} error: {:?}", name, err);
+                process::exit(1);
+            }
+        }
+    }
+    let mut robot = construct_venues_robot(config, venues);
+    info!("paper robot: {:?}", robot.stringify());
+    robot.run_forever();
+}
+
+fn run_backtest(config: &mut Value, matches: &ArgMatches) {
+    if let Some(dir) = matches.value_of("data-dir") {
+        config["backtest"]["data_dir"] = dir.into();
+    }
+    let data = match backtest::data::load(config) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("load data error: {}", err);
+            process::exit(1);
+        }
+    };
+    let result = match backtest::run(config, &data) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("backtest error: {}", err);
+            process::exit(1);
+        }
+    };
+    println!("{}", serde_json::to_string_pretty(&result.metrics).unwrap());
+    if let Some(output) = matches.value_of("output") {
+        let content = serde_json::to_string_pretty(&result).unwrap();
+        if let Err(err) = fs::write(output, content) {
+            eprintln!("write {} error: {}", output, err);
+            process::exit(1);
+        }
+        println!("result written to {}", output);
+    }
+    export(&result, matches);
+}
+
+fn export(result: &backtest::BacktestResult, matches: &ArgMatches) {
+    if let Some(path) = matches.value_of("html") {
+        if let Err(err) = backtest::report::write_html(path, result) {
+            eprintln!("write {} error: {}", path, err);
+            process::exit(1);
+        }
+        println!("html report written to {}", path);
+    }
+    if let Some(prefix) = matches.value_of("csv") {
+        if let Err(err) = backtest::report::write_csv(prefix, result) {
+            eprintln!("write {}_*.csv error: {}", prefix, err);
+            process::exit(1);
+        }
+        println!("csv written to {}_trades.csv and {}_equity.csv", prefix, prefix);
+    }
+}
+
+fn report(matches: &ArgMatches) {
+    let path = matches.value_of("input").unwrap();
+    let content = fs::read_to_string(path).unwrap_or_else(|err| {
+        eprintln!("read {} error: {}", path, err);
+        process::exit(1);
+    });
+    let mut value: Value = serde_json::from_str(&content).unwrap_or_else(|err| {
+        eprintln!("parse {} error: {}", path, err);
+        process::exit(1);
+    });
+    // walk forward reports keep the chained result under "result"
+    if value["result"].is_object() {
+        value = value["result"].take();
+    }
+    let result: backtest::BacktestResult = serde_json::from_value(value).unwrap_or_else(|err| {
+        eprintln!("invalid backtest result {}: {}", path, err);
+        process::exit(1);
+    });
+    export(&result, matches);
+}
+
+fn optimize(config: &Value, matches: &ArgMatches) {
+    let mut optimizer = match backtest::Optimizer::from_config(&config["optimize"]) {
+        Ok(optimizer) => optimizer,
+        Err(err) => {
+            eprintln!("optimize config error: {}", err);
+            process::exit(1);
+        }
+    };
+    if let Some(threads) = matches.value_of("threads") {
+        optimizer.set_threads(threads.parse().expect("threads should be a number"));
+    }
+    let data = match backtest::data::load(config) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("load data error: {}", err);
+            process::exit(1);
+        }
+    };
+    let trials = match optimizer.run(config, &data) {
+        Ok(trials) => trials,
+        Err(err) => {
+            eprintln!("optimize error: {}", err);
+            process::exit(1);
+        }
+    };
+    for (i, trial) in trials.iter().take(10).enumerate() {
+        println!(
+            "#{} score: {:.4}, feasible: {}, max_drawdown: {:.4}, trades: {}, params: {:?}",
+            i + 1,
+            trial.score,
+            trial.feasible,
+            trial.metrics.max_drawdown,
+            trial.metrics.trades,
+            trial.params
+        );
+    }
+    let output = matches.value_of("output").unwrap();
+    if let Err(err) = backtest::optimize::write_csv(output, &trials) {
+        eprintln!("write {} error: {}", output, err);
+        process::exit(1);
+    }
+    println!("{} trials written to {}", trials.len(), output);
+}
+
+fn walk_forward(config: &Value, matches: &ArgMatches) {
+    let optimizer = backtest::Optimizer::from_config(&config["optimize"]);
+    let walk_forward = backtest::WalkForward::from_config(&config["walk_forward"]);
+    let (mut optimizer, walk_forward) = match (optimizer, walk_forward) {
+        (Ok(optimizer), Ok(walk_forward)) => (optimizer, walk_forward),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("walk forward config error: {}", err);
+            process::exit(1);
+        }
+    };
+    if let Some(threads) = matches.value_of("threads") {
+        optimizer.set_threads(threads.parse().expect("threads should be a number"));
+    }
+    let data = match backtest::data::load(config) {
+        Ok(data) => data,
+        Err(err) => {
+            eprintln!("load data error: {}", err);
+            process::exit(1);
+        }
+    };
+    let result = match walk_forward.run(&optimizer, config, &data) {
+        Ok(result) => result,
+        Err(err) => {
+            eprintln!("walk forward error: {}", err);
+            process::exit(1);
+        }
+    };
+    for (i, window) in result.windows.iter().enumerate() {
+        println!(
+            "#{} in-sample pnl: {:.4}, out-of-sample pnl: {:.4}, params: {:?}",
+            i + 1,
+            window.in_sample.net_pnl,
+            window.out_of_sample.net_pnl,
+            window.params
+        );
+    }
+    println!("{}", serde_json::to_string_pretty(&result.result.metrics).unwrap());
+    if let Some(output) = matches.value_of("output") {
+        let content = serde_json::to_string_pretty(&result).unwrap();
+        if let Err(err) = fs::write(output, content) {
+            eprintln!("write {} error: {}", output, err);
+            process::exit(1);
+        }
+        println!("report written to {}", output);
+    }
+}
+
+fn monte_carlo(config: &Value, matches: &ArgMatches) {
+    let mc = match backtest::MonteCarlo::from_config(&config["monte_carlo"]) {
+        Ok(mc) => mc,
+        Err(err) => {
+            eprintln!("monte carlo config error: {}", err);
+            process::exit(1);
+        }
+    };
+    // a backtest result or a strategy state
+    let input = match matches.value_of("input") {
+        Some(path) => {
+            let content = fs::read_to_string(path).unwrap_or_else(|err| {
+                eprintln!("read {} error: {}", path, err);
+                process::exit(1);
+            });
+            serde_json::from_str(&content).unwrap_or_else(|err| {
+                eprintln!("parse {} error: {}", path, err);
+                process::exit(1);
+            })
+        }
+        None => {
+            let data = backtest::data::load(config).unwrap_or_else(|err| {
+                eprintln!("load data error: {}", err);
+                process::exit(1);
+            });
+            let result = backtest::run(config, &data).unwrap_or_else(|err| {
+                eprintln!("backtest error: {}", err);
+                process::exit(1);
+            });
+            serde_json::to_value(&result).unwrap()
+        }
+    };
+    let (trades, capital) = if input["fills"].is_array() {
+        let result: backtest::BacktestResult = serde_json::from_value(input).unwrap_or_else(|err| {
+            eprintln!("invalid backtest result: {}", err);
+            process::exit(1);
+        });
+        (
+            backtest::monte_carlo::from_fills(&result.fills),
+            result.metrics.start_equity,
+        )
+    } else {
+        (backtest::monte_carlo::from_history(&input["history"]), 0f64)
+    };
+    let report = match mc.run(&trades, capital) {
+        Ok(report) => report,
+        Err(err) => {
+            eprintln!("monte carlo error: {}", err);
+            process::exit(1);
+        }
+    };
+    let content = serde_json::to_string_pretty(&report).unwrap();
+    println!("{}", content);
+    if let Some(output) = matches.value_of("output") {
+        if let Err(err) = fs::write(output, content) {
+            eprintln!("write {} error: {}", output, err);
+            process::exit(1);
+        }
+    }
+}
+
+fn validate_config(config: &Value) {
+    let errors = strategies::validate(config);
+    if errors.is_empty() {
+        println!("config ok");
+        return;
+    }
+    for err in &errors {
+        println!("{}", err);
+    }
+    process::exit(1);
+}
+
+fn positions(config: &Value, matches: &ArgMatches) {
+    let quote = MoveStopLoss::currency(config);
+    let min_value: f64 = matches
+        .value_of("min-value")
+        .unwrap()
+        .parse()
+        .expect("min-value should be a number");
+    if !multi_venue(config) {
+        print_positions(&exchange::binance(config), &quote, min_value);
+        return;
+    }
+    // one table per venue
+    for (name, client) in exchange::venues(config) {
+        println!("{}:", name);
+        print_positions(&client, &quote, min_value);
+    }
+}
+
+fn print_positions(client: &dyn Client, quote: &str, min_value: f64) {
+    let balances = match client.get_all_balances() {
+        Ok(balances) => balances,
+        Err(err) => {
+            eprintln!("get_all_balances error: {:?}", err);
+            process::exit(1);
+        }
+    };
+    println!(
+        "{:<10} {:>18} {:>18} {:>14}",
+        "asset", "free", "locked", quote
+    );
+    for balance in balances {
+        let amount = balance.free + balance.locked;
+        if amount <= 0f64 {
+            continue;
+        }
+        let value = if balance.asset == quote {
+            amount
+        } else {
+            match client.get_ticker(&format!("{}{}", balance.asset, quote)) {
+                Ok(ticker) => amount * ticker.bid.price,
+                Err(_) => 0f64,
+            }
+        };
+        if value < min_value {
+            continue;
+        }
+        println!(
+            "{:<10} {:>18} {:>18} {:>14.2}",
+            balance.asset, balance.free, balance.locked, value
+        );
+    }
+}
+
+fn history(config: &Value, matches: &ArgMatches) {
+    let path = state::path(config);
+    let state = match state::load(&path) {
+        Some(state) => state,
+        None => {
+            eprintln!("no state found at {}", path);
+            process::exit(1);
+        }
+    };
+    let limit: usize = matches
+        .value_of("limit")
+        .unwrap()
+        .parse()
+        .expect("limit should be a number");
+    let records = state["history"].as_array().cloned().unwrap_or_default();
+    let start = records.len().saturating_sub(limit);
+    for record in &records[start..] {
+        println!("{}", record);
+    }
+    println!(
+        "records: {}, total_profit: {}",
+        records.len(),
+        state["total_profit"]
+    );
+}
+
+fn audit(config: &Value, matches: &ArgMatches) {
+    let path = match config["audit_file"].as_str() {
+        Some(path) => path,
+        None => {
+            eprintln!("audit_file not configured");
+            process::exit(1);
+        }
+    };
+    let query = audit::Query {
+        symbol: matches.value_of("symbol").map(|s| s.into()),
+        decision: matches.value_of("decision").map(|s| s.into()),
+        ..audit::Query::default()
+    };
+    let entries = match audit::read(path, &query) {
+        Ok(entries) => entries,
+        Err(err) => {
+            eprintln!("read {} error: {}", path, err);
+            process::exit(1);
+        }
+    };
+    let limit: usize = matches
+        .value_of("limit")
+        .unwrap()
+        .parse()
+        .expect("limit should be a number");
+    let start = entries.len().saturating_sub(limit);
+    for entry in &entries[start..] {
+        println!("{}", serde_json::to_string(entry).unwrap());
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let matches = App::new("rsquant")
+        .about("Cryptocurrency quant framework")
+        .setting(AppSettings::VersionlessSubcommands)
+        .arg(
+            Arg::with_name("config")
+                .short("c")
+                .long("config")
+                .value_name("FILE")
+                .default_value("./config.json")
+                .global(true)
+                .help("Config file"),
+        )
+        .subcommand(SubCommand::with_name("run").about("Run the configured strategy (default)"))
+        .subcommand(
+            SubCommand::with_name("backtest")
+                .about("Run the configured strategy over historical klines")
+                .arg(
+                    Arg::with_name("data-dir")
+                        .long("data-dir")
+                        .value_name("DIR")
+                        .help("Override backtest.data_dir"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the full result as json"),
+                )
+                .arg(
+                    Arg::with_name("html")
+                        .long("html")
+                        .value_name("FILE")
+                        .help("Write an html report"),
+                )
+                .arg(
+                    Arg::with_name("csv")
+                        .long("csv")
+                        .value_name("PREFIX")
+                        .help("Write PREFIX_trades.csv and PREFIX_equity.csv"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("report")
+                .about("Export a saved backtest or walk-forward result")
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("input")
+                        .value_name("FILE")
+                        .required(true)
+                        .help("Result json written with -o"),
+                )
+                .arg(
+                    Arg::with_name("html")
+                        .long("html")
+                        .value_name("FILE")
+                        .help("Write an html report"),
+                )
+                .arg(
+                    Arg::with_name("csv")
+                        .long("csv")
+                        .value_name("PREFIX")
+                        .help("Write PREFIX_trades.csv and PREFIX_equity.csv"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("optimize")
+                .about("Search strategy parameters over historical klines")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .default_value("./optimize.csv")
+                        .help("Write all trials as csv"),
+                )
+                .arg(
+                    Arg::with_name("threads")
+                        .short("j")
+                        .long("threads")
+                        .value_name("N")
+                        .help("Override optimize.threads"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("walk-forward")
+                .about("Optimize on rolling windows and test on the data that follows")
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the report as json"),
+                )
+                .arg(
+                    Arg::with_name("threads")
+                        .short("j")
+                        .long("threads")
+                        .value_name("N")
+                        .help("Override optimize.threads"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("monte-carlo")
+                .about("Resample closed trades to estimate PnL, drawdown and risk of ruin")
+                .arg(
+                    Arg::with_name("input")
+                        .short("i")
+                        .long("input")
+                        .value_name("FILE")
+                        .help("Backtest result or state file, runs a backtest if omitted"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Write the report as json"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("paper")
+                .about("Run the configured strategy on live prices with simulated orders"),
+        )
+        .subcommand(SubCommand::with_name("validate-config").about("Check the config file"))
+        .subcommand(SubCommand::with_name("list-strategies").about("List available strategies"))
+        .subcommand(
+            SubCommand::with_name("positions")
+                .about("Show exchange balances and their value in the quote currency")
+                .arg(
+                    Arg::with_name("min-value")
+                        .long("min-value")
+                        .value_name("VALUE")
+                        .default_value("0")
+                        .help("Hide balances worth less than VALUE"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("history")
+                .about("Show trade records saved by the running strategy")
+                .arg(
+                    Arg::with_name("limit")
+                        .short("n")
+                        .long("limit")
+                        .value_name("N")
+                        .default_value("20")
+                        .help("Show the last N records"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("audit")
+                .about("Show decisions recorded in the audit log")
+                .arg(
+                    Arg::with_name("symbol")
+                        .long("symbol")
+                        .value_name("SYMBOL")
+                        .help("Only entries of SYMBOL"),
+                )
+                .arg(
+                    Arg::with_name("decision")
+                        .long("decision")
+                        .value_name("DECISION")
+                        .help("Only entries with DECISION, e.g. stoploss, withdraw, hold"),
+                )
+                .arg(
+                    Arg::with_name("limit")
+                        .short("n")
+                        .long("limit")
+                        .value_name("N")
+                        .default_value("20")
+                        .help("Show the last N entries"),
+                ),
+        )
+        .get_matches();
+
+    let (command, sub_matches) = matches.subcommand();
+    let sub_matches = sub_matches.unwrap_or(&matches);
+    if command == "list-strategies" {
+        for strategy in strategies::STRATEGIES {
+            println!("{}", strategy);
+        }
+        return;
+    }
+    if command == "report" {
+        report(sub_matches);
+        return;
+    }
+
+    let config_path = sub_matches.value_of("config").unwrap();
+    info!("config file: {}", config_path);
+    let mut config = load_config(config_path);
+
+    match command {
+        "backtest" => run_backtest(&mut config, sub_matches),
+        "optimize" => optimize(&config, sub_matches),
+        "walk-forward" => walk_forward(&config, sub_matches),
+        "monte-carlo" => monte_carlo(&config, sub_matches),
+        "paper" => paper(&mut config),
+        "validate-config" => validate_config(&config),
+        "positions" => positions(&config, sub_matches),
+        "history" => history(&config, sub_matches),
+        "audit" => audit(&config, sub_matches),
+        _ => run(&config),
     }
 }
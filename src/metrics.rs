@@ -0,0 +1,218 @@
+use lazy_static::lazy_static;
+use log::{info, warn};
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpListener,
+    sync::Mutex,
+    thread,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Gauge,
+    Counter,
+    Summary,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Gauge => "gauge",
+            Kind::Counter => "counter",
+            Kind::Summary => "summary",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Family {
+    kind: Kind,
+    help: &'static str,
+    // rendered labels -> value, summaries keep (sum, count)
+    values: BTreeMap<String, (f64, u64)>,
+}
+
+#[derive(Debug, Default)]
+struct Registry {
+    families: BTreeMap<&'static str, Family>,
+}
+
+impl Registry {
+    fn family(&mut self, name: &'static str, kind: Kind, help: &'static str) -> &mut Family {
+        self.families.entry(name).or_insert_with(|| Family {
+            kind: kind,
+            help: help,
+            values: BTreeMap::new(),
+        })
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        for (name, family) in &self.families {
+            out += &format!("# HELP {} {}\n", name, family.help);
+            out += &format!("# TYPE {} {}\n", name, family.kind.name());
+            for (labels, (value, count)) in &family.values {
+                if family.kind == Kind::Summary {
+                    out += &format!("{}_sum{} {}\n", name, labels, value);
+                    out += &format!("{}_count{} {}\n", name, labels, count);
+                } else {
+                    out += &format!("{}{} {}\n", name, labels, value);
+                }
+            }
+        }
+        out
+    }
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry::default());
+}
+
+fn render_labels(labels: &[(&str, &str)]) -> String {
+    if labels.is_empty() {
+        return String::new();
+    }
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| {
+            let v = v
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            format!("{}=\"{}\"", k, v)
+        })
+        .collect();
+    format!("{{{}}}", labels.join(","))
+}
+
+pub fn set_gauge(name: &'static str, help: &'static str, labels: &[(&str, &str)], value: f64) {
+    let mut registry = REGISTRY.lock().unwrap();
+    let family = registry.family(name, Kind::Gauge, help);
+    family.values.insert(render_labels(labels), (value, 0));
+}
+
+pub fn inc_counter(name: &'static str, help: &'static str, labels: &[(&str, &str)]) {
+    let mut registry = REGISTRY.lock().unwrap();
+    let family = registry.family(name, Kind::Counter, help);
+    family
+        .values
+        .entry(render_labels(labels))
+        .or_insert((0f64, 0))
+        .0 += 1f64;
+}
+
+pub fn observe(name: &'static str, help: &'static str, labels: &[(&str, &str)], value: f64) {
+    let mut registry = REGISTRY.lock().unwrap();
+    let family = registry.family(name, Kind::Summary, help);
+    let entry = family
+        .values
+        .entry(render_labels(labels))
+        .or_insert((0f64, 0));
+    entry.0 += value;
+    entry.1 += 1;
+}
+
+// drop every series of a gauge, e.g. positions that were closed
+pub fn clear(name: &'static str) {
+    if let Some(family) = REGISTRY.lock().unwrap().families.get_mut(name) {
+        family.values.clear();
+    }
+}
+
+// sum over all series of a counter
+pub fn total(name: &'static str) -> f64 {
+    match REGISTRY.lock().unwrap().families.get(name) {
+        Some(family) => family.values.values().map(|(value, _)| value).sum(),
+        None => 0f64,
+    }
+}
+
+pub fn render() -> String {
+    REGISTRY.lock().unwrap().render()
+}
+
+// serve the prometheus text format on `addr`, any path answers
+pub fn serve(addr: &str) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    info!("metrics listening on {}", listener.local_addr()?);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = match stream {
+                Ok(stream) => stream,
+                Err(err) => {
+                    warn!("metrics accept error: {:?}", err);
+                    continue;
+                }
+            };
+            // skip the request, there is only one page
+            let mut reader = BufReader::new(&stream);
+            let mut line = String::new();
+            while reader.read_line(&mut line).map(|n| n > 0).unwrap_or(false) {
+                if line == "\r\n" || line == "\n" {
+                    break;
+                }
+                line.clear();
+            }
+            let body = render();
+            let ret = write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            if let Err(err) = ret {
+                warn!("metrics write error: {:?}", err);
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::{io::Read, net::TcpStream};
+
+    #[test]
+    fn test_render() {
+        let mut registry = Registry::default();
+        registry
+            .family("rsquant_position_amount", Kind::Gauge, "position amount")
+            .values
+            .insert(render_labels(&[("symbol", "BTCUSDT")]), (0.5, 0));
+        registry
+            .family("rsquant_api_latency_seconds", Kind::Summary, "api latency")
+            .values
+            .insert(render_labels(&[("method", "get_ticker")]), (0.3, 2));
+        assert_eq!(
+            registry.render(),
+            "# HELP rsquant_api_latency_seconds api latency\n\
+             # TYPE rsquant_api_latency_seconds summary\n\
+             rsquant_api_latency_seconds_sum{method=\"get_ticker\"} 0.3\n\
+             rsquant_api_latency_seconds_count{method=\"get_ticker\"} 2\n\
+             # HELP rsquant_position_amount position amount\n\
+             # TYPE rsquant_position_amount gauge\n\
+             rsquant_position_amount{symbol=\"BTCUSDT\"} 0.5\n"
+        );
+        assert_eq!(render_labels(&[("a", "x\"y")]), "{a=\"x\\\"y\"}");
+    }
+
+    #[test]
+    fn test_serve() {
+        inc_counter("rsquant_test_total", "test counter", &[("kind", "a")]);
+        inc_counter("rsquant_test_total", "test counter", &[("kind", "b")]);
+        assert_eq!(total("rsquant_test_total"), 2f64);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+        serve(&addr).unwrap();
+        let mut stream = TcpStream::connect(&addr).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\n\r\n").unwrap();
+        let mut resp = String::new();
+        stream.read_to_string(&mut resp).unwrap();
+        assert!(resp.starts_with("HTTP/1.1 200 OK"));
+        assert!(resp.contains("rsquant_test_total{kind=\"b\"} 1\n"));
+    }
+}
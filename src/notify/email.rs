@@ -0,0 +1,167 @@
+use chrono::Utc;
+use serde_json::Value;
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+use super::{Event, Notifier};
+
+// plain smtp with optional AUTH PLAIN, point it at a local relay when
+// the provider requires TLS
+#[derive(Debug)]
+pub struct Email {
+    server: String,
+    from: String,
+    to: Vec<String>,
+    username: Option<String>,
+    password: Option<String>,
+}
+
+impl Email {
+    pub fn from_config(config: &Value) -> Result<Self, String> {
+        let server = match config["server"].as_str() {
+            Some(server) if server.contains(':') => server.to_string(),
+            Some(server) => format!("{}:25", server),
+            None => return Err("email: server not found".into()),
+        };
+        let from = match config["from"].as_str() {
+            Some(from) => from,
+            None => return Err("email: from not found".into()),
+        };
+        let to: Vec<String> = match config["to"].as_array() {
+            Some(to) => to
+                .iter()
+                .filter_map(|to| to.as_str())
+                .map(|to| to.to_string())
+                .collect(),
+            None => vec![],
+        };
+        if to.is_empty() {
+            return Err("email: to not found".into());
+        }
+        Ok(Email {
+            server: server,
+            from: from.into(),
+            to: to,
+            username: config["username"].as_str().map(|s| s.into()),
+            password: config["password"].as_str().map(|s| s.into()),
+        })
+    }
+
+    fn message(&self, event: &Event) -> String {
+        let mut message = format!(
+            "From: {}\r\nTo: {}\r\nSubject: [rsquant] {}\r\nDate: {}\r\nContent-Type: text/plain; charset=utf-8\r\n\r\n",
+            self.from,
+            self.to.join(", "),
+            event.title,
+            Utc::now().to_rfc2822()
+        );
+        for line in event.message.lines() {
+            // dot stuffing
+            if line.starts_with('.') {
+                message.push('.');
+            }
+            message += line;
+            message += "\r\n";
+        }
+        message += ".\r\n";
+        message
+    }
+}
+
+struct Session {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Session {
+    fn reply(&mut self, expect: &str) -> Result<(), String> {
+        loop {
+            let mut line = String::new();
+            self.reader
+                .read_line(&mut line)
+                .map_err(|err| err.to_string())?;
+            if line.len() < 4 {
+                return Err(format!("smtp: bad reply {:?}", line));
+            }
+            if !line.starts_with(expect) {
+                return Err(format!("smtp: {}", line.trim_end()));
+            }
+            // "250-" continues a multi-line reply
+            if &line[3..4] != "-" {
+                return Ok(());
+            }
+        }
+    }
+
+    fn command(&mut self, command: &str, expect: &str) -> Result<(), String> {
+        self.writer
+            .write_all(format!("{}\r\n", command).as_bytes())
+            .map_err(|err| err.to_string())?;
+        self.reply(expect)
+    }
+}
+
+impl Notifier for Email {
+    fn notify(&self, event: &Event) -> Result<(), String> {
+        let stream = TcpStream::connect(&self.server).map_err(|err| err.to_string())?;
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .map_err(|err| err.to_string())?;
+        let mut session = Session {
+            reader: BufReader::new(stream.try_clone().map_err(|err| err.to_string())?),
+            writer: stream,
+        };
+        session.reply("220")?;
+        session.command("EHLO rsquant", "250")?;
+        if let (Some(username), Some(password)) = (&self.username, &self.password) {
+            let token = base64::encode(format!("\0{}\0{}", username, password));
+            session.command(&format!("AUTH PLAIN {}", token), "235")?;
+        }
+        session.command(&format!("MAIL FROM:<{}>", self.from), "250")?;
+        for to in &self.to {
+            session.command(&format!("RCPT TO:<{}>", to), "250")?;
+        }
+        session.command("DATA", "354")?;
+        session
+            .writer
+            .write_all(self.message(event).as_bytes())
+            .map_err(|err| err.to_string())?;
+        session.reply("250")?;
+        session.command("QUIT", "221")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notify::{test_server::serve_smtp, EventKind};
+    use serde_json::json;
+
+    #[test]
+    fn test_email() {
+        let (addr, transcript) = serve_smtp();
+        let config = json!({
+            "server": addr,
+            "from": "robot@example.com",
+            "to": ["me@example.com"],
+            "username": "robot",
+            "password": "secret",
+        });
+        let email = Email::from_config(&config).unwrap();
+        let event = Event::new(
+            EventKind::Summary,
+            "daily summary",
+            "total_profit: 1.5\n.hidden",
+        );
+        email.notify(&event).unwrap();
+
+        let transcript = transcript.join().unwrap();
+        assert!(transcript.contains("AUTH PLAIN AHJvYm90AHNlY3JldA=="));
+        assert!(transcript.contains("RCPT TO:<me@example.com>"));
+        assert!(transcript.contains("Subject: [rsquant] daily summary"));
+        assert!(transcript.contains("\r\n..hidden\r\n.\r\n"));
+    }
+}
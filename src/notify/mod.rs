@@ -0,0 +1,377 @@
+use chrono::Utc;
+use lazy_static::lazy_static;
+use log::{debug, warn};
+use serde_derive::Serialize;
+use serde_json::Value;
+use std::{
+    fmt::Debug,
+    sync::{
+        mpsc::{channel, Sender},
+        Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+mod email;
+pub use email::Email;
+
+mod telegram;
+pub use telegram::Telegram;
+
+mod webhook;
+pub use webhook::Webhook;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    // an order was sent, it may still rest on the book
+    Placed,
+    Fill,
+    Stop,
+    Error,
+    Summary,
+}
+
+impl EventKind {
+    fn parse(kind: &str) -> Option<Self> {
+        match kind {
+            "placed" => Some(EventKind::Placed),
+            "fill" => Some(EventKind::Fill),
+            "stop" => Some(EventKind::Stop),
+            "error" => Some(EventKind::Error),
+            "summary" => Some(EventKind::Summary),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Event {
+    pub kind: EventKind,
+    pub title: String,
+    pub message: String,
+    pub timestamp: i64,
+}
+
+impl Event {
+    pub fn new(kind: EventKind, title: &str, message: &str) -> Self {
+        Event {
+            kind: kind,
+            title: title.into(),
+            message: message.into(),
+            timestamp: Utc::now().timestamp_millis(),
+        }
+    }
+}
+
+pub trait Notifier: Debug + Send {
+    fn notify(&self, event: &Event) -> Result<(), String>;
+}
+
+// at most `limit` messages per `window` ms, the rest are dropped and
+// counted so the next delivered message can mention them
+#[derive(Debug)]
+struct RateLimit {
+    limit: usize,
+    window: i64,
+    sent: Vec<i64>,
+    dropped: usize,
+}
+
+impl RateLimit {
+    fn allow(&mut self, now: i64) -> bool {
+        let window = self.window;
+        self.sent.retain(|t| now - t < window);
+        if self.sent.len() >= self.limit {
+            self.dropped += 1;
+            return false;
+        }
+        self.sent.push(now);
+        true
+    }
+}
+
+#[derive(Debug)]
+struct Route {
+    notifier: Box<dyn Notifier>,
+    events: Vec<EventKind>,
+    rate_limit: RateLimit,
+}
+
+// routes events to the configured backends, synchronously
+#[derive(Debug, Default)]
+pub struct Dispatcher {
+    routes: Vec<Route>,
+}
+
+impl Dispatcher {
+    pub fn from_config(config: &Value) -> Result<Self, String> {
+        let mut dispatcher = Dispatcher::default();
+        let backends = match config.as_array() {
+            Some(backends) => backends,
+            None if config.is_null() => return Ok(dispatcher),
+            None => return Err("notify: expect array".into()),
+        };
+        for backend in backends {
+            let notifier: Box<dyn Notifier> = match backend["type"].as_str() {
+                Some("webhook") => Box::new(Webhook::from_config(backend)?),
+                Some("telegram") => Box::new(Telegram::from_config(backend)?),
+                Some("email") => Box::new(Email::from_config(backend)?),
+                other => return Err(format!("notify: unknown type {:?}", other)),
+            };
+            let events = match backend["events"].as_array() {
+                Some(events) => {
+                    let mut kinds = vec![];
+                    for event in events {
+                        match event.as_str().and_then(EventKind::parse) {
+                            Some(kind) => kinds.push(kind),
+                            None => return Err(format!("notify: unknown event {}", event)),
+                        }
+                    }
+                    kinds
+                }
+                None => vec![
+                    EventKind::Placed,
+                    EventKind::Fill,
+                    EventKind::Stop,
+                    EventKind::Error,
+                    EventKind::Summary,
+                ],
+            };
+            let limit = backend["rate_limit"].as_u64().unwrap_or(20) as usize;
+            dispatcher.add(notifier, events, limit);
+        }
+        Ok(dispatcher)
+    }
+
+    // `limit` messages per minute
+    pub fn add(&mut self, notifier: Box<dyn Notifier>, events: Vec<EventKind>, limit: usize) {
+        self.routes.push(Route {
+            notifier: notifier,
+            events: events,
+            rate_limit: RateLimit {
+                limit: limit,
+                window: 60_000,
+                sent: vec![],
+                dropped: 0,
+            },
+        });
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.routes.is_empty()
+    }
+
+    pub fn dispatch(&mut self, event: &Event) {
+        for route in self.routes.iter_mut() {
+            if !route.events.contains(&event.kind) {
+                continue;
+            }
+            let dropped = route.rate_limit.dropped;
+            if !route.rate_limit.allow(event.timestamp) {
+                debug!("{:?} rate limited: {:?}", route.notifier, event.title);
+                continue;
+            }
+            route.rate_limit.dropped = 0;
+            let ret = if dropped > 0 {
+                let mut event = event.clone();
+                event.message += &format!("\n({} messages suppressed by rate limit)", dropped);
+                route.notifier.notify(&event)
+            } else {
+                route.notifier.notify(event)
+            };
+            if let Err(err) = ret {
+                warn!("{:?} notify error: {}", route.notifier, err);
+            }
+        }
+    }
+}
+
+lazy_static! {
+    static ref SENDER: Mutex<Option<Sender<Event>>> = Mutex::new(None);
+    static ref WORKER: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+}
+
+// start delivering events in a background thread so a slow backend
+// never delays a tick, events sent before init are dropped
+pub fn init(config: &Value) -> Result<(), String> {
+    let mut dispatcher = Dispatcher::from_config(config)?;
+    if dispatcher.is_empty() {
+        return Ok(());
+    }
+    let (sender, receiver) = channel::<Event>();
+    let worker = thread::spawn(move || {
+        for event in receiver {
+            dispatcher.dispatch(&event);
+        }
+    });
+    *SENDER.lock().unwrap() = Some(sender);
+    *WORKER.lock().unwrap() = Some(worker);
+    Ok(())
+}
+
+// deliver queued events and stop the background thread
+pub fn flush() {
+    SENDER.lock().unwrap().take();
+    if let Some(worker) = WORKER.lock().unwrap().take() {
+        let _ = worker.join();
+    }
+}
+
+pub fn send(kind: EventKind, title: &str, message: &str) {
+    if let Some(sender) = SENDER.lock().unwrap().as_ref() {
+        let _ = sender.send(Event::new(kind, title, message));
+    }
+}
+
+// local stand-ins for the http and smtp backends
+#[cfg(test)]
+pub(crate) mod test_server {
+    use std::{
+        io::{BufRead, BufReader, Read, Write},
+        net::TcpListener,
+        thread::{self, JoinHandle},
+    };
+
+    // answer one request with `status`, return the raw request
+    pub fn serve_http(status: u16) -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request = String::new();
+            let mut length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                if line.to_lowercase().starts_with("content-length:") {
+                    length = line[15..].trim().parse().unwrap();
+                }
+                request += &line;
+                if line == "\r\n" {
+                    break;
+                }
+            }
+            let mut body = vec![0u8; length];
+            reader.read_exact(&mut body).unwrap();
+            request += &String::from_utf8(body).unwrap();
+            let mut stream = stream;
+            write!(
+                stream,
+                "HTTP/1.1 {} X\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                status
+            )
+            .unwrap();
+            request
+        });
+        (addr, handle)
+    }
+
+    // accept one smtp session, return everything the client sent
+    pub fn serve_smtp() -> (String, JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut transcript = String::new();
+            let mut data = false;
+            stream.write_all(b"220 localhost\r\n").unwrap();
+            loop {
+                let mut line = String::new();
+                if reader.read_line(&mut line).unwrap() == 0 {
+                    break;
+                }
+                transcript += &line;
+                let reply: &[u8] = if data {
+                    if line != ".\r\n" {
+                        continue;
+                    }
+                    data = false;
+                    b"250 queued\r\n"
+                } else if line.starts_with("EHLO") {
+                    b"250-localhost\r\n250 AUTH PLAIN\r\n"
+                } else if line.starts_with("AUTH") {
+                    b"235 ok\r\n"
+                } else if line.starts_with("DATA") {
+                    data = true;
+                    b"354 go ahead\r\n"
+                } else if line.starts_with("QUIT") {
+                    stream.write_all(b"221 bye\r\n").unwrap();
+                    break;
+                } else {
+                    b"250 ok\r\n"
+                };
+                stream.write_all(reply).unwrap();
+            }
+            transcript
+        });
+        (addr, handle)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Debug, Default)]
+    struct Collect {
+        events: Arc<Mutex<Vec<Event>>>,
+    }
+
+    impl Notifier for Collect {
+        fn notify(&self, event: &Event) -> Result<(), String> {
+            self.events.lock().unwrap().push(event.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_dispatch_filter_and_rate_limit() {
+        let events = Arc::new(Mutex::new(vec![]));
+        let mut dispatcher = Dispatcher::default();
+        dispatcher.add(
+            Box::new(Collect {
+                events: events.clone(),
+            }),
+            vec![EventKind::Stop],
+            2,
+        );
+
+        let mut event = Event::new(EventKind::Stop, "stop", "BTCUSDT");
+        event.timestamp = 0;
+        dispatcher.dispatch(&Event::new(EventKind::Fill, "fill", "ignored"));
+        dispatcher.dispatch(&event);
+        dispatcher.dispatch(&event);
+        dispatcher.dispatch(&event);
+        assert_eq!(events.lock().unwrap().len(), 2);
+
+        event.timestamp = 60_000;
+        dispatcher.dispatch(&event);
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 3);
+        assert!(events[2].message.contains("1 messages suppressed"));
+    }
+
+    #[test]
+    fn test_from_config() {
+        let config = serde_json::json!([
+            {"type": "webhook", "url": "http://127.0.0.1:1/hook", "events": ["stop", "error"]},
+            {"type": "telegram", "token": "t", "chat_id": "1"},
+        ]);
+        assert_eq!(Dispatcher::from_config(&config).unwrap().routes.len(), 2);
+        let config =
+            serde_json::json!([{"type": "webhook", "url": "x", "events": ["placed", "fill"]}]);
+        assert_eq!(
+            Dispatcher::from_config(&config).unwrap().routes[0]
+                .events
+                .len(),
+            2
+        );
+        let config = serde_json::json!([{"type": "webhook", "url": "x", "events": ["fills"]}]);
+        assert!(Dispatcher::from_config(&config).is_err());
+        assert!(Dispatcher::from_config(&Value::Null).unwrap().is_empty());
+    }
+}
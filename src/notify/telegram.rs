@@ -0,0 +1,78 @@
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use super::{Event, Notifier};
+
+#[derive(Debug)]
+pub struct Telegram {
+    client: Client,
+    api: String,
+    token: String,
+    chat_id: String,
+}
+
+impl Telegram {
+    pub fn from_config(config: &Value) -> Result<Self, String> {
+        let token = match config["token"].as_str() {
+            Some(token) => token,
+            None => return Err("telegram: token not found".into()),
+        };
+        // chat ids are numbers, channel names strings
+        let chat_id = match &config["chat_id"] {
+            Value::String(chat_id) => chat_id.clone(),
+            Value::Number(chat_id) => chat_id.to_string(),
+            _ => return Err("telegram: chat_id not found".into()),
+        };
+        let api = config["api"].as_str().unwrap_or("https://api.telegram.org");
+        let client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|err| err.to_string())?;
+        Ok(Telegram {
+            client: client,
+            api: api.trim_end_matches('/').into(),
+            token: token.into(),
+            chat_id: chat_id,
+        })
+    }
+}
+
+impl Notifier for Telegram {
+    fn notify(&self, event: &Event) -> Result<(), String> {
+        let url = format!("{}/bot{}/sendMessage", self.api, self.token);
+        let resp = self
+            .client
+            .post(&url)
+            .json(&json!({
+                "chat_id": self.chat_id,
+                "text": format!("{}\n{}", event.title, event.message),
+            }))
+            .send()
+            .map_err(|err| err.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("telegram: status {}", resp.status()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notify::{test_server::serve_http, EventKind};
+
+    #[test]
+    fn test_telegram() {
+        let (addr, request) = serve_http(200);
+        let config = json!({"api": format!("http://{}", addr), "token": "abc", "chat_id": 42});
+        let telegram = Telegram::from_config(&config).unwrap();
+        telegram
+            .notify(&Event::new(EventKind::Error, "error", "get_ticker timeout"))
+            .unwrap();
+
+        let request = request.join().unwrap();
+        assert!(request.starts_with("POST /botabc/sendMessage"));
+        assert!(request.contains(r#""chat_id":"42""#));
+    }
+}
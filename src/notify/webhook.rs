@@ -0,0 +1,82 @@
+use reqwest::blocking::Client;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+use super::{Event, Notifier};
+
+// generic json body, or the "slack"/"discord" incoming webhook formats
+#[derive(Debug)]
+pub struct Webhook {
+    client: Client,
+    url: String,
+    format: String,
+}
+
+impl Webhook {
+    pub fn from_config(config: &Value) -> Result<Self, String> {
+        let url = match config["url"].as_str() {
+            Some(url) => url,
+            None => return Err("webhook: url not found".into()),
+        };
+        let format = config["format"].as_str().unwrap_or("json");
+        if !["json", "slack", "discord"].contains(&format) {
+            return Err(format!("webhook: unknown format {:?}", format));
+        }
+        let client = Client::builder()
+            .timeout(Duration::from_secs(10))
+            .build()
+            .map_err(|err| err.to_string())?;
+        Ok(Webhook {
+            client: client,
+            url: url.into(),
+            format: format.into(),
+        })
+    }
+
+    fn body(&self, event: &Event) -> Value {
+        match self.format.as_str() {
+            "slack" => json!({ "text": format!("*{}*\n{}", event.title, event.message) }),
+            "discord" => json!({ "content": format!("**{}**\n{}", event.title, event.message) }),
+            _ => json!(event),
+        }
+    }
+}
+
+impl Notifier for Webhook {
+    fn notify(&self, event: &Event) -> Result<(), String> {
+        let resp = self
+            .client
+            .post(&self.url)
+            .json(&self.body(event))
+            .send()
+            .map_err(|err| err.to_string())?;
+        if !resp.status().is_success() {
+            return Err(format!("webhook: status {}", resp.status()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::notify::{test_server::serve_http, EventKind};
+
+    #[test]
+    fn test_webhook() {
+        let (addr, request) = serve_http(200);
+        let config = json!({"url": format!("http://{}/hook", addr), "format": "slack"});
+        let webhook = Webhook::from_config(&config).unwrap();
+        let event = Event::new(EventKind::Stop, "stoploss", "BTCUSDT sold");
+        webhook.notify(&event).unwrap();
+
+        let request = request.join().unwrap();
+        assert!(request.starts_with("POST /hook"));
+        assert!(request.ends_with(r#"{"text":"*stoploss*\nBTCUSDT sold"}"#));
+
+        let (addr, _) = serve_http(500);
+        let config = json!({ "url": format!("http://{}/hook", addr) });
+        let webhook = Webhook::from_config(&config).unwrap();
+        assert!(webhook.notify(&event).is_err());
+    }
+}
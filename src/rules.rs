@@ -0,0 +1,500 @@
+use rsex::models::Kline;
+use std::collections::HashMap;
+
+use crate::indicators;
+
+// rule expressions of the "rules" strategy, e.g.
+//   ema(12) crosses_above ema(26) and rsi(14) < 70
+// evaluated on a kline series. Values are numbers, comparisons and
+// and/or/not give 1 or 0. Supported:
+//   numbers, open high low close volume
+//   sma(n) ema(n) rsi(n) highest(n) lowest(n) change(n), optionally on
+//   another field: ema(high, 10); atr(n)
+//   + - * /, < <= > >= == !=, crosses_above crosses_below
+//   and or not, parentheses
+// Names defined in the strategy's "indicators" map expand to their
+// expression
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Field {
+    Open,
+    High,
+    Low,
+    Close,
+    Volume,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        match name {
+            "open" => Some(Field::Open),
+            "high" => Some(Field::High),
+            "low" => Some(Field::Low),
+            "close" => Some(Field::Close),
+            "volume" => Some(Field::Volume),
+            _ => None,
+        }
+    }
+
+    fn of(&self, kline: &Kline) -> f64 {
+        match self {
+            Field::Open => kline.open,
+            Field::High => kline.high,
+            Field::Low => kline.low,
+            Field::Close => kline.close,
+            Field::Volume => kline.volume,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Func {
+    Sma,
+    Ema,
+    Rsi,
+    Atr,
+    Highest,
+    Lowest,
+    Change,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    CrossesAbove,
+    CrossesBelow,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(f64),
+    Field(Field),
+    Call(Func, Field, usize),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+fn truth(v: bool) -> f64 {
+    if v {
+        1f64
+    } else {
+        0f64
+    }
+}
+
+impl Expr {
+    // value at bar `index` of `klines`, None while an indicator lacks bars
+    pub fn eval(&self, klines: &[Kline], index: usize) -> Option<f64> {
+        if index >= klines.len() {
+            return None;
+        }
+        match self {
+            Expr::Number(v) => Some(*v),
+            Expr::Field(field) => Some(field.of(&klines[index])),
+            Expr::Call(func, field, n) => {
+                let klines = &klines[..=index];
+                let values: Vec<f64> = klines.iter().map(|k| field.of(k)).collect();
+                let n = *n;
+                match func {
+                    Func::Sma => indicators::sma(&values, n),
+                    Func::Ema => indicators::ema(&values, n),
+                    Func::Rsi => indicators::rsi(&values, n),
+                    Func::Atr => indicators::atr(klines, n),
+                    Func::Highest if n > 0 && values.len() >= n => {
+                        values[values.len() - n..].iter().cloned().reduce(f64::max)
+                    }
+                    Func::Lowest if n > 0 && values.len() >= n => {
+                        values[values.len() - n..].iter().cloned().reduce(f64::min)
+                    }
+                    Func::Change if values.len() > n && values[values.len() - 1 - n] != 0f64 => {
+                        Some(values[values.len() - 1] / values[values.len() - 1 - n] - 1f64)
+                    }
+                    _ => None,
+                }
+            }
+            Expr::Neg(e) => Some(-e.eval(klines, index)?),
+            Expr::Not(e) => Some(truth(e.eval(klines, index)? == 0f64)),
+            Expr::Binary(op, a, b) => {
+                if let Op::CrossesAbove | Op::CrossesBelow = op {
+                    if index == 0 {
+                        return None;
+                    }
+                    let (a0, b0) = (a.eval(klines, index - 1)?, b.eval(klines, index - 1)?);
+                    let (a1, b1) = (a.eval(klines, index)?, b.eval(klines, index)?);
+                    return Some(truth(if *op == Op::CrossesAbove {
+                        a0 <= b0 && a1 > b1
+                    } else {
+                        a0 >= b0 && a1 < b1
+                    }));
+                }
+                let a = a.eval(klines, index)?;
+                // no need to look at the right side
+                match op {
+                    Op::And if a == 0f64 => return Some(0f64),
+                    Op::Or if a != 0f64 => return Some(1f64),
+                    _ => {}
+                }
+                let b = b.eval(klines, index)?;
+                Some(match op {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div if b == 0f64 => return None,
+                    Op::Div => a / b,
+                    Op::Lt => truth(a < b),
+                    Op::Le => truth(a <= b),
+                    Op::Gt => truth(a > b),
+                    Op::Ge => truth(a >= b),
+                    Op::Eq => truth(a == b),
+                    Op::Ne => truth(a != b),
+                    Op::And | Op::Or => truth(b != 0f64),
+                    Op::CrossesAbove | Op::CrossesBelow => unreachable!(),
+                })
+            }
+        }
+    }
+
+    // bars needed before the expression has a value
+    pub fn lookback(&self) -> usize {
+        match self {
+            Expr::Number(_) | Expr::Field(_) => 1,
+            // an ema settles after a few lengths
+            Expr::Call(Func::Ema, _, n) | Expr::Call(Func::Rsi, _, n) => n * 3 + 1,
+            Expr::Call(_, _, n) => n + 1,
+            Expr::Neg(e) | Expr::Not(e) => e.lookback(),
+            Expr::Binary(op, a, b) => {
+                let extra = if let Op::CrossesAbove | Op::CrossesBelow = op {
+                    1
+                } else {
+                    0
+                };
+                a.lookback().max(b.lookback()) + extra
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(&'static str),
+    Open,
+    Close,
+    Comma,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let chars: Vec<char> = text.chars().collect();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() || c == '.' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            let number = number
+                .parse()
+                .map_err(|_| format!("bad number {:?}", number))?;
+            tokens.push(Token::Number(number));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Ident(chars[start..i].iter().collect()));
+        } else {
+            let two: String = chars[i..(i + 2).min(chars.len())].iter().collect();
+            let op = ["<=", ">=", "==", "!="].iter().find(|op| **op == two);
+            if let Some(op) = op {
+                tokens.push(Token::Op(op));
+                i += 2;
+                continue;
+            }
+            tokens.push(match c {
+                '(' => Token::Open,
+                ')' => Token::Close,
+                ',' => Token::Comma,
+                '+' => Token::Op("+"),
+                '-' => Token::Op("-"),
+                '*' => Token::Op("*"),
+                '/' => Token::Op("/"),
+                '<' => Token::Op("<"),
+                '>' => Token::Op(">"),
+                _ => return Err(format!("unexpected {:?} at {}", c, i)),
+            });
+            i += 1;
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    names: &'a HashMap<String, Expr>,
+}
+
+impl<'a> Parser<'a> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn keyword(&mut self, word: &str) -> bool {
+        if self.peek() == Some(&Token::Ident(word.into())) {
+            self.pos += 1;
+            return true;
+        }
+        false
+    }
+
+    fn expect(&mut self, token: Token) -> Result<(), String> {
+        match self.next() {
+            Some(ref t) if *t == token => Ok(()),
+            t => Err(format!("expect {:?}, got {:?}", token, t)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.keyword("or") {
+            expr = Expr::Binary(Op::Or, Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.not()?;
+        while self.keyword("and") {
+            expr = Expr::Binary(Op::And, Box::new(expr), Box::new(self.not()?));
+        }
+        Ok(expr)
+    }
+
+    fn not(&mut self) -> Result<Expr, String> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.sum()?;
+        let op = match self.peek() {
+            Some(Token::Op("<")) => Op::Lt,
+            Some(Token::Op("<=")) => Op::Le,
+            Some(Token::Op(">")) => Op::Gt,
+            Some(Token::Op(">=")) => Op::Ge,
+            Some(Token::Op("==")) => Op::Eq,
+            Some(Token::Op("!=")) => Op::Ne,
+            Some(Token::Ident(word)) if word == "crosses_above" => Op::CrossesAbove,
+            Some(Token::Ident(word)) if word == "crosses_below" => Op::CrossesBelow,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        Ok(Expr::Binary(op, Box::new(left), Box::new(self.sum()?)))
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op("+")) => Op::Add,
+                Some(Token::Op("-")) => Op::Sub,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op("*")) => Op::Mul,
+                Some(Token::Op("/")) => Op::Div,
+                _ => return Ok(expr),
+            };
+            self.pos += 1;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some(&Token::Op("-")) {
+            self.pos += 1;
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::Number(v)) => Ok(Expr::Number(v)),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                self.expect(Token::Close)?;
+                Ok(expr)
+            }
+            Some(Token::Ident(name)) => {
+                if self.peek() == Some(&Token::Open) {
+                    return self.call(&name);
+                }
+                if let Some(field) = Field::parse(&name) {
+                    return Ok(Expr::Field(field));
+                }
+                match self.names.get(&name) {
+                    Some(expr) => Ok(expr.clone()),
+                    None => Err(format!("unknown name {:?}", name)),
+                }
+            }
+            t => Err(format!("unexpected {:?}", t)),
+        }
+    }
+
+    // name(n) or name(field, n)
+    fn call(&mut self, name: &str) -> Result<Expr, String> {
+        let func = match name {
+            "sma" => Func::Sma,
+            "ema" => Func::Ema,
+            "rsi" => Func::Rsi,
+            "atr" => Func::Atr,
+            "highest" => Func::Highest,
+            "lowest" => Func::Lowest,
+            "change" => Func::Change,
+            _ => return Err(format!("unknown function {:?}", name)),
+        };
+        self.expect(Token::Open)?;
+        let mut field = Field::Close;
+        if let Some(Token::Ident(name)) = self.peek().cloned() {
+            field = Field::parse(&name).ok_or(format!("unknown field {:?}", name))?;
+            self.pos += 1;
+            self.expect(Token::Comma)?;
+        }
+        let n = match self.next() {
+            Some(Token::Number(n)) if n >= 1f64 && n.fract() == 0f64 => n as usize,
+            t => return Err(format!("{}: expect a length, got {:?}", name, t)),
+        };
+        self.expect(Token::Close)?;
+        Ok(Expr::Call(func, field, n))
+    }
+}
+
+// parse `text`, names in `names` stand for their expression
+pub fn parse(text: &str, names: &HashMap<String, Expr>) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?,
+        pos: 0,
+        names: names,
+    };
+    let expr = parser.or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(t) => Err(format!("unexpected {:?}", t)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backtest::data::fixture::bars;
+
+    // bars ranging a point either side of their close
+    fn klines(closes: &[f64]) -> Vec<Kline> {
+        bars(closes, 0, 60_000)
+            .iter()
+            .map(|kline| Kline {
+                high: kline.close + 1f64,
+                low: kline.close - 1f64,
+                ..kline.clone()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let names = HashMap::new();
+        assert_eq!(
+            parse("1 + 2 * 3 > 6 and not close < 0", &names).unwrap(),
+            Expr::Binary(
+                Op::And,
+                Box::new(Expr::Binary(
+                    Op::Gt,
+                    Box::new(Expr::Binary(
+                        Op::Add,
+                        Box::new(Expr::Number(1f64)),
+                        Box::new(Expr::Binary(
+                            Op::Mul,
+                            Box::new(Expr::Number(2f64)),
+                            Box::new(Expr::Number(3f64))
+                        )),
+                    )),
+                    Box::new(Expr::Number(6f64)),
+                )),
+                Box::new(Expr::Not(Box::new(Expr::Binary(
+                    Op::Lt,
+                    Box::new(Expr::Field(Field::Close)),
+                    Box::new(Expr::Number(0f64)),
+                )))),
+            )
+        );
+        assert_eq!(
+            parse("ema(high, 10)", &names).unwrap(),
+            Expr::Call(Func::Ema, Field::High, 10)
+        );
+        assert!(parse("ema(12", &names).is_err());
+        assert!(parse("ema(1.5)", &names).is_err());
+        assert!(parse("foo > 1", &names).is_err());
+        assert!(parse("close >", &names).is_err());
+        assert!(parse("close 1", &names).is_err());
+        assert!(parse("macd(12)", &names).is_err());
+    }
+
+    #[test]
+    fn test_eval() {
+        let mut names = HashMap::new();
+        names.insert("fast".to_string(), parse("sma(2)", &names).unwrap());
+        let rule = parse("fast crosses_above sma(4) and rsi(3) < 100", &names).unwrap();
+        let bars = klines(&[10f64, 9f64, 8f64, 7f64, 9f64, 11f64]);
+        // sma(4) needs 4 bars and one more to cross
+        assert_eq!(rule.eval(&bars, 3), None);
+        // sma(2) 8 vs sma(4) 8.25, the bar before 7.5 vs 8.5
+        assert_eq!(rule.eval(&bars, 4), Some(0f64));
+        // 10 vs 8.75
+        assert_eq!(rule.eval(&bars, 5), Some(1f64));
+        assert_eq!(rule.lookback(), 3 * 3 + 1);
+
+        let e = |text: &str| parse(text, &names).unwrap().eval(&bars, 5);
+        assert_eq!(e("highest(high, 3) - lowest(3)"), Some(12f64 - 7f64));
+        assert!((e("change(1) * 9").unwrap() - 2f64).abs() < 1e-9);
+        assert_eq!(e("-(close / 2)"), Some(-5.5));
+        assert_eq!(e("close / 0"), None);
+        assert_eq!(e("close > 20 or close == 11"), Some(1f64));
+        assert_eq!(e("sma(10) > 0"), None);
+        // short circuit, the missing sma doesn't matter
+        assert_eq!(e("close > 20 and sma(10) > 0"), Some(0f64));
+    }
+}
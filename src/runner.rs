@@ -0,0 +1,104 @@
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+use serde_json::Value;
+
+use crate::{
+    exchange::API_ERRORS,
+    metrics,
+    notify::{self, EventKind},
+    scheduler::Schedule,
+    signal, state,
+    traits::Strategy,
+};
+
+const DAY_SECS: i64 = 86400;
+
+fn save_state(robot: &dyn Strategy, path: &str) {
+    let state = robot.state();
+    if state.is_null() {
+        return;
+    }
+    if let Err(err) = state::save(path, &state) {
+        warn!("save state error: {:?}", err);
+    }
+}
+
+fn record_tick(start: DateTime<Utc>, ok: bool) {
+    let now = Utc::now();
+    metrics::set_gauge(
+        "rsquant_tick_duration_seconds",
+        "Duration of the last tick",
+        &[],
+        (now - start).num_milliseconds() as f64 / 1000f64,
+    );
+    metrics::inc_counter("rsquant_ticks_total", "Ticks run", &[]);
+    // a tick without any api error counts as successful
+    if ok {
+        metrics::set_gauge(
+            "rsquant_last_success_timestamp_seconds",
+            "Unix time of the last tick without api errors",
+            &[],
+            now.timestamp() as f64,
+        );
+    }
+}
+
+// tick the robot until SIGINT/SIGTERM, then flush its state
+pub fn run(robot: &mut dyn Strategy, config: &Value) {
+    signal::install();
+    if let Err(err) = notify::init(&config["notify"]) {
+        warn!("notify config error: {}", err);
+    }
+    if let Some(addr) = config["metrics"]["addr"].as_str() {
+        if let Err(err) = metrics::serve(addr) {
+            warn!("metrics server error: {:?}", err);
+        }
+    }
+    let schedule = match Schedule::from_config(&config["schedule"]) {
+        Ok(schedule) => schedule,
+        Err(err) => {
+            warn!("schedule config error: {}, tick every 60s", err);
+            Schedule::default()
+        }
+    };
+    info!("schedule: {:?}", schedule);
+    let state_path = state::path(config);
+    robot.init();
+    if let Some(state) = state::load(&state_path) {
+        info!("restore state from {}", state_path);
+        robot.restore(&state);
+    }
+
+    if !config["schedule"]["immediate"].as_bool().unwrap_or(true) {
+        let now = Utc::now();
+        let next = schedule.next(now, now);
+        debug!("first tick at {}", next);
+        signal::sleep((next - now).to_std().unwrap_or_default());
+    }
+    let mut day = Utc::now().timestamp() / DAY_SECS;
+    while !signal::requested() {
+        let start = Utc::now();
+        let errors = metrics::total(API_ERRORS);
+        robot.on_tick();
+        save_state(robot, &state_path);
+        record_tick(start, metrics::total(API_ERRORS) == errors);
+        if start.timestamp() / DAY_SECS != day {
+            day = start.timestamp() / DAY_SECS;
+            notify::send(EventKind::Summary, "daily summary", &robot.summary());
+        }
+
+        let now = Utc::now();
+        let next = schedule.next(start, now);
+        debug!("tick took {}ms, next tick at {}", (now - start).num_milliseconds(), next);
+        signal::sleep((next - now).to_std().unwrap_or_default());
+    }
+
+    info!("{} shutting down", robot.name());
+    if config["cancel_on_exit"].as_bool().unwrap_or(false) {
+        robot.cancel_orders();
+    }
+    save_state(robot, &state_path);
+    info!("summary: {}", robot.summary());
+    notify::send(EventKind::Summary, "shutdown summary", &robot.summary());
+    notify::flush();
+}
@@ -0,0 +1,295 @@
+use chrono::{DateTime, Datelike, Duration, TimeZone, Timelike, Utc};
+use serde_json::Value;
+
+use crate::utils::period_ms;
+
+// "schedule" config of a strategy:
+//   {"type": "fixed", "interval": 60, "align": true}
+//   {"type": "bar_close", "period": "1h", "delay": 5}
+//   {"type": "cron", "expr": "*/15 * * * *"}
+// fixed ticks are measured from the start of the previous tick so the
+// tick duration doesn't accumulate, aligned ones fire on multiples of the
+// interval since the epoch
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    Fixed { interval: i64, align: bool },
+    BarClose { period: i64, delay: i64 },
+    Cron(Cron),
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        Schedule::Fixed {
+            interval: 60_000,
+            align: false,
+        }
+    }
+}
+
+impl Schedule {
+    pub fn from_config(config: &Value) -> Result<Self, String> {
+        if config.is_null() {
+            return Ok(Schedule::default());
+        }
+        match config["type"].as_str().unwrap_or("fixed") {
+            "fixed" => {
+                let interval = config["interval"].as_f64().unwrap_or(60f64);
+                // under a millisecond truncates to a zero step
+                let ms = (interval * 1000f64) as i64;
+                if ms <= 0 {
+                    return Err(format!("invalid interval: {}", interval));
+                }
+                Ok(Schedule::Fixed {
+                    interval: ms,
+                    align: config["align"].as_bool().unwrap_or(false),
+                })
+            }
+            "bar_close" => {
+                let period = config["period"].as_str().unwrap_or("1h");
+                let period = match period_ms(period) {
+                    Some(ms) if ms > 0 => ms as i64,
+                    _ => return Err(format!("invalid period: {}", period)),
+                };
+                let delay = config["delay"].as_f64().unwrap_or(0f64);
+                Ok(Schedule::BarClose {
+                    period: period,
+                    delay: (delay * 1000f64) as i64,
+                })
+            }
+            "cron" => {
+                let expr = config["expr"].as_str().unwrap_or("");
+                Ok(Schedule::Cron(Cron::parse(expr)?))
+            }
+            other => Err(format!("unknown schedule type: {}", other)),
+        }
+    }
+
+    // next tick after a tick that started at `start`, missed ticks are
+    // skipped rather than fired in a burst
+    pub fn next(&self, start: DateTime<Utc>, now: DateTime<Utc>) -> DateTime<Utc> {
+        if let Schedule::Fixed {
+            interval,
+            align: false,
+        } = self
+        {
+            return (start + Duration::milliseconds(*interval)).max(now);
+        }
+        let next = self.after(start);
+        if next >= now {
+            next
+        } else {
+            self.after(now)
+        }
+    }
+
+    // first fire time strictly after `t`
+    fn after(&self, t: DateTime<Utc>) -> DateTime<Utc> {
+        match self {
+            Schedule::Fixed { interval, .. } => ceil_ms(t, *interval, 0),
+            Schedule::BarClose { period, delay } => ceil_ms(t, *period, *delay),
+            Schedule::Cron(cron) => cron.after(t),
+        }
+    }
+}
+
+fn ceil_ms(t: DateTime<Utc>, step: i64, offset: i64) -> DateTime<Utc> {
+    let ms = t.timestamp_millis() - offset;
+    let next = (ms.div_euclid(step) + 1) * step + offset;
+    Utc.timestamp_millis_opt(next).unwrap()
+}
+
+// minute hour day-of-month month day-of-week, each field supports
+// "*", "5", "1-5", "*/15", "1-30/5" and comma separated lists
+#[derive(Debug, Clone)]
+pub struct Cron {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days: Vec<bool>,
+    months: Vec<bool>,
+    weekdays: Vec<bool>,
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Cron {
+    pub fn parse(expr: &str) -> Result<Self, String> {
+        let fields: Vec<&str> = expr.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(format!("cron expr expects 5 fields: {:?}", expr));
+        }
+        let mut weekdays = parse_field(fields[4], 0, 7)?;
+        // 7 is sunday as well
+        if weekdays[7] {
+            weekdays[0] = true;
+        }
+        Ok(Cron {
+            minutes: parse_field(fields[0], 0, 59)?,
+            hours: parse_field(fields[1], 0, 23)?,
+            days: parse_field(fields[2], 1, 31)?,
+            months: parse_field(fields[3], 1, 12)?,
+            weekdays: weekdays,
+            any_day: fields[2] == "*",
+            any_weekday: fields[4] == "*",
+        })
+    }
+
+    // like cron, a restricted day-of-month or day-of-week matches if
+    // either matches
+    fn day_matches(&self, t: &DateTime<Utc>) -> bool {
+        if !self.months[t.month() as usize] {
+            return false;
+        }
+        let day = self.days[t.day() as usize];
+        let weekday = self.weekdays[t.weekday().num_days_from_sunday() as usize];
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday,
+            (false, true) => day,
+            (false, false) => day || weekday,
+        }
+    }
+
+    pub fn after(&self, t: DateTime<Utc>) -> DateTime<Utc> {
+        let mut t = Utc.timestamp_opt(t.timestamp() / 60 * 60 + 60, 0).unwrap();
+        // every schedule fires at least once in 4 years
+        let limit = t + Duration::days(366 * 4 + 1);
+        while t < limit {
+            if !self.day_matches(&t) {
+                t = Utc
+                    .timestamp_opt(t.timestamp() / 86400 * 86400 + 86400, 0)
+                    .unwrap();
+                continue;
+            }
+            if !self.hours[t.hour() as usize] {
+                t = t.with_minute(0).unwrap() + Duration::hours(1);
+                continue;
+            }
+            if !self.minutes[t.minute() as usize] {
+                t += Duration::minutes(1);
+                continue;
+            }
+            return t;
+        }
+        limit
+    }
+}
+
+fn parse_field(field: &str, min: usize, max: usize) -> Result<Vec<bool>, String> {
+    let mut ret = vec![false; max + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.find('/') {
+            Some(i) => {
+                let step: usize = part[i + 1..]
+                    .parse()
+                    .map_err(|_| format!("invalid step: {:?}", part))?;
+                (&part[..i], step)
+            }
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(format!("invalid step: {:?}", part));
+        }
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some(i) = range.find('-') {
+            let start = parse_value(&range[..i], min, max)?;
+            let end = parse_value(&range[i + 1..], min, max)?;
+            (start, end)
+        } else {
+            let start = parse_value(range, min, max)?;
+            // "5/15" means from 5 to the end every 15
+            (start, if step > 1 { max } else { start })
+        };
+        if start > end {
+            return Err(format!("invalid range: {:?}", part));
+        }
+        for i in (start..=end).step_by(step) {
+            ret[i] = true;
+        }
+    }
+    Ok(ret)
+}
+
+fn parse_value(value: &str, min: usize, max: usize) -> Result<usize, String> {
+    match value.parse::<usize>() {
+        Ok(v) if v >= min && v <= max => Ok(v),
+        _ => Err(format!("invalid value: {:?}, expect {}-{}", value, min, max)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn at(y: i32, m: u32, d: u32, h: u32, min: u32, s: u32) -> DateTime<Utc> {
+        format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}Z", y, m, d, h, min, s)
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_fixed() {
+        let schedule = Schedule::from_config(&json!({"type": "fixed", "interval": 60})).unwrap();
+        let start = at(2020, 6, 1, 10, 0, 30);
+        // tick took 10s, next one is 50s later
+        assert_eq!(
+            schedule.next(start, at(2020, 6, 1, 10, 0, 40)),
+            at(2020, 6, 1, 10, 1, 30)
+        );
+        // tick overran the interval, fire right away
+        let now = at(2020, 6, 1, 10, 2, 0);
+        assert_eq!(schedule.next(start, now), now);
+
+        let aligned = Schedule::from_config(&json!({"interval": 300, "align": true})).unwrap();
+        assert_eq!(
+            aligned.next(start, at(2020, 6, 1, 10, 0, 40)),
+            at(2020, 6, 1, 10, 5, 0)
+        );
+        assert_eq!(
+            aligned.next(start, at(2020, 6, 1, 10, 7, 0)),
+            at(2020, 6, 1, 10, 10, 0)
+        );
+
+        // a step under a millisecond would never advance
+        assert!(Schedule::from_config(&json!({"interval": 0.0001, "align": true})).is_err());
+        assert!(Schedule::from_config(&json!({"interval": 0})).is_err());
+    }
+
+    #[test]
+    fn test_bar_close() {
+        let schedule =
+            Schedule::from_config(&json!({"type": "bar_close", "period": "1h", "delay": 5}))
+                .unwrap();
+        let start = at(2020, 6, 1, 10, 0, 5);
+        assert_eq!(
+            schedule.next(start, at(2020, 6, 1, 10, 0, 20)),
+            at(2020, 6, 1, 11, 0, 5)
+        );
+        assert_eq!(
+            schedule.next(at(2020, 6, 1, 9, 59, 0), at(2020, 6, 1, 9, 59, 1)),
+            at(2020, 6, 1, 10, 0, 5)
+        );
+        assert!(Schedule::from_config(&json!({"type": "bar_close", "period": "0h"})).is_err());
+    }
+
+    #[test]
+    fn test_cron() {
+        let cron = Cron::parse("*/15 * * * *").unwrap();
+        assert_eq!(cron.after(at(2020, 6, 1, 10, 7, 0)), at(2020, 6, 1, 10, 15, 0));
+        assert_eq!(cron.after(at(2020, 6, 1, 10, 15, 0)), at(2020, 6, 1, 10, 30, 0));
+        assert_eq!(cron.after(at(2020, 6, 1, 23, 59, 0)), at(2020, 6, 2, 0, 0, 0));
+
+        // 2020-06-01 is a monday
+        let cron = Cron::parse("30 8 * * 1-5").unwrap();
+        assert_eq!(cron.after(at(2020, 6, 1, 9, 0, 0)), at(2020, 6, 2, 8, 30, 0));
+        assert_eq!(cron.after(at(2020, 6, 5, 9, 0, 0)), at(2020, 6, 8, 8, 30, 0));
+
+        let cron = Cron::parse("0 0 1 1,7 *").unwrap();
+        assert_eq!(cron.after(at(2020, 6, 1, 0, 0, 0)), at(2020, 7, 1, 0, 0, 0));
+
+        assert!(Cron::parse("* * *").is_err());
+        assert!(Cron::parse("60 * * * *").is_err());
+        assert!(Cron::parse("*/0 * * * *").is_err());
+    }
+}
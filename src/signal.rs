@@ -0,0 +1,50 @@
+use log::{info, warn};
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Once,
+    },
+    thread, time,
+};
+
+static SHUTDOWN: AtomicBool = AtomicBool::new(false);
+static INSTALL: Once = Once::new();
+
+// first SIGINT/SIGTERM asks the robot to stop after the current tick,
+// a second one exits immediately
+pub fn install() {
+    INSTALL.call_once(|| {
+        let ret = ctrlc::set_handler(|| {
+            if SHUTDOWN.swap(true, Ordering::SeqCst) {
+                warn!("second signal received, exit now");
+                process::exit(130);
+            }
+            info!("signal received, stopping after the current tick");
+        });
+        if let Err(err) = ret {
+            warn!("install signal handler error: {:?}", err);
+        }
+    });
+}
+
+pub fn request() {
+    SHUTDOWN.store(true, Ordering::SeqCst);
+}
+
+pub fn requested() -> bool {
+    SHUTDOWN.load(Ordering::SeqCst)
+}
+
+// sleep for `duration`, waking up early when a shutdown is requested
+pub fn sleep(duration: time::Duration) {
+    let step = time::Duration::from_millis(200);
+    let start = time::Instant::now();
+    while !requested() {
+        let elapsed = start.elapsed();
+        if elapsed >= duration {
+            return;
+        }
+        thread::sleep(step.min(duration - elapsed));
+    }
+}
@@ -0,0 +1,21 @@
+use serde_json::Value;
+use std::{fs, io};
+
+pub fn path(config: &Value) -> String {
+    config["state_file"]
+        .as_str()
+        .unwrap_or("./state.json")
+        .into()
+}
+
+pub fn load(path: &str) -> Option<Value> {
+    let file = fs::File::open(path).ok()?;
+    serde_json::from_reader(file).ok()
+}
+
+// write to a temporary file first so a crash never leaves a truncated state
+pub fn save(path: &str, state: &Value) -> io::Result<()> {
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, serde_json::to_string_pretty(state)?)?;
+    fs::rename(&tmp, path)
+}
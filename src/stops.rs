@@ -0,0 +1,173 @@
+use serde_json::Value;
+
+use crate::{
+    trailing::{chandelier, Trail},
+    utils::period_ms,
+};
+
+// stops that sit beside the trailing withdraw curve of move_stoploss
+
+// "atr_stop" config:
+//   {"period": "1h", "length": 14, "stop": 2, "trail": 3, "max_loss": 0.15}
+// the stop sits `stop` ATRs below the entry price (at most `max_loss`),
+// the trailing exit `trail` ATRs below the high. Either falls back to the
+// fixed stoploss / trailing curve when there are no klines
+#[derive(Debug, Clone)]
+pub struct AtrStop {
+    pub period: String,
+    pub length: usize,
+    stop: Option<f64>,
+    trail: Option<f64>,
+    max_loss: Option<f64>,
+}
+
+impl AtrStop {
+    pub fn from_config(config: &Value) -> Result<Option<Self>, String> {
+        if config.is_null() {
+            return Ok(None);
+        }
+        let period = config["period"].as_str().unwrap_or("1h");
+        if period_ms(period).is_none() {
+            return Err(format!("atr_stop.period: invalid period {:?}", period));
+        }
+        let stop = config["stop"].as_f64();
+        let trail = config["trail"].as_f64();
+        if stop.is_none() && trail.is_none() {
+            return Err("atr_stop: expect stop or trail".into());
+        }
+        if stop.into_iter().chain(trail).any(|m| m <= 0f64) {
+            return Err("atr_stop: multiples should be positive".into());
+        }
+        Ok(Some(AtrStop {
+            period: period.into(),
+            length: config["length"].as_u64().unwrap_or(14) as usize,
+            stop: stop,
+            trail: trail,
+            max_loss: config["max_loss"].as_f64(),
+        }))
+    }
+
+    // stoploss ratio, negative like the fixed one
+    pub fn stoploss(&self, atr: Option<f64>, stoploss: f64) -> f64 {
+        match (self.stop, atr) {
+            (Some(stop), Some(atr)) => {
+                let loss = stop * atr;
+                -self.max_loss.map(|max| loss.min(max)).unwrap_or(loss)
+            }
+            _ => stoploss,
+        }
+    }
+
+    pub fn withdraw_ratio(&self, t: &Trail) -> Option<f64> {
+        match (self.trail, t.atr) {
+            (Some(trail), Some(atr)) => Some(chandelier(t.high, atr, trail)),
+            _ => None,
+        }
+    }
+}
+
+// "breakeven" config: {"after": 0.1, "offset": 0.002}
+// once the high was `after` above the entry, the stop moves up to
+// `offset` above it, enough to cover the fees
+#[derive(Debug, Clone)]
+pub struct Breakeven {
+    pub after: f64,
+    pub offset: f64,
+}
+
+impl Breakeven {
+    pub fn from_config(config: &Value) -> Result<Option<Self>, String> {
+        if config.is_null() {
+            return Ok(None);
+        }
+        let after = match config["after"].as_f64() {
+            Some(after) if after > 0f64 => after,
+            _ => return Err("breakeven.after: expect a positive ratio".into()),
+        };
+        let offset = config["offset"].as_f64().unwrap_or(0.002);
+        if offset >= after {
+            return Err("breakeven.offset should be below after".into());
+        }
+        Ok(Some(Breakeven {
+            after: after,
+            offset: offset,
+        }))
+    }
+}
+
+// "time_stop" config: {"after": "72h", "target": 0.05}
+// exit positions held `after` whose high never reached `target`
+#[derive(Debug, Clone)]
+pub struct TimeStop {
+    pub after: u64,
+    pub target: f64,
+}
+
+impl TimeStop {
+    pub fn from_config(config: &Value) -> Result<Option<Self>, String> {
+        if config.is_null() {
+            return Ok(None);
+        }
+        let after = config["after"].as_str().unwrap_or("");
+        let after = match period_ms(after) {
+            Some(after) if after > 0 => after,
+            _ => return Err(format!("time_stop.after: invalid duration {:?}", after)),
+        };
+        Ok(Some(TimeStop {
+            after: after,
+            target: config["target"].as_f64().unwrap_or(0f64),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_atr_stop() {
+        assert!(AtrStop::from_config(&Value::Null).unwrap().is_none());
+        assert!(AtrStop::from_config(&json!({"period": "1h"})).is_err());
+        assert!(AtrStop::from_config(&json!({"stop": -1})).is_err());
+
+        let atr_stop = AtrStop::from_config(&json!({"stop": 2, "max_loss": 0.15}))
+            .unwrap()
+            .unwrap();
+        assert_eq!((atr_stop.period.as_str(), atr_stop.length), ("1h", 14));
+        assert!(close(atr_stop.stoploss(Some(0.01), -0.05), -0.02));
+        assert!(close(atr_stop.stoploss(Some(0.1), -0.05), -0.15));
+        assert_eq!(atr_stop.stoploss(None, -0.05), -0.05);
+        let t = Trail {
+            profit: 0.4,
+            high: 0.5,
+            atr: Some(0.05),
+        };
+        assert_eq!(atr_stop.withdraw_ratio(&t), None);
+
+        let atr_stop = AtrStop::from_config(&json!({"trail": 2})).unwrap().unwrap();
+        assert_eq!(atr_stop.stoploss(Some(0.01), -0.05), -0.05);
+        assert!(close(atr_stop.withdraw_ratio(&t).unwrap(), 0.8));
+        assert_eq!(atr_stop.withdraw_ratio(&Trail { atr: None, ..t }), None);
+    }
+
+    #[test]
+    fn test_stop_rules() {
+        assert!(Breakeven::from_config(&Value::Null).unwrap().is_none());
+        let breakeven = Breakeven::from_config(&json!({"after": 0.1}))
+            .unwrap()
+            .unwrap();
+        assert_eq!(breakeven.offset, 0.002);
+        assert!(Breakeven::from_config(&json!({"after": 0.1, "offset": 0.2})).is_err());
+
+        let time_stop = TimeStop::from_config(&json!({"after": "3d", "target": 0.05}))
+            .unwrap()
+            .unwrap();
+        assert_eq!(time_stop.after, 3 * 86_400_000);
+        assert!(TimeStop::from_config(&json!({"after": 72})).is_err());
+    }
+}
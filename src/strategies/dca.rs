@@ -0,0 +1,686 @@
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, fs};
+use rsex::{
+    constant::{ORDER_ACTION_BUY, ORDER_ACTION_SELL, ORDER_TYPE_LIMIT, ORDER_TYPE_MARKET},
+    models::{Order, SymbolInfo},
+};
+use crate::{
+    exchange, metrics,
+    notify::{self, EventKind},
+    runner,
+    strategies::send_order,
+    traits::{Client, Strategy},
+    utils::{check_keys, round_to},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DealOrder {
+    order_id: String,
+    price: f64,
+    amount: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Deal {
+    symbol: String,
+    // base bought so far and what it cost in quote, fees included
+    amount: f64,
+    cost: f64,
+    entry: f64,
+    // safety orders filled
+    safety: usize,
+    safety_orders: Vec<DealOrder>,
+    take_profit: Option<DealOrder>,
+    since: u64,
+}
+
+impl Deal {
+    fn average(&self) -> f64 {
+        self.cost / self.amount
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    symbol: String,
+    buy_price: f64,
+    sell_price: f64,
+    amount: f64,
+    safety: usize,
+    profit: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Plan {
+    pub base_order: f64,
+    pub safety_order: f64,
+    pub deviation: f64,
+    pub step_scale: f64,
+    pub volume_scale: f64,
+    pub max_safety_orders: usize,
+    pub max_capital: f64,
+}
+
+impl Plan {
+    // safety orders below `entry` as (price, quote value): the n-th sits
+    // deviation * (1 + step_scale + .. + step_scale^(n-1)) below and is
+    // volume_scale^(n-1) times the first, as long as the deal stays
+    // within max_capital
+    pub fn safety_orders(&self, entry: f64) -> Vec<(f64, f64)> {
+        let mut orders = vec![];
+        let mut deviation = 0f64;
+        let mut step = self.deviation;
+        let mut value = self.safety_order;
+        let mut capital = self.base_order;
+        for _ in 0..self.max_safety_orders {
+            deviation += step;
+            if deviation >= 1f64 || capital + value > self.max_capital {
+                break;
+            }
+            capital += value;
+            orders.push((entry * (1f64 - deviation), value));
+            step *= self.step_scale;
+            value *= self.volume_scale;
+        }
+        orders
+    }
+}
+
+// a deal per symbol: a market buy of `base_order`, safety buys further
+// down and a take-profit sell over the averaged entry that moves with each
+// safety fill. A closed deal is followed by the next one in the same tick
+#[derive(Debug)]
+pub struct Dca {
+    config: Value,
+    client: Box<dyn Client>,
+    info: HashMap<String, SymbolInfo>,
+    deals: Vec<Deal>,
+    history: Vec<Record>,
+    total_profit: f64,
+
+    symbols: Vec<String>,
+    plan: Plan,
+    take_profit: f64,
+    max_deals: usize,
+    fee: f64,
+}
+
+impl Dca {
+    pub fn validate(config: &Value) -> Vec<String> {
+        let mut errors = check_keys(
+            config,
+            &[],
+            &[
+                "base_order",
+                "safety_order",
+                "deviation",
+                "take_profit",
+                "max_safety_orders",
+            ],
+        );
+        match config["symbols"].as_array() {
+            Some(symbols) if !symbols.is_empty() && symbols.iter().all(|s| s.is_string()) => {}
+            _ => errors.push("symbols: expect a list of symbols".into()),
+        }
+        let deviation = config["deviation"].as_f64().unwrap_or(0f64);
+        if deviation <= 0f64 || deviation >= 1f64 {
+            errors.push("deviation: expect 0 < deviation < 1".into());
+        }
+        if config["take_profit"].as_f64().unwrap_or(0f64) <= 0f64 {
+            errors.push("take_profit: expect > 0".into());
+        }
+        for key in &["step_scale", "volume_scale", "max_capital"] {
+            if !config[key].is_null() && config[key].as_f64().unwrap_or(0f64) <= 0f64 {
+                errors.push(format!("{}: expect > 0", key));
+            }
+        }
+        errors
+    }
+
+    fn round_amount(&self, symbol: &str, amount: f64) -> f64 {
+        match self.info.get(symbol) {
+            Some(info) => round_to(amount, info.amount_precision as u32),
+            None => amount,
+        }
+    }
+
+    fn round_price(&self, symbol: &str, price: f64) -> f64 {
+        match self.info.get(symbol) {
+            Some(info) => round_to(price, info.price_precision as u32),
+            None => price,
+        }
+    }
+
+    fn order(
+        &self,
+        symbol: &str,
+        price: f64,
+        amount: f64,
+        side: &str,
+        order_type: &str,
+    ) -> Option<DealOrder> {
+        let price = self.round_price(symbol, price);
+        let amount = self.round_amount(symbol, amount);
+        let order_id = send_order(
+            &*self.client,
+            "dca",
+            symbol,
+            price,
+            amount,
+            side,
+            order_type,
+        )
+        .ok()?;
+        Some(DealOrder {
+            order_id: order_id,
+            price: price,
+            amount: amount,
+        })
+    }
+
+    fn open_deal(&mut self, symbol: &str, ask: f64, timestamp: u64) {
+        let amount = self.plan.base_order / ask;
+        let base = match self.order(symbol, ask, amount, ORDER_ACTION_BUY, ORDER_TYPE_MARKET) {
+            Some(base) => base,
+            None => return,
+        };
+        // the deal holds what the market buy filled, at the ask when the
+        // exchange reports no price for market orders
+        let (amount, price) = match self.fetch(&base) {
+            Some(order) if order.price > 0f64 => (order.filled, order.price),
+            Some(order) => (order.filled, ask),
+            None => (base.amount, ask),
+        };
+        if amount <= 0f64 {
+            warn!("dca {} base order {} didn't fill", symbol, base.order_id);
+            return;
+        }
+        let mut deal = Deal {
+            symbol: symbol.into(),
+            amount: amount,
+            cost: price * amount * (1f64 + self.fee),
+            entry: price,
+            safety: 0,
+            safety_orders: vec![],
+            take_profit: None,
+            since: timestamp,
+        };
+        self.place_safety_orders(&mut deal);
+        notify::send(
+            EventKind::Fill,
+            &format!("{} dca deal opened", symbol),
+            &format!(
+                "buy {} at {}, {} safety orders",
+                deal.amount,
+                ask,
+                deal.safety_orders.len()
+            ),
+        );
+        self.place_take_profit(&mut deal);
+        self.deals.push(deal);
+    }
+
+    // the safety orders not filled yet
+    fn place_safety_orders(&self, deal: &mut Deal) {
+        for (price, value) in self
+            .plan
+            .safety_orders(deal.entry)
+            .into_iter()
+            .skip(deal.safety)
+        {
+            let order = self.order(
+                &deal.symbol,
+                price,
+                value / price,
+                ORDER_ACTION_BUY,
+                ORDER_TYPE_LIMIT,
+            );
+            if let Some(order) = order {
+                deal.safety_orders.push(order);
+            }
+        }
+    }
+
+    fn place_take_profit(&mut self, deal: &mut Deal) {
+        if let Some(order) = deal.take_profit.take() {
+            if let Err(err) = self.client.cancel(&order.order_id) {
+                warn!("cancel take profit {} error: {:?}", order.order_id, err);
+            }
+            // what sold before the cancel is booked, the rest of the deal
+            // keeps its average
+            match self.client.get_order(&order.order_id) {
+                Ok(sold) if sold.filled > 0f64 => {
+                    info!(
+                        "dca {} take profit sold {} before replacing",
+                        deal.symbol, sold.filled
+                    );
+                    self.book_sale(deal, order.price, sold.filled.min(deal.amount), false);
+                }
+                Ok(_) => {}
+                Err(err) => warn!("get_order {} error: {:?}", order.order_id, err),
+            }
+        }
+        // exchanges charging the buy fee in base leave less than was
+        // bought, never sell more than is there
+        let mut amount = deal.amount;
+        if let Some(info) = self.info.get(&deal.symbol) {
+            match self.client.get_balance(&info.base) {
+                Ok(balance) => amount = amount.min(balance.free),
+                Err(err) => warn!("get_balance {} error: {:?}", info.base, err),
+            }
+        }
+        if self.round_amount(&deal.symbol, amount) <= 0f64 {
+            return;
+        }
+        // the sell fee comes out of the proceeds
+        let price = deal.average() * (1f64 + self.take_profit) / (1f64 - self.fee);
+        deal.take_profit = self.order(
+            &deal.symbol,
+            price,
+            amount,
+            ORDER_ACTION_SELL,
+            ORDER_TYPE_LIMIT,
+        );
+    }
+
+    // book `amount` sold at `price` against its share of the cost, all of
+    // the cost left once the deal closes
+    fn book_sale(&mut self, deal: &mut Deal, price: f64, amount: f64, close: bool) -> f64 {
+        let cost = if close {
+            deal.cost
+        } else {
+            deal.cost * (amount / deal.amount).min(1f64)
+        };
+        let profit = price * amount * (1f64 - self.fee) - cost;
+        self.total_profit += profit;
+        metrics::set_gauge(
+            "rsquant_total_profit",
+            "Realised profit",
+            &[],
+            self.total_profit,
+        );
+        self.history.push(Record {
+            symbol: deal.symbol.clone(),
+            buy_price: deal.average(),
+            sell_price: price,
+            amount: amount,
+            safety: deal.safety,
+            profit: profit,
+        });
+        deal.amount -= amount;
+        deal.cost -= cost;
+        profit
+    }
+
+    fn fetch(&self, order: &DealOrder) -> Option<Order> {
+        match self.client.get_order(&order.order_id) {
+            Ok(order) => Some(order),
+            Err(err) => {
+                warn!("get_order {} error: {:?}", order.order_id, err);
+                None
+            }
+        }
+    }
+
+    // true when the deal closed
+    fn check_deal(&mut self, deal: &mut Deal) -> bool {
+        // orders cancelled on exit are placed again
+        if deal.take_profit.is_none() && deal.safety_orders.is_empty() {
+            self.place_safety_orders(deal);
+        }
+        let mut replace = deal.take_profit.is_none();
+        if let Some(order) = deal.take_profit.clone() {
+            match self.fetch(&order).map(|order| order.status).as_deref() {
+                Some("FILLED") => {
+                    if self.close_deal(deal, &order) {
+                        return true;
+                    }
+                }
+                Some("NEW") | Some("PARTIALLY_FILLED") | None => {}
+                Some(status) => {
+                    warn!("dca take profit {} {}, replacing", order.order_id, status);
+                    replace = true;
+                }
+            }
+        }
+        let mut filled = false;
+        for order in deal.safety_orders.clone() {
+            let fetched = self.fetch(&order);
+            match fetched.as_ref().map(|order| order.status.as_str()) {
+                Some("FILLED") => {
+                    self.book_buy(deal, order.price, order.amount);
+                    deal.safety += 1;
+                    filled = true;
+                    info!(
+                        "dca {} safety order {} filled at {}, average {}",
+                        deal.symbol,
+                        deal.safety,
+                        order.price,
+                        deal.average()
+                    );
+                }
+                Some("NEW") | Some("PARTIALLY_FILLED") | None => continue,
+                Some(status) => {
+                    warn!("dca safety order {} {}, dropped", order.order_id, status);
+                    // what filled before it was cancelled still counts
+                    let bought = fetched.as_ref().map(|order| order.filled).unwrap_or(0f64);
+                    if bought > 0f64 {
+                        self.book_buy(deal, order.price, bought);
+                        filled = true;
+                    }
+                }
+            }
+            deal.safety_orders.retain(|o| o.order_id != order.order_id);
+        }
+        if filled || replace {
+            self.place_take_profit(deal);
+        }
+        // sold out by take-profits replaced after a partial fill
+        if deal.take_profit.is_none() && self.round_amount(&deal.symbol, deal.amount) <= 0f64 {
+            self.cancel_safety_orders(deal);
+            if self.round_amount(&deal.symbol, deal.amount) <= 0f64 {
+                info!("dca {} deal sold out", deal.symbol);
+                return true;
+            }
+            self.place_take_profit(deal);
+        }
+        false
+    }
+
+    fn book_buy(&self, deal: &mut Deal, price: f64, amount: f64) {
+        deal.amount += amount;
+        deal.cost += price * amount * (1f64 + self.fee);
+    }
+
+    // cancel the safety orders left, what they bought so far joins the deal
+    fn cancel_safety_orders(&self, deal: &mut Deal) {
+        for safety in std::mem::take(&mut deal.safety_orders) {
+            if let Err(err) = self.client.cancel(&safety.order_id) {
+                warn!("cancel safety order {} error: {:?}", safety.order_id, err);
+            }
+            match self.fetch(&safety) {
+                Some(order) if order.filled > 0f64 => {
+                    info!(
+                        "dca {} safety order bought {} before cancel",
+                        deal.symbol, order.filled
+                    );
+                    self.book_buy(deal, safety.price, order.filled);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // true when nothing is left of the deal, a safety order that partly
+    // filled leaves base over for a new take-profit
+    fn close_deal(&mut self, deal: &mut Deal, order: &DealOrder) -> bool {
+        deal.take_profit = None;
+        let amount = deal.amount;
+        self.cancel_safety_orders(deal);
+        let close = deal.amount <= amount;
+        let profit = self.book_sale(deal, order.price, order.amount, close);
+        info!(
+            "dca {} deal closed at {} after {} safety orders, profit: {}",
+            deal.symbol, order.price, deal.safety, profit
+        );
+        notify::send(
+            EventKind::Fill,
+            &format!("{} dca deal closed", deal.symbol),
+            &format!(
+                "sold {} at {}, profit {}",
+                order.amount, order.price, profit
+            ),
+        );
+        if !close {
+            self.place_take_profit(deal);
+        }
+        close
+    }
+}
+
+impl Strategy for Dca {
+    fn new(config_path: &str) -> Box<dyn Strategy> {
+        let file = fs::File::open(config_path).expect("file should open read only");
+        let config: Value = serde_json::from_reader(file).expect("file should be proper json");
+        let client = exchange::binance(&config);
+        Self::from_config(&config, Box::new(client))
+    }
+
+    fn from_config(config: &Value, client: Box<dyn Client>) -> Box<dyn Strategy> {
+        let symbols: Vec<String> = config["symbols"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter_map(|s| s.as_str())
+            .map(|s| s.to_uppercase())
+            .collect();
+        let base_order = config["base_order"].as_f64().unwrap();
+        let safety_order = config["safety_order"].as_f64().unwrap();
+        let max_safety_orders = config["max_safety_orders"].as_u64().unwrap() as usize;
+        let plan = Plan {
+            base_order: base_order,
+            safety_order: safety_order,
+            deviation: config["deviation"].as_f64().unwrap(),
+            step_scale: config["step_scale"].as_f64().unwrap_or(1f64),
+            volume_scale: config["volume_scale"].as_f64().unwrap_or(1f64),
+            max_safety_orders: max_safety_orders,
+            max_capital: config["max_capital"].as_f64().unwrap_or(f64::MAX),
+        };
+        Box::new(Dca {
+            config: config.clone(),
+            client: client,
+            info: HashMap::new(),
+            deals: vec![],
+            history: vec![],
+            total_profit: 0f64,
+
+            max_deals: config["max_deals"]
+                .as_u64()
+                .map(|n| n as usize)
+                .unwrap_or(symbols.len()),
+            symbols: symbols,
+            plan: plan,
+            take_profit: config["take_profit"].as_f64().unwrap(),
+            fee: config["fee"].as_f64().unwrap_or(0.001),
+        })
+    }
+
+    fn init(&mut self) {
+        match self.client.get_symbols() {
+            Ok(symbols) => {
+                for info in symbols {
+                    if self.symbols.contains(&info.symbol) {
+                        self.info.insert(info.symbol.clone(), info);
+                    }
+                }
+            }
+            Err(err) => warn!("get_symbols error: {:?}", err),
+        }
+        for symbol in &self.symbols {
+            if !self.info.contains_key(symbol) {
+                warn!("dca symbol {} not found", symbol);
+            }
+        }
+    }
+
+    fn on_tick(&mut self) {
+        let mut deals = std::mem::take(&mut self.deals);
+        deals.retain_mut(|deal| !self.check_deal(deal));
+        self.deals.extend(deals);
+
+        for symbol in self.symbols.clone() {
+            if self.deals.len() >= self.max_deals {
+                break;
+            }
+            if self.deals.iter().any(|deal| deal.symbol == symbol) {
+                continue;
+            }
+            match self.client.get_ticker(&symbol) {
+                Ok(ticker) => self.open_deal(&symbol, ticker.ask.price, ticker.timestamp),
+                Err(err) => {
+                    warn!("get_ticker {} error: {:?}", symbol, err);
+                    notify::send(EventKind::Error, "get_ticker error", &format!("{:?}", err));
+                }
+            }
+        }
+    }
+
+    fn run_forever(&mut self) {
+        let config = self.config.clone();
+        runner::run(self, &config);
+    }
+
+    fn name(&self) -> String {
+        "dca".into()
+    }
+
+    fn stringify(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn state(&self) -> Value {
+        json!({
+            "strategy": self.name(),
+            "deals": self.deals,
+            "history": self.history,
+            "total_profit": self.total_profit,
+        })
+    }
+
+    fn restore(&mut self, state: &Value) {
+        if state["strategy"] != "dca" {
+            return;
+        }
+        if let Ok(deals) = serde_json::from_value(state["deals"].clone()) {
+            self.deals = deals;
+        }
+        if let Ok(history) = serde_json::from_value(state["history"].clone()) {
+            self.history = history;
+        }
+        self.total_profit = state["total_profit"].as_f64().unwrap_or(0f64);
+    }
+
+    fn cancel_orders(&mut self) {
+        for deal in &self.deals {
+            for order in deal.safety_orders.iter().chain(deal.take_profit.iter()) {
+                if let Err(err) = self.client.cancel(&order.order_id) {
+                    warn!("cancel {} error: {:?}", order.order_id, err);
+                }
+            }
+        }
+        // placed again on the next start
+        for deal in &mut self.deals {
+            deal.safety_orders.clear();
+            deal.take_profit = None;
+        }
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "open deals: {}, closed deals: {}, total_profit: {}",
+            self.deals.len(),
+            self.history.len(),
+            self.total_profit
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backtest::data::{
+        fixture::{bars, replay, sim},
+        symbol_info,
+    };
+    use rsex::traits::SpotRest;
+
+    #[test]
+    fn test_plan() {
+        let plan = Plan {
+            base_order: 10f64,
+            safety_order: 10f64,
+            deviation: 0.02,
+            step_scale: 2f64,
+            volume_scale: 2f64,
+            max_safety_orders: 5,
+            max_capital: 80f64,
+        };
+        let orders = plan.safety_orders(100f64);
+        // 10 + 10 + 20 + 40 = 80, the next 80 is over the cap
+        assert_eq!(orders.len(), 3);
+        let prices: Vec<f64> = orders.iter().map(|o| (o.0 * 1e6).round() / 1e6).collect();
+        assert_eq!(prices, vec![98f64, 94f64, 86f64]);
+        assert_eq!(
+            orders.iter().map(|o| o.1).collect::<Vec<_>>(),
+            vec![10f64, 20f64, 40f64]
+        );
+    }
+
+    #[test]
+    fn test_dca() {
+        let closes = [100f64, 100f64, 93f64, 93f64, 100f64, 100f64];
+        let data = vec![(symbol_info("ETH", "USDT"), bars(&closes, 0, 60_000))];
+        let exchange = sim(data, &[("USDT", 1000f64)], 0f64);
+        let config = json!({
+            "symbols": ["ethusdt"],
+            "base_order": 10,
+            "safety_order": 10,
+            "deviation": 0.05,
+            "volume_scale": 2,
+            "max_safety_orders": 3,
+            "take_profit": 0.02,
+            "fee": 0,
+        });
+        assert!(Dca::validate(&config).is_empty());
+        let mut robot = Dca::from_config(&config, Box::new(exchange.clone()));
+        replay(&[&exchange], &mut *robot);
+
+        // 0.1 at 100 and 10 / 95 at 95, sold 2% over the average
+        let state = robot.state();
+        let history = state["history"].as_array().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["safety"], 1);
+        let amount = 0.1 + 10f64 / 95f64;
+        let profit = 20f64 * 0.02;
+        assert!((history[0]["amount"].as_f64().unwrap() - amount).abs() < 1e-6);
+        assert!((state["total_profit"].as_f64().unwrap() - profit).abs() < 1e-4);
+        // the next deal opened right after with another base order
+        assert_eq!(state["deals"].as_array().unwrap().len(), 1);
+        let usdt = exchange.get_balance("USDT").unwrap();
+        assert!((usdt.free + usdt.locked - (1000f64 + profit - 10f64)).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_base_fee() {
+        let data = vec![(
+            symbol_info("ETH", "USDT"),
+            bars(&[100f64, 103f64], 0, 60_000),
+        )];
+        // bought 0.1, the exchange kept 0.0005 as the fee
+        let exchange = sim(data, &[("ETH", 0.0995), ("USDT", 1000f64)], 0f64);
+        let config = json!({
+            "symbols": ["ETHUSDT"],
+            "base_order": 10,
+            "safety_order": 10,
+            "deviation": 0.05,
+            "max_safety_orders": 1,
+            "take_profit": 0.02,
+            "fee": 0,
+            "max_deals": 1,
+        });
+        let mut robot = Dca::from_config(&config, Box::new(exchange.clone()));
+        robot.restore(&json!({"strategy": "dca", "deals": [{"symbol": "ETHUSDT", "amount": 0.1,
+            "cost": 10, "entry": 100, "safety": 0, "safety_orders": [], "take_profit": null,
+            "since": 0}]}));
+        replay(&[&exchange], &mut *robot);
+
+        // the take-profit sold what was there
+        let state = robot.state();
+        let history = state["history"].as_array().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["amount"], 0.0995);
+        assert_eq!(exchange.get_balance("ETH").unwrap().free, 0f64);
+    }
+}
@@ -1,5 +1,6 @@
-use crate::traits::Strategy;
+use crate::traits::{Client, Strategy};
 use log::warn;
+use serde_json::Value;
 
 #[derive(Debug)]
 pub struct Dummy {}
@@ -9,6 +10,14 @@ impl Strategy for Dummy {
         Box::new(Dummy {})
     }
 
+    fn from_config(_: &Value, _: Box<dyn Client>) -> Box<dyn Strategy> {
+        Box::new(Dummy {})
+    }
+
+    fn init(&mut self) {}
+
+    fn on_tick(&mut self) {}
+
     fn run_forever(&mut self) {
         warn!("dummy!");
     }
@@ -0,0 +1,571 @@
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use rsex::{
+    constant::{ORDER_ACTION_BUY, ORDER_ACTION_SELL, ORDER_TYPE_LIMIT, ORDER_TYPE_MARKET},
+    models::SymbolInfo,
+};
+use crate::{
+    exchange, metrics,
+    notify::{self, EventKind},
+    runner,
+    strategies::send_order,
+    traits::{Client, Strategy},
+    utils::{check_keys, round_to},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct GridOrder {
+    level: usize,
+    side: String,
+    order_id: String,
+    // placed after the opposite order filled, its fill closes a round trip
+    paired: bool,
+    // failed lookups in a row
+    #[serde(default)]
+    misses: u32,
+}
+
+// lookups of an order that may fail before its level is placed again
+const MAX_MISSES: u32 = 3;
+
+// price lines between lower and upper, both included
+pub fn levels(lower: f64, upper: f64, count: usize, geometric: bool) -> Vec<f64> {
+    if count < 2 {
+        return vec![lower];
+    }
+    let n = (count - 1) as f64;
+    (0..count)
+        .map(|i| {
+            if geometric {
+                lower * (upper / lower).powf(i as f64 / n)
+            } else {
+                lower + (upper - lower) * i as f64 / n
+            }
+        })
+        .collect()
+}
+
+// bounds of the same width (or ratio) centred on `price`
+pub fn shift(lower: f64, upper: f64, price: f64, geometric: bool) -> (f64, f64) {
+    if geometric {
+        let half = (upper / lower).sqrt();
+        (price / half, price * half)
+    } else {
+        let half = (upper - lower) / 2f64;
+        (price - half, price + half)
+    }
+}
+
+// resting orders at every level but the one nearest to the price, buys
+// below it and sells above. Each fill places the opposite order one level
+// away, so one level always stays empty
+#[derive(Debug)]
+pub struct Grid {
+    config: Value,
+    client: Box<dyn Client>,
+    info: Option<SymbolInfo>,
+    levels: Vec<f64>,
+    orders: Vec<GridOrder>,
+    // profit and round trips per cell between two levels
+    profits: Vec<f64>,
+    trips: Vec<u64>,
+    // base bought by the grid and not sold yet
+    inventory: f64,
+    paused: bool,
+    stopped: bool,
+
+    symbol: String,
+    lower: f64,
+    upper: f64,
+    count: usize,
+    geometric: bool,
+    amount: f64,
+    fee: f64,
+    out_of_range: String,
+}
+
+impl Grid {
+    pub fn validate(config: &Value) -> Vec<String> {
+        let mut errors = check_keys(config, &["symbol"], &["lower", "upper", "levels", "amount"]);
+        let lower = config["lower"].as_f64().unwrap_or(0f64);
+        let upper = config["upper"].as_f64().unwrap_or(0f64);
+        if lower <= 0f64 || upper <= lower {
+            errors.push("lower, upper: expect 0 < lower < upper".into());
+        }
+        if config["levels"].as_u64().unwrap_or(0) < 3 {
+            errors.push("levels: expect at least 3".into());
+        }
+        if !["arithmetic", "geometric"].contains(&config["mode"].as_str().unwrap_or("arithmetic")) {
+            errors.push("mode: expect arithmetic or geometric".into());
+        }
+        if !["pause", "shift", "stop"].contains(&config["out_of_range"].as_str().unwrap_or("pause"))
+        {
+            errors.push("out_of_range: expect pause, shift or stop".into());
+        }
+        errors
+    }
+
+    fn round_price(&self, price: f64) -> f64 {
+        match &self.info {
+            Some(info) => round_to(price, info.price_precision as u32),
+            None => price,
+        }
+    }
+
+    fn round_amount(&self, amount: f64) -> f64 {
+        match &self.info {
+            Some(info) => round_to(amount, info.amount_precision as u32),
+            None => amount,
+        }
+    }
+
+    fn place(&mut self, level: usize, side: &str, paired: bool) {
+        let price = self.round_price(self.levels[level]);
+        let amount = self.round_amount(self.amount);
+        let ret = send_order(
+            &*self.client,
+            "grid",
+            &self.symbol,
+            price,
+            amount,
+            side,
+            ORDER_TYPE_LIMIT,
+        );
+        if let Ok(order_id) = ret {
+            self.orders.push(GridOrder {
+                level: level,
+                side: side.into(),
+                order_id: order_id,
+                paired: paired,
+                misses: 0,
+            });
+        }
+    }
+
+    // lay out the grid around `price`
+    fn place_all(&mut self, price: f64) {
+        self.levels = levels(self.lower, self.upper, self.count, self.geometric);
+        let nearest = (0..self.levels.len())
+            .min_by(|a, b| {
+                let da = (self.levels[*a] - price).abs();
+                let db = (self.levels[*b] - price).abs();
+                da.partial_cmp(&db).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap_or(0);
+        for level in 0..self.levels.len() {
+            if level < nearest {
+                self.place(level, ORDER_ACTION_BUY, false);
+            } else if level > nearest {
+                self.place(level, ORDER_ACTION_SELL, false);
+            }
+        }
+    }
+
+    fn cancel_all(&mut self) {
+        for order in self.orders.drain(..).collect::<Vec<_>>() {
+            if let Err(err) = self.client.cancel(&order.order_id) {
+                warn!("cancel {} error: {:?}", order.order_id, err);
+            }
+        }
+    }
+
+    fn on_fill(&mut self, order: &GridOrder) {
+        let price = self.levels[order.level];
+        let amount = self.round_amount(self.amount);
+        info!("grid {} {} filled at {}", self.symbol, order.side, price);
+        if order.side == ORDER_ACTION_BUY {
+            self.inventory += amount;
+        } else {
+            self.inventory -= amount;
+        }
+        // a round trip spans the filled level and its neighbour
+        let (cell, other) = if order.side == ORDER_ACTION_BUY {
+            (order.level, order.level + 1)
+        } else {
+            (order.level - 1, order.level - 1)
+        };
+        if order.paired {
+            let (low, high) = (self.levels[cell], self.levels[cell + 1]);
+            let profit = (high - low) * amount - (high + low) * amount * self.fee;
+            self.profits[cell] += profit;
+            self.trips[cell] += 1;
+            metrics::set_gauge(
+                "rsquant_grid_profit",
+                "Realised grid profit in quote currency",
+                &[("symbol", self.symbol.as_str())],
+                self.profits.iter().sum(),
+            );
+        }
+        notify::send(
+            EventKind::Fill,
+            &format!("{} grid {} filled", self.symbol, order.side.to_lowercase()),
+            &format!("{} {} at {}", order.side, amount, price),
+        );
+        let side = if order.side == ORDER_ACTION_BUY {
+            ORDER_ACTION_SELL
+        } else {
+            ORDER_ACTION_BUY
+        };
+        self.place(other, side, true);
+    }
+
+    fn check_orders(&mut self) {
+        for order in self.orders.clone() {
+            // a stale id shouldn't hold up the rest of the grid, one the
+            // exchange keeps failing to find gets its level placed again
+            let status = match self.client.get_order(&order.order_id) {
+                Ok(order) => order.status,
+                Err(err) => {
+                    warn!("get_order {} error: {:?}", order.order_id, err);
+                    let misses = order.misses + 1;
+                    if misses < MAX_MISSES {
+                        for o in self
+                            .orders
+                            .iter_mut()
+                            .filter(|o| o.order_id == order.order_id)
+                        {
+                            o.misses = misses;
+                        }
+                        continue;
+                    }
+                    "UNKNOWN".into()
+                }
+            };
+            match status.as_str() {
+                "NEW" | "PARTIALLY_FILLED" => {
+                    for o in self
+                        .orders
+                        .iter_mut()
+                        .filter(|o| o.order_id == order.order_id)
+                    {
+                        o.misses = 0;
+                    }
+                }
+                "FILLED" => {
+                    self.orders.retain(|o| o.order_id != order.order_id);
+                    self.on_fill(&order);
+                }
+                // cancelled outside of the grid or lost, put it back
+                _ => {
+                    warn!("grid order {} {}, replacing", order.order_id, status);
+                    self.orders.retain(|o| o.order_id != order.order_id);
+                    self.place(order.level, &order.side, order.paired);
+                }
+            }
+        }
+    }
+
+    // true when the grid should keep trading at `price`
+    fn check_range(&mut self, price: f64) -> bool {
+        if price >= self.lower && price <= self.upper {
+            if self.paused {
+                info!("grid {} back in range at {}", self.symbol, price);
+                self.paused = false;
+                self.place_all(price);
+            }
+            return true;
+        }
+        match self.out_of_range.as_str() {
+            "shift" => {
+                let (lower, upper) = shift(self.lower, self.upper, price, self.geometric);
+                info!(
+                    "grid {} shift to {} - {} at {}",
+                    self.symbol, lower, upper, price
+                );
+                self.cancel_all();
+                self.lower = lower;
+                self.upper = upper;
+                self.place_all(price);
+                true
+            }
+            "stop" => {
+                self.cancel_all();
+                let amount = self.round_amount(self.inventory);
+                if amount > 0f64 {
+                    let ret = self.client.create_order(
+                        &self.symbol,
+                        price,
+                        amount,
+                        ORDER_ACTION_SELL,
+                        ORDER_TYPE_MARKET,
+                    );
+                    info!(
+                        "grid {} stop-out, sell {} at market: {:?}",
+                        self.symbol, amount, ret
+                    );
+                    if ret.is_ok() {
+                        self.inventory -= amount;
+                    }
+                }
+                self.stopped = true;
+                notify::send(
+                    EventKind::Stop,
+                    &format!("{} grid stopped", self.symbol),
+                    &format!("price {} left {} - {}", price, self.lower, self.upper),
+                );
+                false
+            }
+            // no orders out of range, the grid is laid out again around
+            // the price once it's back, the inventory stays
+            _ => {
+                if !self.paused {
+                    info!("grid {} paused, price {} out of range", self.symbol, price);
+                    self.cancel_all();
+                    self.paused = true;
+                }
+                false
+            }
+        }
+    }
+}
+
+impl Strategy for Grid {
+    fn new(config_path: &str) -> Box<dyn Strategy> {
+        let file = fs::File::open(config_path).expect("file should open read only");
+        let config: Value = serde_json::from_reader(file).expect("file should be proper json");
+        let client = exchange::binance(&config);
+        Self::from_config(&config, Box::new(client))
+    }
+
+    fn from_config(config: &Value, client: Box<dyn Client>) -> Box<dyn Strategy> {
+        let count = config["levels"].as_u64().unwrap() as usize;
+        Box::new(Grid {
+            config: config.clone(),
+            client: client,
+            info: None,
+            levels: vec![],
+            orders: vec![],
+            profits: vec![0f64; count.saturating_sub(1)],
+            trips: vec![0; count.saturating_sub(1)],
+            inventory: 0f64,
+            paused: false,
+            stopped: false,
+
+            symbol: config["symbol"].as_str().unwrap().to_uppercase(),
+            lower: config["lower"].as_f64().unwrap(),
+            upper: config["upper"].as_f64().unwrap(),
+            count: count,
+            geometric: config["mode"].as_str() == Some("geometric"),
+            amount: config["amount"].as_f64().unwrap(),
+            fee: config["fee"].as_f64().unwrap_or(0.001),
+            out_of_range: config["out_of_range"].as_str().unwrap_or("pause").into(),
+        })
+    }
+
+    // orders go out on the first tick, after a saved grid had the chance
+    // to be restored
+    fn init(&mut self) {
+        match self.client.get_symbols() {
+            Ok(symbols) => {
+                self.info = symbols
+                    .into_iter()
+                    .find(|info| info.symbol.eq_ignore_ascii_case(&self.symbol));
+                if self.info.is_none() {
+                    warn!("grid symbol {} not found", self.symbol);
+                }
+            }
+            Err(err) => warn!("get_symbols error: {:?}", err),
+        }
+        self.levels = levels(self.lower, self.upper, self.count, self.geometric);
+    }
+
+    fn on_tick(&mut self) {
+        if self.stopped {
+            return;
+        }
+        let price = match self.client.get_ticker(&self.symbol) {
+            Ok(ticker) => (ticker.bid.price + ticker.ask.price) / 2f64,
+            Err(err) => {
+                warn!("get_ticker error: {:?}", err);
+                notify::send(EventKind::Error, "get_ticker error", &format!("{:?}", err));
+                return;
+            }
+        };
+        if self.orders.is_empty() && !self.paused {
+            if price < self.lower || price > self.upper {
+                self.check_range(price);
+                return;
+            }
+            self.place_all(price);
+            return;
+        }
+        self.check_orders();
+        self.check_range(price);
+    }
+
+    fn run_forever(&mut self) {
+        let config = self.config.clone();
+        runner::run(self, &config);
+    }
+
+    fn name(&self) -> String {
+        "grid".into()
+    }
+
+    fn stringify(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn state(&self) -> Value {
+        json!({
+            "strategy": self.name(),
+            "lower": self.lower,
+            "upper": self.upper,
+            "orders": self.orders,
+            "profits": self.profits,
+            "trips": self.trips,
+            "inventory": self.inventory,
+            "paused": self.paused,
+            "stopped": self.stopped,
+            "total_profit": self.profits.iter().sum::<f64>(),
+        })
+    }
+
+    fn restore(&mut self, state: &Value) {
+        if state["strategy"] != "grid" {
+            return;
+        }
+        self.lower = state["lower"].as_f64().unwrap_or(self.lower);
+        self.upper = state["upper"].as_f64().unwrap_or(self.upper);
+        self.levels = levels(self.lower, self.upper, self.count, self.geometric);
+        self.orders = serde_json::from_value(state["orders"].clone()).unwrap_or_default();
+        let count = self.levels.len();
+        self.orders.retain(|order| order.level < count);
+        let profits: Vec<f64> =
+            serde_json::from_value(state["profits"].clone()).unwrap_or_default();
+        let trips: Vec<u64> = serde_json::from_value(state["trips"].clone()).unwrap_or_default();
+        if profits.len() == self.profits.len() && trips.len() == self.trips.len() {
+            self.profits = profits;
+            self.trips = trips;
+        }
+        self.inventory = state["inventory"].as_f64().unwrap_or(0f64);
+        self.paused = state["paused"].as_bool().unwrap_or(false);
+        self.stopped = state["stopped"].as_bool().unwrap_or(false);
+    }
+
+    fn cancel_orders(&mut self) {
+        info!("cancel {} grid orders", self.orders.len());
+        self.cancel_all();
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "orders: {}, round trips: {}, inventory: {}, total_profit: {}",
+            self.orders.len(),
+            self.trips.iter().sum::<u64>(),
+            self.inventory,
+            self.profits.iter().sum::<f64>()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backtest::data::{
+            fixture::{bars, replay, sim},
+            symbol_info,
+        },
+        exchange::SimExchange,
+    };
+    use rsex::traits::SpotRest;
+
+    #[test]
+    fn test_levels() {
+        assert_eq!(
+            levels(100f64, 120f64, 5, false),
+            vec![100f64, 105f64, 110f64, 115f64, 120f64]
+        );
+        let geometric = levels(100f64, 400f64, 3, true);
+        assert!((geometric[1] - 200f64).abs() < 1e-9);
+        assert_eq!(shift(100f64, 120f64, 130f64, false), (120f64, 140f64));
+        let (lower, upper) = shift(100f64, 400f64, 100f64, true);
+        assert!((lower - 50f64).abs() < 1e-9 && (upper - 200f64).abs() < 1e-9);
+    }
+
+    fn run(closes: &[f64], config: &Value) -> (SimExchange, Value) {
+        let data = vec![(symbol_info("ETH", "USDT"), bars(closes, 0, 60_000))];
+        let exchange = sim(data, &[("ETH", 1f64), ("USDT", 1000f64)], 0f64);
+        let mut robot = Grid::from_config(config, Box::new(exchange.clone()));
+        replay(&[&exchange], &mut *robot);
+        (exchange, robot.state())
+    }
+
+    #[test]
+    fn test_grid() {
+        let mut config = json!({
+            "symbol": "ETHUSDT",
+            "lower": 95,
+            "upper": 115,
+            "levels": 5,
+            "amount": 0.1,
+            "fee": 0,
+        });
+        assert!(Grid::validate(&config).is_empty());
+
+        // sells at 105 and 110 fill, then the buys placed below them
+        let (exchange, state) = run(
+            &[100f64, 100f64, 110f64, 110f64, 105f64, 100f64, 100f64],
+            &config,
+        );
+        assert_eq!(state["trips"], json!([0, 1, 1, 0]));
+        assert!((state["total_profit"].as_f64().unwrap() - 1f64).abs() < 1e-9);
+        // buy at 95, sells at 105, 110, 115
+        assert_eq!(state["orders"].as_array().unwrap().len(), 4);
+        assert_eq!(exchange.get_open_orders("ETHUSDT").unwrap().len(), 4);
+
+        // paused above the range without orders, laid out again around
+        // the price once back in it
+        let (exchange, state) = run(&[100f64, 100f64, 120f64, 120f64], &config);
+        assert_eq!(state["paused"], true);
+        assert!((state["inventory"].as_f64().unwrap() + 0.3).abs() < 1e-9);
+        assert!(exchange.get_open_orders("ETHUSDT").unwrap().is_empty());
+        let (exchange, state) = run(&[100f64, 100f64, 120f64, 120f64, 110f64], &config);
+        assert_eq!(state["paused"], false);
+        assert_eq!(state["trips"], json!([0, 0, 0, 0]));
+        // buys at 95, 100 and 105, sell at 115
+        assert_eq!(exchange.get_open_orders("ETHUSDT").unwrap().len(), 4);
+
+        // an order the exchange no longer knows doesn't hold up the others
+        let data = vec![(
+            symbol_info("ETH", "USDT"),
+            bars(&[100f64, 110f64], 0, 60_000),
+        )];
+        let exchange = sim(data, &[("ETH", 1f64), ("USDT", 1000f64)], 0f64);
+        exchange.advance(0);
+        let sell = exchange
+            .create_order("ETHUSDT", 105f64, 0.1, ORDER_ACTION_SELL, ORDER_TYPE_LIMIT)
+            .unwrap();
+        let mut robot = Grid::from_config(&config, Box::new(exchange.clone()));
+        robot.init();
+        robot.restore(&json!({"strategy": "grid", "orders": [
+            {"level": 0, "side": "BUY", "order_id": "missing", "paired": false},
+            {"level": 2, "side": "SELL", "order_id": sell, "paired": false},
+        ]}));
+        exchange.advance(60_000);
+        robot.on_tick();
+        assert!((robot.state()["inventory"].as_f64().unwrap() + 0.1).abs() < 1e-9);
+        // and is given up on after a few lookups, its level placed again
+        robot.on_tick();
+        robot.on_tick();
+        let state = robot.state();
+        let orders = state["orders"].as_array().unwrap();
+        assert!(orders.iter().all(|order| order["order_id"] != "missing"));
+        assert!(orders
+            .iter()
+            .any(|order| order["level"] == 0 && order["side"] == "BUY"));
+        let buys = exchange.get_open_orders("ETHUSDT").unwrap();
+        assert!(buys.iter().any(|order| order.price == 95f64));
+
+        // bought at 95 and 90 is below the grid, sell it all
+        config["out_of_range"] = "stop".into();
+        let (exchange, state) = run(&[100f64, 100f64, 95f64, 90f64, 90f64], &config);
+        assert_eq!(state["stopped"], true);
+        assert_eq!(state["inventory"], 0f64);
+        assert!(exchange.get_open_orders("ETHUSDT").unwrap().is_empty());
+        assert_eq!(exchange.get_balance("ETH").unwrap().free, 1f64);
+    }
+}
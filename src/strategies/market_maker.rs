@@ -0,0 +1,524 @@
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use rsex::{
+    constant::{ORDER_ACTION_BUY, ORDER_ACTION_SELL, ORDER_TYPE_LIMIT},
+    models::{SymbolInfo, Ticker},
+};
+use crate::{
+    exchange, metrics,
+    notify::{self, EventKind},
+    runner,
+    strategies::send_order,
+    traits::{Client, Strategy},
+    utils::{check_keys, round_to},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Quote {
+    order_id: String,
+    side: String,
+    price: f64,
+    amount: f64,
+    // fair price when quoted, the fill earns the distance to it
+    fair: f64,
+    // amount already booked into the pnl
+    #[serde(default)]
+    filled: f64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Pnl {
+    // quote currency and base received by fills, fees included
+    cash: f64,
+    base: f64,
+    fees: f64,
+    capture: f64,
+    volume: f64,
+    fills: u64,
+}
+
+// size weighted mid, leaning towards the side with less size; plain mid
+// without usable sizes
+pub fn micro_price(bid: f64, bid_size: f64, ask: f64, ask_size: f64) -> f64 {
+    let total = bid_size + ask_size;
+    if !total.is_finite() || total <= 0f64 {
+        return (bid + ask) / 2f64;
+    }
+    (bid * ask_size + ask * bid_size) / total
+}
+
+// bid and ask `half_spread` around a reservation price moved against the
+// inventory: `ratio` is the inventory over its cap in [-1, 1], a full long
+// shifts both quotes down by `skew` half spreads
+pub fn quotes(fair: f64, half_spread: f64, ratio: f64, skew: f64) -> (f64, f64) {
+    let reservation = fair * (1f64 - skew * ratio.clamp(-1f64, 1f64) * half_spread);
+    (
+        reservation * (1f64 - half_spread),
+        reservation * (1f64 + half_spread),
+    )
+}
+
+// quotes a bid and an ask around the mid or micro-price, skewed by the
+// inventory away from `target_inventory`, and stops quoting the side that
+// would push it past `max_inventory`. Quotes are cancelled and replaced
+// once the wanted price moved more than `reprice` away
+#[derive(Debug)]
+pub struct MarketMaker {
+    config: Value,
+    client: Box<dyn Client>,
+    info: Option<SymbolInfo>,
+    bid: Option<Quote>,
+    ask: Option<Quote>,
+    target: Option<f64>,
+    inventory: f64,
+    pnl: Pnl,
+    mark: f64,
+
+    symbol: String,
+    amount: f64,
+    half_spread: f64,
+    max_inventory: f64,
+    skew: f64,
+    reprice: f64,
+    micro: bool,
+    fee: f64,
+}
+
+impl MarketMaker {
+    pub fn validate(config: &Value) -> Vec<String> {
+        let mut errors = check_keys(config, &["symbol"], &["amount", "spread", "max_inventory"]);
+        if config["spread"].as_f64().unwrap_or(0f64) <= 0f64 {
+            errors.push("spread: expect > 0".into());
+        }
+        if config["max_inventory"].as_f64().unwrap_or(0f64) <= 0f64 {
+            errors.push("max_inventory: expect > 0".into());
+        }
+        if !["mid", "micro"].contains(&config["price"].as_str().unwrap_or("mid")) {
+            errors.push("price: expect mid or micro".into());
+        }
+        errors
+    }
+
+    fn load_symbol(&mut self) {
+        match self.client.get_symbols() {
+            Ok(symbols) => {
+                self.info = symbols
+                    .into_iter()
+                    .find(|info| info.symbol.eq_ignore_ascii_case(&self.symbol));
+                if self.info.is_none() {
+                    warn!("market maker symbol {} not found", self.symbol);
+                }
+            }
+            Err(err) => warn!("get_symbols error: {:?}", err),
+        }
+    }
+
+    fn step(&self) -> f64 {
+        match &self.info {
+            Some(info) => 1f64 / 10f64.powi(info.price_precision as i32),
+            None => 0f64,
+        }
+    }
+
+    fn place(&self, side: &str, price: f64, fair: f64) -> Option<Quote> {
+        let (price, amount) = match &self.info {
+            Some(info) => (
+                round_to(price, info.price_precision as u32),
+                round_to(self.amount, info.amount_precision as u32),
+            ),
+            None => (price, self.amount),
+        };
+        let order_id = send_order(
+            &*self.client,
+            "market_maker",
+            &self.symbol,
+            price,
+            amount,
+            side,
+            ORDER_TYPE_LIMIT,
+        )
+        .ok()?;
+        Some(Quote {
+            order_id: order_id,
+            side: side.into(),
+            price: price,
+            amount: amount,
+            fair: fair,
+            filled: 0f64,
+        })
+    }
+
+    // cancel and book what filled until then
+    fn cancel(&mut self, quote: Quote) {
+        if let Err(err) = self.client.cancel(&quote.order_id) {
+            warn!("cancel {} error: {:?}", quote.order_id, err);
+        }
+        self.check(Some(quote));
+    }
+
+    fn on_fill(&mut self, quote: &Quote, amount: f64) {
+        let value = quote.price * amount;
+        let fee = value * self.fee;
+        if quote.side == ORDER_ACTION_BUY {
+            self.pnl.cash -= value + fee;
+            self.pnl.base += amount;
+            self.pnl.capture += (quote.fair - quote.price) * amount;
+        } else {
+            self.pnl.cash += value - fee;
+            self.pnl.base -= amount;
+            self.pnl.capture += (quote.price - quote.fair) * amount;
+        }
+        self.pnl.fees += fee;
+        self.pnl.volume += value;
+        self.pnl.fills += 1;
+        info!(
+            "{} {} {} filled at {}",
+            self.symbol, quote.side, amount, quote.price
+        );
+        notify::send(
+            EventKind::Fill,
+            &format!("{} {} filled", self.symbol, quote.side.to_lowercase()),
+            &format!("{} {} at {}", quote.side, amount, quote.price),
+        );
+    }
+
+    // the resting quote after booking what filled since the last check,
+    // None once gone
+    fn check(&mut self, quote: Option<Quote>) -> Option<Quote> {
+        let mut quote = quote?;
+        match self.client.get_order(&quote.order_id) {
+            Ok(order) => {
+                if order.filled > quote.filled {
+                    self.on_fill(&quote, order.filled - quote.filled);
+                    quote.filled = order.filled;
+                }
+                match order.status.as_str() {
+                    "NEW" | "PARTIALLY_FILLED" => Some(quote),
+                    _ => None,
+                }
+            }
+            Err(err) => {
+                warn!("get_order {} error: {:?}", quote.order_id, err);
+                Some(quote)
+            }
+        }
+    }
+
+    // keep, replace or drop the quote of one side
+    fn requote(
+        &mut self,
+        current: Option<Quote>,
+        side: &str,
+        wanted: Option<f64>,
+        fair: f64,
+    ) -> Option<Quote> {
+        match (current, wanted) {
+            (Some(quote), Some(price)) if (quote.price / price - 1f64).abs() <= self.reprice => {
+                Some(quote)
+            }
+            (current, wanted) => {
+                if let Some(quote) = current {
+                    self.cancel(quote);
+                }
+                wanted.and_then(|price| self.place(side, price, fair))
+            }
+        }
+    }
+
+    fn fair(&self, ticker: &Ticker) -> f64 {
+        if self.micro {
+            micro_price(
+                ticker.bid.price,
+                ticker.bid.amount,
+                ticker.ask.price,
+                ticker.ask.amount,
+            )
+        } else {
+            (ticker.bid.price + ticker.ask.price) / 2f64
+        }
+    }
+
+    // pnl split into what the spread earned and what holding inventory
+    // through price moves did, at the last mark
+    fn report(&self) -> (f64, f64) {
+        let total = self.pnl.cash + self.pnl.base * self.mark;
+        (total, total - self.pnl.capture + self.pnl.fees)
+    }
+}
+
+impl Strategy for MarketMaker {
+    fn new(config_path: &str) -> Box<dyn Strategy> {
+        let file = fs::File::open(config_path).expect("file should open read only");
+        let config: Value = serde_json::from_reader(file).expect("file should be proper json");
+        let client = exchange::binance(&config);
+        Self::from_config(&config, Box::new(client))
+    }
+
+    fn from_config(config: &Value, client: Box<dyn Client>) -> Box<dyn Strategy> {
+        Box::new(MarketMaker {
+            config: config.clone(),
+            client: client,
+            info: None,
+            bid: None,
+            ask: None,
+            target: config["target_inventory"].as_f64(),
+            inventory: 0f64,
+            pnl: Pnl::default(),
+            mark: 0f64,
+
+            symbol: config["symbol"].as_str().unwrap().to_uppercase(),
+            amount: config["amount"].as_f64().unwrap(),
+            half_spread: config["spread"].as_f64().unwrap() / 2f64,
+            max_inventory: config["max_inventory"].as_f64().unwrap(),
+            skew: config["skew"].as_f64().unwrap_or(1f64),
+            reprice: config["reprice"].as_f64().unwrap_or(0.001),
+            micro: config["price"].as_str() == Some("micro"),
+            fee: config["fee"].as_f64().unwrap_or(0.001),
+        })
+    }
+
+    fn init(&mut self) {
+        self.load_symbol();
+    }
+
+    fn on_tick(&mut self) {
+        // init couldn't load it, keep trying
+        if self.info.is_none() {
+            self.load_symbol();
+        }
+        let bid = self.bid.take();
+        self.bid = self.check(bid);
+        let ask = self.ask.take();
+        self.ask = self.check(ask);
+
+        let base = match &self.info {
+            Some(info) => info.base.clone(),
+            None => return,
+        };
+        match self.client.get_balance(&base) {
+            Ok(balance) => self.inventory = balance.free + balance.locked,
+            Err(err) => {
+                warn!("get_balance error: {:?}", err);
+                return;
+            }
+        }
+        let target = *self.target.get_or_insert(self.inventory);
+        let ticker = match self.client.get_ticker(&self.symbol) {
+            Ok(ticker) => ticker,
+            Err(err) => {
+                warn!("get_ticker error: {:?}", err);
+                notify::send(EventKind::Error, "get_ticker error", &format!("{:?}", err));
+                return;
+            }
+        };
+        let fair = self.fair(&ticker);
+        self.mark = fair;
+
+        let excess = self.inventory - target;
+        let (bid, ask) = quotes(
+            fair,
+            self.half_spread,
+            excess / self.max_inventory,
+            self.skew,
+        );
+        // never cross the book, never grow the inventory past the cap
+        let step = self.step();
+        let bid = bid.min(ticker.ask.price - step);
+        let ask = ask.max(ticker.bid.price + step);
+        let room = self.max_inventory + 1e-12;
+        let wanted_bid = if excess + self.amount <= room {
+            Some(bid)
+        } else {
+            None
+        };
+        let wanted_ask = if excess - self.amount >= -room {
+            Some(ask)
+        } else {
+            None
+        };
+
+        let current = self.bid.take();
+        self.bid = self.requote(current, ORDER_ACTION_BUY, wanted_bid, fair);
+        let current = self.ask.take();
+        self.ask = self.requote(current, ORDER_ACTION_SELL, wanted_ask, fair);
+
+        let (total, inventory_pnl) = self.report();
+        let labels = [("symbol", self.symbol.as_str())];
+        metrics::set_gauge(
+            "rsquant_mm_spread_capture",
+            "Spread captured by fills",
+            &labels,
+            self.pnl.capture,
+        );
+        metrics::set_gauge(
+            "rsquant_mm_inventory_pnl",
+            "PnL from holding inventory",
+            &labels,
+            inventory_pnl,
+        );
+        metrics::set_gauge(
+            "rsquant_mm_total_pnl",
+            "Market making PnL, marked to market",
+            &labels,
+            total,
+        );
+    }
+
+    fn run_forever(&mut self) {
+        let config = self.config.clone();
+        runner::run(self, &config);
+    }
+
+    fn name(&self) -> String {
+        "market_maker".into()
+    }
+
+    fn stringify(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn state(&self) -> Value {
+        let (total, inventory_pnl) = self.report();
+        json!({
+            "strategy": self.name(),
+            "target_inventory": self.target,
+            "inventory": self.inventory,
+            "quotes": [self.bid, self.ask],
+            "pnl": self.pnl,
+            "spread_capture": self.pnl.capture,
+            "inventory_pnl": inventory_pnl,
+            "fees": self.pnl.fees,
+            "total_pnl": total,
+        })
+    }
+
+    fn restore(&mut self, state: &Value) {
+        if state["strategy"] != "market_maker" {
+            return;
+        }
+        if self.target.is_none() {
+            self.target = state["target_inventory"].as_f64();
+        }
+        if let Ok(pnl) = serde_json::from_value(state["pnl"].clone()) {
+            self.pnl = pnl;
+        }
+        // quotes left over from the last run are requoted
+        if let Ok(quotes) = serde_json::from_value::<Vec<Option<Quote>>>(state["quotes"].clone()) {
+            for quote in quotes.into_iter().flatten() {
+                if quote.side == ORDER_ACTION_BUY {
+                    self.bid = Some(quote);
+                } else {
+                    self.ask = Some(quote);
+                }
+            }
+        }
+    }
+
+    fn cancel_orders(&mut self) {
+        for quote in self.bid.take().into_iter().chain(self.ask.take()) {
+            self.cancel(quote);
+        }
+    }
+
+    fn summary(&self) -> String {
+        let (total, inventory_pnl) = self.report();
+        format!(
+            "fills: {}, inventory: {}, spread capture: {}, inventory pnl: {}, fees: {}, total: {}",
+            self.pnl.fills, self.inventory, self.pnl.capture, inventory_pnl, self.pnl.fees, total
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backtest::data::{
+        fixture::{bars, replay, sim},
+        symbol_info,
+    };
+    use rsex::traits::SpotRest;
+
+    #[test]
+    fn test_quotes() {
+        assert_eq!(micro_price(99f64, 1f64, 101f64, 3f64), 99.5);
+        assert_eq!(micro_price(99f64, f64::MAX, 101f64, f64::MAX), 100f64);
+        let (bid, ask) = quotes(100f64, 0.01, 0f64, 1f64);
+        assert!((bid - 99f64).abs() < 1e-9 && (ask - 101f64).abs() < 1e-9);
+        // long, both quotes lower
+        let (bid, ask) = quotes(100f64, 0.01, 1f64, 1f64);
+        assert!((bid - 98.01).abs() < 1e-9 && (ask - 99.99).abs() < 1e-9);
+    }
+
+    fn run(closes: &[f64]) -> Value {
+        let data = vec![(symbol_info("ETH", "USDT"), bars(closes, 0, 60_000))];
+        let exchange = sim(data, &[("ETH", 1f64), ("USDT", 1000f64)], 0f64);
+        let config = json!({
+            "symbol": "ETHUSDT",
+            "amount": 0.1,
+            "spread": 0.01,
+            "max_inventory": 0.3,
+            "reprice": 0.002,
+            "fee": 0,
+        });
+        assert!(MarketMaker::validate(&config).is_empty());
+        let mut robot = MarketMaker::from_config(&config, Box::new(exchange.clone()));
+        replay(&[&exchange], &mut *robot);
+        robot.state()
+    }
+
+    #[test]
+    fn test_market_maker() {
+        // back and forth through both quotes
+        let state = run(&[100f64, 100f64, 101f64, 99f64, 101f64, 99f64, 100f64]);
+        assert!(state["pnl"]["fills"].as_u64().unwrap() >= 3);
+        assert!(state["spread_capture"].as_f64().unwrap() > 0f64);
+        let total = state["total_pnl"].as_f64().unwrap();
+        let split =
+            state["spread_capture"].as_f64().unwrap() + state["inventory_pnl"].as_f64().unwrap();
+        assert!((total - split).abs() < 1e-9);
+
+        // falling prices fill the bid until the cap
+        let falling: Vec<f64> = (0..20).map(|i| 100f64 - i as f64).collect();
+        let state = run(&falling);
+        let excess =
+            state["inventory"].as_f64().unwrap() - state["target_inventory"].as_f64().unwrap();
+        assert!((excess - 0.3).abs() < 1e-9);
+        assert!(state["inventory_pnl"].as_f64().unwrap() < 0f64);
+        assert!(state["quotes"][0].is_null());
+    }
+
+    #[test]
+    fn test_partial_fill() {
+        let data = vec![(
+            symbol_info("ETH", "USDT"),
+            bars(&[100f64, 100f64], 0, 60_000),
+        )];
+        let exchange = sim(data, &[("ETH", 1f64), ("USDT", 1000f64)], 0f64);
+        exchange.advance(0);
+        let order_id = exchange
+            .create_order("ETHUSDT", 100f64, 0.1, ORDER_ACTION_BUY, ORDER_TYPE_LIMIT)
+            .unwrap();
+        let config = json!({
+            "symbol": "ETHUSDT",
+            "amount": 0.1,
+            "spread": 0.01,
+            "max_inventory": 0.3,
+            "fee": 0,
+        });
+        let mut robot = MarketMaker::from_config(&config, Box::new(exchange.clone()));
+        // 0.04 was booked before the restart, the rest filled since
+        robot.restore(
+            &json!({"strategy": "market_maker", "quotes": [{"order_id": order_id, "side": "BUY",
+            "price": 100, "amount": 0.1, "fair": 100, "filled": 0.04}, null]}),
+        );
+        robot.init();
+        exchange.advance(60_000);
+        robot.on_tick();
+        let state = robot.state();
+        assert!((state["pnl"]["base"].as_f64().unwrap() - 0.06).abs() < 1e-9);
+        assert!((state["pnl"]["cash"].as_f64().unwrap() + 6f64).abs() < 1e-9);
+        assert_eq!(state["pnl"]["fills"], 1);
+    }
+}
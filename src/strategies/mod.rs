@@ -1,8 +1,137 @@
+use log::{info, warn};
+use rsex::errors::APIResult;
+use serde_json::Value;
+
+use crate::{
+    metrics,
+    notify::{self, EventKind},
+    traits::{Client, Strategy, Venues},
+    utils::check_keys,
+};
+
+mod dca;
+pub use dca::Dca;
+
 mod dummy;
 pub use dummy::Dummy;
 
+mod grid;
+pub use grid::Grid;
+
+mod market_maker;
+pub use market_maker::MarketMaker;
+
 mod move_stoploss;
 pub use move_stoploss::MoveStopLoss;
 
+mod pairs;
+pub use pairs::Pairs;
+
+mod rotation;
+pub use rotation::Rotation;
+
+mod rule_based;
+pub use rule_based::RuleBased;
+
+mod script;
+pub use script::Script;
+
+mod spread;
+pub use spread::Spread;
+
+mod triangular;
+pub use triangular::Triangular;
+
 //mod turtle;
 //pub use turtle::Turtle;
+
+pub const STRATEGIES: &[&str] = &[
+    "move_stoploss", "grid", "dca", "rotation", "triangular", "spread",
+    "market_maker", "pairs", "rules", "script",
+];
+
+// strategies built from one client per "venues" entry
+pub const MULTI_VENUE: &[&str] = &["spread"];
+
+pub fn build(config: &Value, client: Box<dyn Client>) -> Option<Box<dyn Strategy>> {
+    match config["strategy"].as_str()? {
+        "move_stoploss" => Some(MoveStopLoss::from_config(config, client)),
+        "grid" => Some(Grid::from_config(config, client)),
+        "dca" => Some(Dca::from_config(config, client)),
+        "rotation" => Some(Rotation::from_config(config, client)),
+        "triangular" => Some(Triangular::from_config(config, client)),
+        "market_maker" => Some(MarketMaker::from_config(config, client)),
+        "pairs" => Some(Pairs::from_config(config, client)),
+        "rules" => Some(RuleBased::from_config(config, client)),
+        "script" => Some(Script::from_config(config, client)),
+        _ => None,
+    }
+}
+
+pub fn build_venues(config: &Value, venues: Venues) -> Option<Box<dyn Strategy>> {
+    match config["strategy"].as_str()? {
+        "spread" => Some(Spread::from_venues(config, venues)),
+        _ => {
+            let (_, client) = venues.into_iter().next()?;
+            build(config, client)
+        }
+    }
+}
+
+pub fn validate(config: &Value) -> Vec<String> {
+    let mut errors = match config["strategy"].as_str() {
+        // credentials live under "venues"
+        Some(strategy) if MULTI_VENUE.contains(&strategy) => check_keys(config, &["strategy"], &[]),
+        _ => check_keys(config, &["host", "apikey", "secret_key", "strategy"], &[]),
+    };
+    match config["strategy"].as_str() {
+        Some("move_stoploss") => errors.extend(MoveStopLoss::validate(config)),
+        Some("grid") => errors.extend(Grid::validate(config)),
+        Some("dca") => errors.extend(Dca::validate(config)),
+        Some("rotation") => errors.extend(Rotation::validate(config)),
+        Some("triangular") => errors.extend(Triangular::validate(config)),
+        Some("spread") => errors.extend(Spread::validate(config)),
+        Some("market_maker") => errors.extend(MarketMaker::validate(config)),
+        Some("pairs") => errors.extend(Pairs::validate(config)),
+        Some("rules") => errors.extend(RuleBased::validate(config)),
+        Some("script") => errors.extend(Script::validate(config)),
+        Some(strategy) => errors.push(format!("strategy: unknown strategy {:?}", strategy)),
+        None => {}
+    }
+    errors
+}
+
+// send an order the way every strategy accounts for it: counted in
+// rsquant_orders_total, logged under `label` and notified when it fails
+pub fn send_order(
+    client: &dyn Client,
+    label: &str,
+    symbol: &str,
+    price: f64,
+    amount: f64,
+    side: &str,
+    order_type: &str,
+) -> APIResult<String> {
+    let ret = client.create_order(symbol, price, amount, side, order_type);
+    let status = if ret.is_ok() { "placed" } else { "failed" };
+    metrics::inc_counter(
+        "rsquant_orders_total",
+        "Orders sent by the strategy",
+        &[("symbol", symbol), ("status", status)],
+    );
+    match &ret {
+        Ok(order_id) => info!(
+            "{} {} {} {} at {}, order_id: {}",
+            label, symbol, side, amount, price, order_id
+        ),
+        Err(err) => {
+            warn!("{} {} {} {} at {} error: {:?}", label, symbol, side, amount, price, err);
+            notify::send(
+                EventKind::Error,
+                &format!("{} {} order failed", symbol, label),
+                &format!("{} {} at {}: {:?}", side, amount, price, err),
+            );
+        }
+    }
+    ret
+}
@@ -1,114 +1,270 @@
+use chrono::Utc;
 use log::{debug, info, warn};
-use serde_json::Value;
-use std::{
-    fs, {thread, time},
-};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, fs};
 use rsex::{
-    binance::spot_rest::Binance,
     constant::{ORDER_ACTION_SELL, ORDER_TYPE_LIMIT},
     errors::APIResult,
-    models::{Balance, SymbolInfo},
-    traits::SpotRest,
+    models::{Balance, SymbolInfo, Ticker},
 };
 use crate::{
-    traits::Strategy,
-    utils::{round_same, round_to},
+    audit::{AuditEntry, AuditLog},
+    exchange, indicators, metrics,
+    notify::{self, EventKind},
+    runner,
+    stops::{AtrStop, Breakeven, TimeStop},
+    strategies::send_order,
+    traits::{Client, Strategy},
+    trailing::{Curve, Trail},
+    universe::Universe,
+    utils::{check_keys, round_same, round_to},
 };
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Position {
     symbol: String,
+    #[serde(default)]
+    base: String,
+    #[serde(default)]
+    quote: String,
     amount: f64,
     price: f64,
     high: f64,
+    // take_profit legs sold and the amount held before the first one
+    #[serde(default)]
+    legs: usize,
+    #[serde(default)]
+    initial: f64,
+    // when the position was first seen, ms
+    #[serde(default)]
+    since: u64,
+    // sells left resting, booked as they fill
+    #[serde(default)]
+    resting: Vec<Resting>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Resting {
+    order_id: String,
+    buy_price: f64,
+    // booked so far
+    filled: f64,
+    // failed lookups in a row
+    #[serde(default)]
+    misses: u32,
+}
+
+// lookups of a resting sell that may fail before it's given up on
+const MAX_MISSES: u32 = 3;
+
+// sell `ratio` of the initial position once profit reaches `profit`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Leg {
+    profit: f64,
+    ratio: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 struct Record {
     symbol: String,
     buy_price: f64,
     sell_price: f64,
     amount: f64,
+    // in the reporting currency
     profit: f64,
+    // the sell order, empty in states saved before it was recorded
+    #[serde(default)]
+    order_id: String,
 }
 
 #[derive(Debug)]
 pub struct MoveStopLoss {
     config: Value,
-    client: Binance,
+    client: Box<dyn Client>,
     watch: Vec<SymbolInfo>,
     positions: Vec<Position>,
     balances: Vec<Balance>,
     history: Vec<Record>,
     total_profit: f64,
+    audit: AuditLog,
+    // reporting currency value of one unit of each quote
+    rates: HashMap<String, f64>,
+    universe: Universe,
+    ticks: u64,
+    take_profit: Vec<Leg>,
+    trailing: Curve,
+    atr_stop: Option<AtrStop>,
+    breakeven: Option<Breakeven>,
+    time_stop: Option<TimeStop>,
 
-    quote: String,
+    quotes: Vec<String>,
+    currency: String,
     min_value: f64,
     stoploss: f64,
     start_threshold: f64,
-    withdraw_ratio: f64,
+}
+
+// the ticker time, the clock for exchanges that leave it 0
+fn timestamp(ticker: &Ticker) -> u64 {
+    if ticker.timestamp > 0 {
+        ticker.timestamp
+    } else {
+        Utc::now().timestamp_millis() as u64
+    }
 }
 
 impl MoveStopLoss {
+    // "quote" is a single quote currency or a list of them, lowercase
+    pub fn quotes(config: &Value) -> Vec<String> {
+        match &config["quote"] {
+            Value::String(quote) => vec![quote.to_lowercase()],
+            Value::Array(quotes) => quotes
+                .iter()
+                .filter_map(|quote| quote.as_str())
+                .map(|quote| quote.to_lowercase())
+                .collect(),
+            _ => vec![],
+        }
+    }
+
+    // values, min_value and profits are in "currency", the first quote
+    // by default
+    pub fn currency(config: &Value) -> String {
+        match config["currency"].as_str() {
+            Some(currency) => currency.to_uppercase(),
+            None => Self::quotes(config)
+                .first()
+                .map(|quote| quote.to_uppercase())
+                .unwrap_or_default(),
+        }
+    }
+
+    pub fn validate(config: &Value) -> Vec<String> {
+        let mut errors = check_keys(
+            config,
+            &[],
+            &["min_value", "stoploss", "start_threshold", "withdraw_ratio"],
+        );
+        let quotes = match &config["quote"] {
+            Value::String(_) => 1,
+            Value::Array(quotes) if quotes.iter().all(|quote| quote.is_string()) => quotes.len(),
+            _ => 0,
+        };
+        if quotes == 0 {
+            errors.push("quote: expect string or array of strings".into());
+        }
+        if !config["ignore"].is_array() {
+            errors.push("ignore: expect array".into());
+        }
+        let start_threshold = config["start_threshold"].as_f64().unwrap_or(0f64);
+        let withdraw_ratio = config["withdraw_ratio"].as_f64().unwrap_or(0f64);
+        if let Err(err) = Curve::from_config(&config["trailing"], start_threshold, withdraw_ratio) {
+            errors.push(err);
+        }
+        if let Err(err) = AtrStop::from_config(&config["atr_stop"]) {
+            errors.push(err);
+        }
+        if let Err(err) = Breakeven::from_config(&config["breakeven"]) {
+            errors.push(err);
+        }
+        if let Err(err) = TimeStop::from_config(&config["time_stop"]) {
+            errors.push(err);
+        }
+        if !config["take_profit"].is_null() {
+            match serde_json::from_value::<Vec<Leg>>(config["take_profit"].clone()) {
+                Ok(legs) => {
+                    let total: f64 = legs.iter().map(|leg| leg.ratio).sum();
+                    if legs.iter().any(|leg| leg.ratio <= 0f64) || total > 1f64 {
+                        errors.push(
+                            "take_profit: ratios should be positive and sum to at most 1".into(),
+                        );
+                    }
+                    if legs.windows(2).any(|w| w[0].profit >= w[1].profit) {
+                        errors.push("take_profit: profits should be ascending".into());
+                    }
+                }
+                Err(_) => errors
+                    .push("take_profit: expect [{\"profit\": f64, \"ratio\": f64}]".into()),
+            }
+        }
+        errors
+    }
+
+    // symbols of every configured quote, a base listed under several
+    // quotes is only watched against the first one
     fn get_symbols(&self) -> APIResult<Vec<SymbolInfo>> {
         let symbol_info = self.client.get_symbols()?;
         debug!("client.get_symbols: {:?}", symbol_info);
-        let symbol_info = symbol_info
-            .into_iter()
-            .filter(|symbol| symbol.quote.to_lowercase() == self.quote)
-            .collect();
-        Ok(symbol_info)
+        let mut watch: Vec<SymbolInfo> = vec![];
+        for quote in &self.quotes {
+            for info in &symbol_info {
+                if info.quote.to_lowercase() != *quote
+                    || watch.iter().any(|w| w.base.eq_ignore_ascii_case(&info.base))
+                {
+                    continue;
+                }
+                watch.push(info.clone());
+            }
+        }
+        Ok(watch)
     }
 
-    fn init(&mut self) {
-        // set watch_list
-        let ret = self.get_symbols();
-        debug!("get_symbols: {:?}", ret);
-        let symbol_info = match ret {
-            Ok(symbols) => symbols,
+    // reporting currency value of one `asset`, through either direction
+    // of the pair with the reporting currency
+    fn rate(&self, asset: &str) -> APIResult<f64> {
+        if asset.eq_ignore_ascii_case(&self.currency) {
+            return Ok(1f64);
+        }
+        let asset = asset.to_uppercase();
+        match self.client.get_ticker(&format!("{}{}", asset, self.currency)) {
+            Ok(ticker) => Ok(ticker.bid.price),
             Err(err) => {
-                warn!("get_symbols error: {:?}", err);
-                vec![]
+                let ticker = self
+                    .client
+                    .get_ticker(&format!("{}{}", self.currency, asset))
+                    .map_err(|_| err)?;
+                if ticker.ask.price <= 0f64 {
+                    return Err(format!("no {} price in {}", asset, self.currency).into());
+                }
+                Ok(1f64 / ticker.ask.price)
             }
-        };
-        debug!("symbol_info: {:?}", symbol_info);
+        }
+    }
 
-        // symbols - ignore
-        let ignore: Vec<String> = self.config["ignore"]
-            .as_array()
-            .unwrap()
-            .into_iter()
-            .map(|coin| coin.as_str().unwrap().to_owned() + &self.quote)
-            .collect();
-        info!("ignore: {:?}", ignore);
+    fn refresh_rates(&mut self) {
+        self.rates.clear();
+        for quote in self.quotes.clone() {
+            match self.rate(&quote) {
+                Ok(rate) => {
+                    self.rates.insert(quote, rate);
+                }
+                Err(err) => warn!("{} rate in {} error: {:?}", quote, self.currency, err),
+            }
+        }
+    }
 
-        self.watch = symbol_info
-            .into_iter()
-            .filter(|info| !ignore.contains(&info.symbol.to_lowercase()))
-            .collect();
-        debug!("watch_list: {:?}", self.watch);
+    // `amount` of `quote` in the reporting currency
+    fn value(&self, quote: &str, amount: f64) -> f64 {
+        amount * self.rates.get(&quote.to_lowercase()).cloned().unwrap_or(0f64)
+    }
 
-        self.positions = self
-            .watch
+    fn precision(&self, symbol: &str) -> u32 {
+        self.watch
             .iter()
-            .map(|info| Position {
-                symbol: info.symbol.clone(),
-                price: 0f64,
-                amount: 0f64,
-                high: 0f64,
-            })
-            .collect();
+            .find(|info| info.symbol == symbol)
+            .map(|info| info.amount_precision as u32)
+            .unwrap_or(8)
     }
 
     fn refresh_position(&self, pos: &Position) -> APIResult<Position> {
-        let mut coin = pos.symbol.clone();
-        let len = self.quote.len();
-        for _ in 0..len {
-            coin.pop();
-        }
+        let coin = &pos.base;
         //let balance = self.client.get_balance(&coin)?;
         let ticker = self.client.get_ticker(&pos.symbol)?;
-        let ret = self.balances.iter().find(|balance| balance.asset == coin);
+        let ret = self
+            .balances
+            .iter()
+            .find(|balance| balance.asset.eq_ignore_ascii_case(coin));
         let balance = match ret {
             Some(balance) => balance,
             None => {
@@ -116,29 +272,45 @@ impl MoveStopLoss {
                 return Ok(pos.clone());
             }
         };
-        if balance.free == pos.amount {
-            if pos.amount * pos.price < self.min_value {
+        // balances drift from our own bookkeeping by rounding and dust,
+        // anything under one lot is the same position
+        let lot = 10f64.powi(-(self.precision(&pos.symbol) as i32));
+        let high = if ticker.bid.price > pos.high {
+            ticker.bid.price
+        } else {
+            pos.high
+        };
+        let now = timestamp(&ticker);
+        if (balance.free - pos.amount).abs() < lot {
+            if self.value(&pos.quote, pos.amount * pos.price) < self.min_value {
                 return Ok(pos.clone());
             } else {
-                let high = if ticker.bid.price > pos.high {
-                    ticker.bid.price
-                } else {
-                    pos.high
-                };
                 return Ok(Position {
-                    symbol: pos.symbol.clone(),
-                    price: pos.price,
-                    amount: pos.amount,
+                    amount: balance.free,
                     high: high,
+                    since: if pos.since > 0 { pos.since } else { now },
+                    ..pos.clone()
                 });
             }
         }
-        if balance.free * ticker.bid.price < self.min_value {
+        if self.value(&pos.quote, balance.free * ticker.bid.price) < self.min_value {
             return Ok(Position {
-                symbol: pos.symbol.clone(),
                 price: 0f64,
                 amount: balance.free,
                 high: 0f64,
+                legs: 0,
+                initial: 0f64,
+                since: 0,
+                ..pos.clone()
+            });
+        }
+        // sold down, by a take-profit leg, a sell still resting or by
+        // hand: the units left keep their entry price and ladder
+        if pos.price > 0f64 && balance.free < pos.amount {
+            return Ok(Position {
+                amount: balance.free,
+                high: high,
+                ..pos.clone()
             });
         }
         // get avg_price
@@ -149,19 +321,19 @@ impl MoveStopLoss {
             if order.side == "BUY" {
                 avg_price =
                     (amount * avg_price + order.filled * order.price) / (amount + order.filled);
-                amount += order.amount;
+                amount += order.filled;
             } else if order.side == "SELL" {
                 avg_price =
                     (amount * avg_price - order.filled * order.price) / (amount - order.filled);
-                amount -= order.amount;
+                amount -= order.filled;
             }
 
-            if amount == balance.free {
+            if (amount - balance.free).abs() < lot {
                 break;
             }
         }
         // ignore low value position
-        if amount * avg_price < self.min_value {
+        if self.value(&pos.quote, amount * avg_price) < self.min_value {
             amount = 0f64;
             avg_price = 0f64;
         }
@@ -171,26 +343,213 @@ impl MoveStopLoss {
         } else {
             avg_price
         };
+        // bought more of a held position, the ladder and the time stop go
+        // on over the larger holding
+        let held = pos.price > 0f64 && amount > 0f64;
+        let (legs, initial) = if held && pos.initial > 0f64 {
+            (pos.legs, pos.initial + (amount - pos.amount).max(0f64))
+        } else {
+            (0, 0f64)
+        };
+        let since = match (held && pos.since > 0, amount > 0f64) {
+            (true, _) => pos.since,
+            (false, true) => now,
+            (false, false) => 0,
+        };
         Ok(Position {
-            symbol: pos.symbol.clone(),
             amount: amount,
             price: avg_price,
             high: high,
+            legs: legs,
+            initial: initial,
+            since: since,
+            ..pos.clone()
         })
     }
 
-    fn calc_withdraw_ratio(&self, profit_ratio: f64) -> f64 {
-        if profit_ratio < self.start_threshold {
-            return self.withdraw_ratio;
+    fn calc_withdraw_ratio(&self, trail: &Trail) -> f64 {
+        self.atr_stop
+            .as_ref()
+            .and_then(|atr_stop| atr_stop.withdraw_ratio(trail))
+            .unwrap_or_else(|| self.trailing.ratio(trail))
+    }
+
+    // ATR of `symbol` as a ratio of `price`
+    fn atr_ratio(&self, symbol: &str, price: f64, period: &str, length: usize) -> Option<f64> {
+        let klines = match self.client.get_kline(symbol, period, length as u16 + 1) {
+            Ok(klines) => klines,
+            Err(err) => {
+                warn!("{} get_kline error: {:?}", symbol, err);
+                return None;
+            }
+        };
+        indicators::atr(&klines, length).map(|atr| atr / price)
+    }
+
+    // select the watch list, symbols still holding a position stay
+    // watched even when they drop out of the universe
+    fn refresh_watch(&mut self) {
+        let ret = self.get_symbols();
+        debug!("get_symbols: {:?}", ret);
+        let symbol_info = match ret {
+            Ok(symbols) => symbols,
+            Err(err) => {
+                warn!("get_symbols error: {:?}", err);
+                return;
+            }
+        };
+        if self.rates.is_empty() {
+            self.refresh_rates();
+        }
+        let mut watch = self.universe.select(&*self.client, symbol_info.clone(), |info, amount| {
+            self.value(&info.quote, amount)
+        });
+        for pos in &self.positions {
+            if pos.amount > 0f64 && !watch.iter().any(|info| info.symbol == pos.symbol) {
+                if let Some(info) = symbol_info.iter().find(|info| info.symbol == pos.symbol) {
+                    watch.push(info.clone());
+                }
+            }
+        }
+        info!("watch {} symbols", watch.len());
+        debug!("watch_list: {:?}", watch);
+
+        self.positions = watch
+            .iter()
+            .map(|info| {
+                match self.positions.iter().find(|pos| pos.symbol == info.symbol) {
+                    Some(pos) => pos.clone(),
+                    None => Position {
+                        symbol: info.symbol.clone(),
+                        base: info.base.clone(),
+                        quote: info.quote.to_lowercase(),
+                        price: 0f64,
+                        amount: 0f64,
+                        high: 0f64,
+                        legs: 0,
+                        initial: 0f64,
+                        since: 0,
+                        resting: vec![],
+                    },
+                }
+            })
+            .collect();
+        self.watch = watch;
+    }
+
+    // book `amount` of `pos` bought at `buy_price` and sold at `price`
+    fn book(&mut self, pos: &Position, buy_price: f64, price: f64, amount: f64, order_id: &str) {
+        let profit = round_to(self.value(&pos.quote, (price - buy_price) * amount), 2);
+        notify::send(
+            EventKind::Fill,
+            &format!("{} sell order filled", pos.symbol),
+            &format!("sold {} at {}, order_id: {}, profit: {}", amount, price, order_id, profit),
+        );
+        self.history.push(Record {
+            symbol: pos.symbol.clone(),
+            buy_price: buy_price,
+            sell_price: price,
+            amount: amount,
+            profit: profit,
+            order_id: order_id.into(),
+        });
+        self.total_profit += profit;
+    }
+
+    // sell `amount` of the position below the bid, the amount executed so
+    // far once the order is placed. Only that much is booked, the rest
+    // when a later tick sees it fill
+    fn exit(
+        &mut self,
+        pos: &mut Position,
+        bid: f64,
+        amount: f64,
+        reason: &str,
+        entry: &mut AuditEntry,
+    ) -> Option<f64> {
+        let price = round_same(bid, bid * 0.95);
+        let label = format!("move_stoploss {}", reason);
+        let oid = send_order(
+            &*self.client,
+            &label,
+            &pos.symbol,
+            price,
+            amount,
+            ORDER_ACTION_SELL,
+            ORDER_TYPE_LIMIT,
+        );
+        entry.order(ORDER_ACTION_SELL, price, amount, &oid);
+        if let Ok(oid) = &oid {
+            notify::send(
+                EventKind::Placed,
+                &format!("{} sell order placed", pos.symbol),
+                &format!("sell {} at {}, order_id: {}", amount, price, oid),
+            );
+        }
+        let oid = oid.ok()?;
+        let filled = match self.client.get_order(&oid) {
+            Ok(order) => order.filled,
+            Err(err) => {
+                warn!("{} get_order {} error: {:?}", pos.symbol, oid, err);
+                0f64
+            }
+        };
+        let profit = round_to(self.value(&pos.quote, (bid - pos.price) * amount), 2);
+        notify::send(
+            EventKind::Stop,
+            &format!("{} {} triggered", pos.symbol, reason),
+            &format!(
+                "price: {}, high: {}, buy_price: {}, amount: {}, profit: {}",
+                bid, pos.high, pos.price, amount, profit
+            ),
+        );
+        if filled > 0f64 {
+            self.book(pos, pos.price, bid, filled, &oid);
+        }
+        if filled < amount {
+            pos.resting.push(Resting {
+                order_id: oid,
+                buy_price: pos.price,
+                filled: filled,
+                misses: 0,
+            });
+        }
+        Some(filled)
+    }
+
+    // book what the resting sells filled since the last tick, they are
+    // dropped once done or after MAX_MISSES failed lookups
+    fn check_resting(&mut self, pos: &mut Position) {
+        for resting in std::mem::take(&mut pos.resting) {
+            let order = match self.client.get_order(&resting.order_id) {
+                Ok(order) => order,
+                Err(err) => {
+                    warn!("{} get_order {} error: {:?}", pos.symbol, resting.order_id, err);
+                    if resting.misses + 1 < MAX_MISSES {
+                        pos.resting.push(Resting {
+                            misses: resting.misses + 1,
+                            ..resting
+                        });
+                    }
+                    continue;
+                }
+            };
+            if order.filled > resting.filled {
+                let amount = order.filled - resting.filled;
+                self.book(pos, resting.buy_price, order.price, amount, &resting.order_id);
+            }
+            if order.status == "NEW" || order.status == "PARTIALLY_FILLED" {
+                pos.resting.push(Resting {
+                    filled: order.filled,
+                    misses: 0,
+                    ..resting
+                });
+            }
         }
-        // y = (10*x-10*a+1)/10*x
-        return (round_to(profit_ratio * 10f64, 0) - round_to(self.start_threshold * 10f64, 0)
-            + 1f64)
-            / round_to(profit_ratio * 10f64, 0);
     }
 
-    fn check_move_stoploss(&mut self, pos: &Position) -> APIResult<()> {
-        if pos.amount * pos.price < self.min_value {
+    fn check_move_stoploss(&mut self, pos: &mut Position) -> APIResult<()> {
+        if self.value(&pos.quote, pos.amount * pos.price) < self.min_value {
             return Ok(());
         }
         // get current price
@@ -198,9 +557,28 @@ impl MoveStopLoss {
         let diff_ratio = (ticker.bid.price - pos.price) / pos.price;
         let high_ratio = (pos.high - pos.price) / pos.price;
 
-        let stoploss_price = round_same(ticker.bid.price, pos.price * (1f64 + self.stoploss));
+        // volatility of the symbol, the atr_stop one if both are set
+        let atr = match (&self.atr_stop, self.trailing.atr()) {
+            (Some(atr_stop), _) => {
+                self.atr_ratio(&pos.symbol, pos.price, &atr_stop.period, atr_stop.length)
+            }
+            (None, Some((period, length))) => {
+                self.atr_ratio(&pos.symbol, pos.price, period, length)
+            }
+            (None, None) => None,
+        };
+        let stoploss = match &self.atr_stop {
+            Some(atr_stop) => atr_stop.stoploss(atr, self.stoploss),
+            None => self.stoploss,
+        };
+        let stoploss_price = round_same(ticker.bid.price, pos.price * (1f64 + stoploss));
         // calc withdraw ratio
-        let withdraw_ratio = self.calc_withdraw_ratio(diff_ratio);
+        let trail = Trail {
+            profit: diff_ratio,
+            high: high_ratio,
+            atr: atr,
+        };
+        let withdraw_ratio = self.calc_withdraw_ratio(&trail);
         let withdraw_price = round_same(
             ticker.bid.price,
             pos.price * (1f64 + withdraw_ratio * high_ratio),
@@ -219,59 +597,179 @@ impl MoveStopLoss {
             self.total_profit, self.history
         );
 
-        let profit = round_to((ticker.bid.price - pos.price) * pos.amount, 2);
+        let profit = round_to(
+            self.value(&pos.quote, (ticker.bid.price - pos.price) * pos.amount),
+            2,
+        );
+        let mut entry = AuditEntry::new(&self.name(), &pos.symbol, ticker.timestamp);
+        entry.price = ticker.bid.price;
+        entry.amount = pos.amount;
+        entry.entry_price = pos.price;
+        entry
+            .value("high", pos.high)
+            .value("diff_ratio", diff_ratio)
+            .value("high_ratio", high_ratio)
+            .value("stoploss", stoploss)
+            .value("stoploss_price", stoploss_price)
+            .value("start_threshold", self.start_threshold)
+            .value("withdraw_ratio", withdraw_ratio)
+            .value("withdraw_price", withdraw_price)
+            .value("atr", trail.atr.unwrap_or(0f64))
+            .value("profit", profit);
+        let mut decisions = vec![];
+        let labels = [("symbol", pos.symbol.as_str()), ("quote", pos.quote.as_str())];
+        metrics::set_gauge("rsquant_position_amount", "Position amount", &labels, pos.amount);
+        metrics::set_gauge(
+            "rsquant_position_value",
+            "Position value in the reporting currency",
+            &labels,
+            self.value(&pos.quote, pos.amount * ticker.bid.price),
+        );
+        metrics::set_gauge(
+            "rsquant_unrealised_pnl",
+            "Unrealised profit of the position in the reporting currency",
+            &labels,
+            self.value(&pos.quote, (ticker.bid.price - pos.price) * pos.amount),
+        );
 
-        // stoploss
-        if diff_ratio <= self.stoploss {
+        // stoploss, lifted to break-even once the gain was reached
+        let breakeven = self
+            .breakeven
+            .as_ref()
+            .filter(|breakeven| high_ratio >= breakeven.after && breakeven.offset > stoploss)
+            .map(|breakeven| breakeven.offset);
+        let (stoploss, reason) = match breakeven {
+            Some(offset) => (offset, "breakeven"),
+            None => (stoploss, "stoploss"),
+        };
+        entry.value("breakeven", breakeven.unwrap_or(0f64));
+        if diff_ratio <= stoploss {
             // sell all
-            let price = round_same(ticker.bid.price, ticker.bid.price * 0.95);
-            let oid = self.client.create_order(
-                &pos.symbol,
-                price,
-                pos.amount,
-                ORDER_ACTION_SELL,
-                ORDER_TYPE_LIMIT,
-            );
-            info!(
-                "{:?} stoploss triggered, sell {:?} at {:?}, order_id: {:?}",
-                pos.symbol, price, pos.amount, oid
-            );
-            self.history.push(Record {
-                symbol: pos.symbol.clone(),
-                buy_price: pos.price,
-                sell_price: ticker.bid.price,
-                amount: pos.amount,
-                profit: profit,
-            });
-            self.total_profit += profit;
+            if self.exit(pos, ticker.bid.price, pos.amount, reason, &mut entry).is_some() {
+                pos.amount = 0f64;
+            }
+            decisions.push(reason);
         }
-        if high_ratio >= self.start_threshold {
+        // held too long without reaching the target
+        if let Some(time_stop) = &self.time_stop {
+            let held = timestamp(&ticker).saturating_sub(pos.since);
+            entry.value("held_hours", held as f64 / 3_600_000f64);
+            if decisions.is_empty()
+                && pos.since > 0
+                && held >= time_stop.after
+                && high_ratio < time_stop.target
+            {
+                if self.exit(pos, ticker.bid.price, pos.amount, "time_stop", &mut entry).is_some() {
+                    pos.amount = 0f64;
+                }
+                decisions.push("time_stop");
+            }
+        }
+        let stopped = !decisions.is_empty();
+        // scale out, the rest keeps trailing
+        while !stopped && pos.legs < self.take_profit.len() {
+            let leg = &self.take_profit[pos.legs];
+            if diff_ratio < leg.profit {
+                break;
+            }
+            if pos.legs == 0 {
+                pos.initial = pos.amount;
+            }
+            let precision = self.precision(&pos.symbol);
+            let mut amount = round_to(pos.initial * leg.ratio, precision).min(pos.amount);
+            // don't leave dust behind
+            if self.value(&pos.quote, (pos.amount - amount) * ticker.bid.price) < self.min_value {
+                amount = pos.amount;
+            }
+            let filled = match self.exit(pos, ticker.bid.price, amount, "take_profit", &mut entry) {
+                Some(filled) => filled,
+                None => break,
+            };
+            // average cost: the units left keep their entry price
+            pos.amount -= filled;
+            pos.legs += 1;
+            if !decisions.contains(&"take_profit") {
+                decisions.push("take_profit");
+            }
+            if pos.amount <= 0f64 {
+                break;
+            }
+        }
+        if !stopped && pos.amount > 0f64 && high_ratio >= self.start_threshold {
             if diff_ratio <= high_ratio * withdraw_ratio {
                 // sell all
-                let price = round_same(ticker.bid.price, ticker.bid.price * 0.95);
-                let oid = self.client.create_order(
-                    &pos.symbol,
-                    price,
-                    pos.amount,
-                    ORDER_ACTION_SELL,
-                    ORDER_TYPE_LIMIT,
-                );
-                info!(
-                    "{:?} profit withdraw triggered, sell {:?} at {:?}, order_id: {:?}",
-                    pos.symbol, price, pos.amount, oid
-                );
-                self.history.push(Record {
-                    symbol: pos.symbol.clone(),
-                    buy_price: pos.price,
-                    sell_price: ticker.bid.price,
-                    amount: pos.amount,
-                    profit: profit,
-                });
-                self.total_profit += profit;
+                if self.exit(pos, ticker.bid.price, pos.amount, "withdraw", &mut entry).is_some() {
+                    pos.amount = 0f64;
+                }
+                decisions.push("withdraw");
             }
         }
+        if !decisions.is_empty() {
+            entry.decision = decisions.join(",");
+        }
+        self.audit.record(&entry);
         Ok(())
     }
+}
+
+impl Strategy for MoveStopLoss {
+    fn new(config_path: &str) -> Box<dyn Strategy> {
+        let file = fs::File::open(config_path).expect("file should open read only");
+        let config: Value = serde_json::from_reader(file).expect("file should be proper json");
+        let client = exchange::binance(&config);
+        Self::from_config(&config, Box::new(client))
+    }
+
+    fn from_config(config: &Value, client: Box<dyn Client>) -> Box<dyn Strategy> {
+        let quotes = Self::quotes(config);
+        let currency = Self::currency(config);
+        let min_value = config["min_value"].as_f64().unwrap();
+        let stoploss = config["stoploss"].as_f64().unwrap();
+        let start_threshold = config["start_threshold"].as_f64().unwrap();
+        let withdraw_ratio = config["withdraw_ratio"].as_f64().unwrap();
+
+        Box::new(MoveStopLoss {
+            config: config.clone(),
+            client: client,
+            watch: vec![],
+            positions: vec![],
+            balances: vec![],
+            history: vec![],
+
+            total_profit: 0f64,
+            audit: AuditLog::from_config(config),
+            rates: HashMap::new(),
+            universe: Universe::from_config(&config["universe"], &config["ignore"]),
+            ticks: 0,
+            take_profit: serde_json::from_value(config["take_profit"].clone()).unwrap_or_default(),
+            trailing: Curve::from_config(&config["trailing"], start_threshold, withdraw_ratio)
+                .unwrap_or_else(|err| {
+                    warn!("trailing config error: {}, using the default curve", err);
+                    Curve::from_config(&Value::Null, start_threshold, withdraw_ratio).unwrap()
+                }),
+            atr_stop: AtrStop::from_config(&config["atr_stop"]).unwrap_or_else(|err| {
+                warn!("atr_stop config error: {}, using the fixed stoploss", err);
+                None
+            }),
+            breakeven: Breakeven::from_config(&config["breakeven"]).unwrap_or_else(|err| {
+                warn!("breakeven config error: {}, disabled", err);
+                None
+            }),
+            time_stop: TimeStop::from_config(&config["time_stop"]).unwrap_or_else(|err| {
+                warn!("time_stop config error: {}, disabled", err);
+                None
+            }),
+            quotes: quotes,
+            currency: currency,
+            min_value: min_value,
+            stoploss: stoploss,
+            start_threshold: start_threshold,
+        })
+    }
+
+    fn init(&mut self) {
+        self.refresh_watch();
+    }
 
     fn on_tick(&mut self) {
         let ret = self.client.get_all_balances();
@@ -279,16 +777,28 @@ impl MoveStopLoss {
             self.balances = balances;
         } else {
             warn!("get_all_balances error: {:?}", ret);
+            notify::send(EventKind::Error, "get_all_balances error", &format!("{:?}", ret));
             return;
         }
+        self.refresh_rates();
+        self.ticks += 1;
+        if self.universe.due(self.ticks) {
+            self.refresh_watch();
+        }
+        metrics::clear("rsquant_position_amount");
+        metrics::clear("rsquant_position_value");
+        metrics::clear("rsquant_unrealised_pnl");
         self.positions = self
             .positions
             .clone()
             .iter()
             .map(|pos| {
+                if !self.rates.contains_key(&pos.quote) {
+                    return pos.clone();
+                }
                 let new_pos = self.refresh_position(&pos);
                 //info!("new_pos: {:?}", new_pos);
-                let new_pos = match new_pos {
+                let mut new_pos = match new_pos {
                     Ok(new_pos) => new_pos,
                     Err(err) => {
                         warn!("refresh_position error: {:?}", err);
@@ -298,66 +808,409 @@ impl MoveStopLoss {
                 if new_pos.amount > 0f64 {
                     debug!("old_pos: {:?}, new_pos: {:?}", pos, new_pos);
                 }
-                let ret = self.check_move_stoploss(&new_pos);
+                self.check_resting(&mut new_pos);
+                let ret = self.check_move_stoploss(&mut new_pos);
                 if let Err(err) = ret {
                     warn!("check_move_stoploss error: {:?}", err);
+                    notify::send(
+                        EventKind::Error,
+                        &format!("{} check_move_stoploss error", new_pos.symbol),
+                        &format!("{:?}", err),
+                    );
                 }
                 new_pos
             })
             .collect();
+        metrics::set_gauge(
+            "rsquant_total_profit",
+            "Realised profit since start in the reporting currency",
+            &[],
+            self.total_profit,
+        );
     }
-}
 
-impl Strategy for MoveStopLoss {
-    fn new(config_path: &str) -> Box<dyn Strategy> {
-        let file = fs::File::open(config_path).expect("file should open read only");
-        let config: Value = serde_json::from_reader(file).expect("file should be proper json");
-        let quote = config["quote"].as_str().unwrap();
-        let apikey = config["apikey"].as_str().unwrap();
-        let secret_key = config["secret_key"].as_str().unwrap();
-        let host = config["host"].as_str().unwrap();
-        let min_value = config["min_value"].as_f64().unwrap();
-        let stoploss = config["stoploss"].as_f64().unwrap();
-        let start_threshold = config["start_threshold"].as_f64().unwrap();
-        let withdraw_ratio = config["withdraw_ratio"].as_f64().unwrap();
+    fn run_forever(&mut self) {
+        let config = self.config.clone();
+        runner::run(self, &config);
+    }
 
-        Box::new(MoveStopLoss {
-            config: config.clone(),
-            client: Binance::new(Some(apikey.into()), Some(secret_key.into()), host.into()),
-            watch: vec![],
-            positions: vec![],
-            balances: vec![],
-            history: vec![],
+    fn name(&self) -> String {
+        "move_stoploss".into()
+    }
 
-            total_profit: 0f64,
-            quote: quote.into(),
-            min_value: min_value,
-            stoploss: stoploss,
-            start_threshold: start_threshold,
-            withdraw_ratio: withdraw_ratio,
+    fn stringify(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn state(&self) -> Value {
+        let positions: Vec<&Position> = self
+            .positions
+            .iter()
+            .filter(|pos| pos.amount > 0f64 || !pos.resting.is_empty())
+            .collect();
+        json!({
+            "strategy": self.name(),
+            "currency": self.currency,
+            "positions": positions,
+            "history": self.history,
+            "total_profit": self.total_profit,
         })
     }
 
-    fn run_forever(&mut self) {
-        self.init();
-        loop {
-            self.on_tick();
-            thread::sleep(time::Duration::from_secs(60));
+    fn restore(&mut self, state: &Value) {
+        if let Ok(history) = serde_json::from_value(state["history"].clone()) {
+            self.history = history;
+        }
+        self.total_profit = state["total_profit"].as_f64().unwrap_or(0f64);
+        let saved: Vec<Position> =
+            serde_json::from_value(state["positions"].clone()).unwrap_or_default();
+        for saved in saved {
+            match self.positions.iter_mut().find(|pos| pos.symbol == saved.symbol) {
+                Some(pos) => {
+                    pos.amount = saved.amount;
+                    pos.price = saved.price;
+                    pos.high = saved.high;
+                    pos.legs = saved.legs;
+                    pos.initial = saved.initial;
+                    pos.since = saved.since;
+                    pos.resting = saved.resting;
+                }
+                // held but outside the universe, keep protecting it
+                None if !saved.base.is_empty() => self.positions.push(saved),
+                None => {}
+            }
         }
     }
 
-    fn name(&self) -> String {
-        "move_stoploss".into()
+    // cancel the sell orders the robot placed and left open, the user's
+    // own orders on the same symbols stay untouched
+    fn cancel_orders(&mut self) {
+        let resting = self
+            .positions
+            .iter()
+            .flat_map(|pos| pos.resting.iter().map(move |r| (&pos.symbol, &r.order_id)));
+        let ours: Vec<(&String, &String)> = self
+            .history
+            .iter()
+            .filter(|record| !record.order_id.is_empty())
+            .map(|record| (&record.symbol, &record.order_id))
+            .chain(resting)
+            .collect();
+        let mut symbols: Vec<&String> = ours.iter().map(|(symbol, _)| *symbol).collect();
+        symbols.sort();
+        symbols.dedup();
+        for symbol in symbols {
+            let orders = match self.client.get_open_orders(symbol) {
+                Ok(orders) => orders,
+                Err(err) => {
+                    warn!("get_open_orders error: {:?}", err);
+                    continue;
+                }
+            };
+            for order in orders {
+                if ours.contains(&(symbol, &order.order_id)) {
+                    let ret = self.client.cancel(&order.order_id);
+                    info!("cancel {} order {}: {:?}", symbol, order.order_id, ret);
+                }
+            }
+        }
     }
 
-    fn stringify(&self) -> String {
-        format!("{:?}", self)
+    fn summary(&self) -> String {
+        let holding = self
+            .positions
+            .iter()
+            .filter(|pos| self.value(&pos.quote, pos.amount * pos.price) >= self.min_value)
+            .count();
+        format!(
+            "positions: {}, records: {}, total_profit: {}",
+            holding,
+            self.history.len(),
+            self.total_profit
+        )
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::backtest::data::{
+        fixture::{bars, replay, sim},
+        symbol_info,
+    };
+    use rsex::{
+        constant::{ORDER_ACTION_BUY, ORDER_TYPE_MARKET},
+        models::Kline,
+        traits::SpotRest,
+    };
+    use std::sync::Arc;
+
+    // rises 50% in 20 bars, then falls back
+    fn klines(start: f64) -> Arc<Vec<Kline>> {
+        let closes: Vec<f64> = (0..40)
+            .map(|i| {
+                let ratio = if i < 20 { i as f64 } else { 40f64 - i as f64 };
+                start * (1f64 + 0.025 * ratio)
+            })
+            .collect();
+        bars(&closes, 0, 60_000)
+    }
+
+    #[test]
+    fn test_multiple_quotes() {
+        let data = vec![
+            (symbol_info("ETH", "USDT"), klines(100f64)),
+            (symbol_info("XRP", "BTC"), klines(0.0001)),
+            (symbol_info("BTC", "USDT"), bars(&[10000f64], 0, 60_000)),
+        ];
+        let exchange = sim(data, &[("ETH", 10f64), ("XRP", 1000f64)], 0f64);
+        let config = json!({
+            "quote": ["usdt", "btc"],
+            "ignore": [],
+            "min_value": 10,
+            "stoploss": -0.05,
+            "start_threshold": 0.3,
+            "withdraw_ratio": 0.5,
+        });
+        assert!(MoveStopLoss::validate(&config).is_empty());
+        let mut robot = MoveStopLoss::from_config(&config, Box::new(exchange.clone()));
+        replay(&[&exchange], &mut *robot);
+
+        let state = robot.state();
+        assert_eq!(state["currency"], "USDT");
+        let history = state["history"].as_array().unwrap();
+        assert_eq!(history.len(), 2);
+        let eth = history.iter().find(|r| r["symbol"] == "ETHUSDT").unwrap();
+        let xrp = history.iter().find(|r| r["symbol"] == "XRPBTC").unwrap();
+        // same move and value on both, the btc profit is reported in usdt
+        let ratio = |r: &Value, start: f64| r["sell_price"].as_f64().unwrap() / start;
+        assert!((ratio(eth, 100f64) - ratio(xrp, 0.0001)).abs() < 1e-9);
+        assert!((eth["profit"].as_f64().unwrap() - xrp["profit"].as_f64().unwrap()).abs() <= 0.02);
+    }
+
+    #[test]
+    fn test_take_profit() {
+        let data = vec![(symbol_info("ETH", "USDT"), klines(100f64))];
+        let exchange = sim(data, &[("ETH", 10f64)], 0f64);
+        let config = json!({
+            "quote": "usdt",
+            "ignore": [],
+            "min_value": 10,
+            "stoploss": -0.05,
+            "start_threshold": 0.3,
+            "withdraw_ratio": 0.5,
+            "take_profit": [{"profit": 0.2, "ratio": 0.3}, {"profit": 0.4, "ratio": 0.3}],
+        });
+        assert!(MoveStopLoss::validate(&config).is_empty());
+        let mut robot = MoveStopLoss::from_config(&config, Box::new(exchange.clone()));
+        replay(&[&exchange], &mut *robot);
+
+        let state = robot.state();
+        let history = state["history"].as_array().unwrap();
+        let legs: Vec<(f64, f64)> = history
+            .iter()
+            .map(|r| (r["amount"].as_f64().unwrap(), r["sell_price"].as_f64().unwrap()))
+            .collect();
+        // 3 at +20%, 3 at +40%, the rest trails from the +47.5% high
+        assert_eq!(legs.len(), 3);
+        assert_eq!(legs[0], (3f64, 120f64));
+        assert_eq!(legs[1], (3f64, 140f64));
+        assert_eq!(legs[2].0, 4f64);
+        assert!(legs[2].1 < 140f64);
+        assert_eq!(exchange.get_balance("ETH").unwrap().free, 0f64);
+
+        let invalid = json!({
+            "take_profit": [{"profit": 0.4, "ratio": 0.6}, {"profit": 0.2, "ratio": 0.6}],
+        });
+        let errors = MoveStopLoss::validate(&invalid);
+        assert_eq!(errors.iter().filter(|e| e.starts_with("take_profit")).count(), 2);
+    }
+
+    #[test]
+    fn test_take_profit_after_sell() {
+        let data = vec![(symbol_info("ETH", "USDT"), klines(100f64))];
+        let exchange = sim(data, &[("ETH", 10f64)], 0f64);
+        let config = json!({
+            "quote": "usdt",
+            "ignore": [],
+            "min_value": 10,
+            "stoploss": -0.05,
+            "start_threshold": 0.3,
+            "withdraw_ratio": 0.5,
+            "take_profit": [{"profit": 0.2, "ratio": 0.3}, {"profit": 0.4, "ratio": 0.3}],
+        });
+        let mut robot = MoveStopLoss::from_config(&config, Box::new(exchange.clone()));
+        for (i, timestamp) in exchange.timestamps().into_iter().enumerate() {
+            exchange.advance(timestamp);
+            if i == 0 {
+                robot.init();
+            }
+            // sold by hand after the first leg
+            if i == 10 {
+                exchange
+                    .create_order("ETHUSDT", 0f64, 1f64, ORDER_ACTION_SELL, ORDER_TYPE_MARKET)
+                    .unwrap();
+            }
+            robot.on_tick();
+        }
+
+        let state = robot.state();
+        let amounts: Vec<f64> = state["history"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["amount"].as_f64().unwrap())
+            .collect();
+        // the ladder goes on with the second leg, nothing is sold twice
+        assert_eq!(amounts, vec![3f64, 3f64, 3f64]);
+    }
+
+    #[test]
+    fn test_cancel_orders() {
+        let data = vec![(symbol_info("ETH", "USDT"), klines(100f64))];
+        let exchange = sim(data, &[("USDT", 1000f64)], 0f64);
+        exchange.advance(0);
+        let manual = exchange
+            .create_order("ETHUSDT", 50f64, 1f64, ORDER_ACTION_BUY, ORDER_TYPE_LIMIT)
+            .unwrap();
+        let placed = exchange
+            .create_order("ETHUSDT", 60f64, 1f64, ORDER_ACTION_BUY, ORDER_TYPE_LIMIT)
+            .unwrap();
+        let config = json!({
+            "quote": "usdt",
+            "ignore": [],
+            "min_value": 10,
+            "stoploss": -0.05,
+            "start_threshold": 0.3,
+            "withdraw_ratio": 0.5,
+        });
+        let mut robot = MoveStopLoss::from_config(&config, Box::new(exchange.clone()));
+        robot.restore(&json!({"history": [{"symbol": "ETHUSDT", "buy_price": 100, "sell_price": 60,
+            "amount": 1, "profit": 0, "order_id": placed}]}));
+        robot.cancel_orders();
+        let open: Vec<String> = exchange
+            .get_open_orders("ETHUSDT")
+            .unwrap()
+            .into_iter()
+            .map(|order| order.order_id)
+            .collect();
+        assert_eq!(open, vec![manual]);
+    }
+
+    #[test]
+    fn test_resting_sell() {
+        let data = vec![(symbol_info("ETH", "USDT"), klines(100f64))];
+        let exchange = sim(data, &[("ETH", 10f64)], 0f64);
+        exchange.advance(0);
+        let placed = exchange
+            .create_order("ETHUSDT", 130f64, 1f64, ORDER_ACTION_SELL, ORDER_TYPE_LIMIT)
+            .unwrap();
+        let config = json!({
+            "quote": "usdt",
+            "ignore": [],
+            "min_value": 10,
+            "stoploss": -0.05,
+            "start_threshold": 0.3,
+            "withdraw_ratio": 0.5,
+        });
+        let mut robot = MoveStopLoss::from_config(&config, Box::new(exchange.clone()));
+        // a take-profit sell left resting at 130 when the state was saved
+        robot.restore(&json!({"positions": [{"symbol": "ETHUSDT", "base": "ETH", "quote": "usdt",
+            "amount": 9, "price": 100, "high": 100,
+            "resting": [{"order_id": placed, "buy_price": 100, "filled": 0}]}]}));
+        replay(&[&exchange], &mut *robot);
+
+        // booked once, when it filled, the rest trails out
+        let state = robot.state();
+        let history = state["history"].as_array().unwrap();
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0]["order_id"], placed);
+        assert_eq!(history[0]["amount"], 1f64);
+        assert_eq!(history[0]["sell_price"], 130f64);
+        assert_eq!(history[0]["profit"], 30f64);
+        assert_eq!(history[1]["amount"], 9f64);
+    }
+
+    #[test]
+    fn test_atr_stop() {
+        // calm hourly bars, then a 1.5% dip
+        let closes: Vec<f64> = (0..32).map(|i| if i < 30 { 100f64 } else { 98.5 }).collect();
+        let klines: Vec<Kline> = bars(&closes, 0, 3_600_000)
+            .iter()
+            .map(|kline| Kline {
+                high: kline.close + 0.25,
+                low: kline.close - 0.25,
+                ..kline.clone()
+            })
+            .collect();
+        let run = |config: Value| {
+            let data = vec![(symbol_info("ETH", "USDT"), Arc::new(klines.clone()))];
+            let exchange = sim(data, &[("ETH", 10f64)], 0f64);
+            let mut robot = MoveStopLoss::from_config(&config, Box::new(exchange.clone()));
+            replay(&[&exchange], &mut *robot);
+            robot.state()["history"].as_array().unwrap().len()
+        };
+        let mut config = json!({
+            "quote": "usdt",
+            "ignore": [],
+            "min_value": 10,
+            "stoploss": -0.05,
+            "start_threshold": 0.3,
+            "withdraw_ratio": 0.5,
+        });
+        assert_eq!(run(config.clone()), 0);
+        // ~0.5% ATR, stopped out 2 ATRs below the entry
+        config["atr_stop"] = json!({"period": "1h", "length": 14, "stop": 2});
+        assert!(MoveStopLoss::validate(&config).is_empty());
+        assert_eq!(run(config), 1);
+    }
+
+    // hourly bars closing at `closes`, 10 ETH bought at the first one
+    fn hourly(closes: &[f64], config: &Value) -> Vec<Value> {
+        let data = vec![(symbol_info("ETH", "USDT"), bars(closes, 1_600_000_000_000, 3_600_000))];
+        let exchange = sim(data, &[("ETH", 10f64)], 0f64);
+        let mut robot = MoveStopLoss::from_config(config, Box::new(exchange.clone()));
+        replay(&[&exchange], &mut *robot);
+        robot.state()["history"].as_array().unwrap().clone()
+    }
+
+    #[test]
+    fn test_breakeven_and_time_stop() {
+        let mut config = json!({
+            "quote": "usdt",
+            "ignore": [],
+            "min_value": 10,
+            "stoploss": -0.05,
+            "start_threshold": 0.3,
+            "withdraw_ratio": 0.5,
+        });
+        // up 12%, back to the entry
+        let closes = [100f64, 106f64, 112f64, 106f64, 100.1, 99f64];
+        assert!(hourly(&closes, &config).is_empty());
+        config["breakeven"] = json!({"after": 0.1, "offset": 0.002});
+        let history = hourly(&closes, &config);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["sell_price"], 100.1);
+
+        // going nowhere for 5 hours
+        let closes = [100f64, 101f64, 102f64, 101f64, 100f64, 101f64, 102f64];
+        config["time_stop"] = json!({"after": "5h", "target": 0.05});
+        assert!(MoveStopLoss::validate(&config).is_empty());
+        let history = hourly(&closes, &config);
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["sell_price"], 101f64);
+        config["time_stop"]["target"] = json!(0.02);
+        assert!(hourly(&closes, &config).is_empty());
+
+        // a take-profit leg doesn't restart the clock
+        config["time_stop"]["target"] = json!(0.05);
+        config["take_profit"] = json!([{"profit": 0.02, "ratio": 0.5}]);
+        let history = hourly(&closes, &config);
+        assert_eq!(history.len(), 2);
+        assert_eq!(history[0]["amount"], 5f64);
+        assert_eq!(history[0]["sell_price"], 102f64);
+        assert_eq!(history[1]["sell_price"], 101f64);
+    }
 
     #[test]
     fn test_move_stoploss() {
@@ -366,7 +1219,7 @@ mod test {
         info!("config file: {}", config_path);
 
         let mut robot = MoveStopLoss::new(&config_path);
-        info!("robot: {:?}", robot);
+        info!("robot: {:?}", robot.stringify());
         robot.run_forever();
     }
 }
@@ -0,0 +1,590 @@
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, fs};
+use rsex::{
+    constant::{ORDER_ACTION_BUY, ORDER_ACTION_SELL, ORDER_TYPE_MARKET},
+    models::{Kline, SymbolInfo},
+};
+use crate::{
+    exchange, metrics,
+    notify::{self, EventKind},
+    runner,
+    strategies::send_order,
+    traits::{Client, Strategy},
+    utils::{check_keys, period_ms, round_to},
+};
+
+// least squares fit of ys = alpha + beta * xs, as (beta, alpha)
+pub fn hedge_ratio(xs: &[f64], ys: &[f64]) -> Option<(f64, f64)> {
+    let n = xs.len().min(ys.len());
+    if n < 3 {
+        return None;
+    }
+    let (xs, ys) = (&xs[xs.len() - n..], &ys[ys.len() - n..]);
+    let mean_x = xs.iter().sum::<f64>() / n as f64;
+    let mean_y = ys.iter().sum::<f64>() / n as f64;
+    let cov: f64 = xs
+        .iter()
+        .zip(ys)
+        .map(|(x, y)| (x - mean_x) * (y - mean_y))
+        .sum();
+    let var: f64 = xs.iter().map(|x| (x - mean_x).powi(2)).sum();
+    if var <= 0f64 {
+        return None;
+    }
+    let beta = cov / var;
+    Some((beta, mean_y - beta * mean_x))
+}
+
+// how many standard deviations the last value is from the mean
+pub fn zscore(values: &[f64]) -> Option<f64> {
+    let n = values.len() as f64;
+    if values.len() < 3 {
+        return None;
+    }
+    let mean = values.iter().sum::<f64>() / n;
+    let std = (values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / (n - 1f64)).sqrt();
+    if std <= 0f64 {
+        return None;
+    }
+    Some((values[values.len() - 1] - mean) / std)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Leg {
+    symbol: String,
+    side: String,
+    amount: f64,
+    price: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Position {
+    // 1 when y was rich (y sold, x bought), -1 the other way round
+    direction: i8,
+    entry_z: f64,
+    legs: Vec<Leg>,
+    // set by an exit that left legs open: they are traded back on the
+    // next ticks whatever the z-score, the profit so far carried along
+    #[serde(default)]
+    reason: String,
+    #[serde(default)]
+    exit_z: f64,
+    #[serde(default)]
+    profit: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    direction: i8,
+    entry_z: f64,
+    exit_z: f64,
+    profit: f64,
+    reason: String,
+}
+
+// trades the spread log(y) - beta * log(x), beta fitted over the last
+// `lookback` bars. Over `entry_z` it sells the rich coin out of the
+// holdings and buys the cheap one, beta times its value of x or 1 / beta
+// of it of y, under `exit_z` it trades back, past `stop_z` it gives up and
+// waits for the z-score to fall inside `entry_z` before trading again
+#[derive(Debug)]
+pub struct Pairs {
+    config: Value,
+    client: Box<dyn Client>,
+    info: HashMap<String, SymbolInfo>,
+    position: Option<Position>,
+    cooldown: bool,
+    last: Option<(f64, f64)>,
+    history: Vec<Record>,
+    total_profit: f64,
+
+    x: String,
+    y: String,
+    period: String,
+    lookback: u16,
+    entry_z: f64,
+    exit_z: f64,
+    stop_z: f64,
+    value: f64,
+    fee: f64,
+}
+
+impl Pairs {
+    pub fn validate(config: &Value) -> Vec<String> {
+        let mut errors = check_keys(
+            config,
+            &["x", "y", "period"],
+            &["lookback", "entry_z", "value"],
+        );
+        if config["period"].as_str().and_then(period_ms).is_none() {
+            errors.push("period: expect a kline period like \"1h\"".into());
+        }
+        if config["lookback"].as_u64().unwrap_or(0) < 10 {
+            errors.push("lookback: expect at least 10".into());
+        }
+        let entry = config["entry_z"].as_f64().unwrap_or(0f64);
+        let exit = config["exit_z"].as_f64().unwrap_or(0.5);
+        let stop = config["stop_z"].as_f64().unwrap_or(f64::MAX);
+        if !(exit >= 0f64 && exit < entry && entry < stop) {
+            errors.push("entry_z, exit_z, stop_z: expect 0 <= exit_z < entry_z < stop_z".into());
+        }
+        errors
+    }
+
+    // z-score of the latest bar and the hedge ratio
+    fn signal(&self) -> Option<(f64, f64)> {
+        let klines = |symbol: &str| -> Option<Vec<Kline>> {
+            match self.client.get_kline(symbol, &self.period, self.lookback) {
+                Ok(klines) => Some(klines),
+                Err(err) => {
+                    warn!("{} get_kline error: {:?}", symbol, err);
+                    None
+                }
+            }
+        };
+        let (xs, ys) = (klines(&self.x)?, klines(&self.y)?);
+        // bars both symbols have
+        let ys: HashMap<u64, f64> = ys.iter().map(|k| (k.timestamp, k.close)).collect();
+        let (xs, ys): (Vec<f64>, Vec<f64>) = xs
+            .iter()
+            .filter_map(|k| ys.get(&k.timestamp).map(|y| (k.close.ln(), y.ln())))
+            .unzip();
+        if xs.len() < self.lookback as usize {
+            return None;
+        }
+        let (beta, _) = hedge_ratio(&xs, &ys)?;
+        let spread: Vec<f64> = xs.iter().zip(&ys).map(|(x, y)| y - beta * x).collect();
+        Some((zscore(&spread)?, beta))
+    }
+
+    fn order(&self, symbol: &str, side: &str, amount: f64) -> Option<Leg> {
+        let info = self.info.get(symbol)?;
+        let amount = round_to(amount, info.amount_precision as u32);
+        if amount <= 0f64 || amount < info.min_amount {
+            return None;
+        }
+        let ticker = self.client.get_ticker(symbol).ok()?;
+        let price = if side == ORDER_ACTION_BUY {
+            ticker.ask.price
+        } else {
+            ticker.bid.price
+        };
+        if amount * price < info.min_value {
+            return None;
+        }
+        send_order(
+            &*self.client,
+            "pairs",
+            symbol,
+            price,
+            amount,
+            side,
+            ORDER_TYPE_MARKET,
+        )
+        .ok()?;
+        Some(Leg {
+            symbol: symbol.into(),
+            side: side.into(),
+            amount: amount,
+            price: price,
+        })
+    }
+
+    fn enter(&mut self, z: f64, beta: f64) {
+        let direction = if z > 0f64 { 1 } else { -1 };
+        let (rich, cheap) = if direction > 0 {
+            (self.y.clone(), self.x.clone())
+        } else {
+            (self.x.clone(), self.y.clone())
+        };
+        let base = match self.info.get(&rich) {
+            Some(info) => info.base.clone(),
+            None => return,
+        };
+        let (held, price) = match (
+            self.client.get_balance(&base),
+            self.client.get_ticker(&rich),
+        ) {
+            (Ok(balance), Ok(ticker)) => (balance.free, ticker.bid.price),
+            _ => return,
+        };
+        // spot only: the short leg sells what is held
+        let amount = (self.value / price).min(held);
+        let sold = match self.order(&rich, ORDER_ACTION_SELL, amount) {
+            Some(leg) => leg,
+            None => {
+                info!("pairs z {} but not enough {} to sell", z, base);
+                return;
+            }
+        };
+        // y moves beta times as much as x: y sold is hedged with beta times
+        // its value of x, x sold with 1 / beta of its value of y
+        let sold_value = sold.amount * sold.price;
+        let hedge = if direction > 0 {
+            sold_value * beta.abs()
+        } else {
+            sold_value / beta.abs()
+        };
+        let cheap_price = match self.client.get_ticker(&cheap) {
+            Ok(ticker) => ticker.ask.price,
+            Err(_) => 0f64,
+        };
+        let mut legs = vec![sold];
+        if cheap_price > 0f64 && hedge.is_finite() {
+            if let Some(leg) = self.order(&cheap, ORDER_ACTION_BUY, hedge / cheap_price) {
+                legs.push(leg);
+            }
+        }
+        if legs.len() < 2 {
+            warn!("pairs hedge leg {} failed, holding one leg", cheap);
+        }
+        info!("pairs enter at z {}, beta {}: {:?}", z, beta, legs);
+        notify::send(
+            EventKind::Fill,
+            &format!("pairs {}/{} entered", self.y, self.x),
+            &format!("z {:.2}, sold {}, bought {}", z, rich, cheap),
+        );
+        self.position = Some(Position {
+            direction: direction,
+            entry_z: z,
+            legs: legs,
+            reason: String::new(),
+            exit_z: 0f64,
+            profit: 0f64,
+        });
+    }
+
+    fn exit(&mut self, z: f64, reason: &str) {
+        let mut position = match self.position.take() {
+            Some(position) => position,
+            None => return,
+        };
+        if position.reason.is_empty() {
+            position.reason = reason.into();
+            position.exit_z = z;
+        }
+        let mut profit = 0f64;
+        let mut open = vec![];
+        for leg in &position.legs {
+            let side = if leg.side == ORDER_ACTION_BUY {
+                ORDER_ACTION_SELL
+            } else {
+                ORDER_ACTION_BUY
+            };
+            match self.order(&leg.symbol, side, leg.amount) {
+                Some(back) => {
+                    profit += if leg.side == ORDER_ACTION_SELL {
+                        (leg.price - back.price) * leg.amount
+                    } else {
+                        (back.price - leg.price) * leg.amount
+                    };
+                    profit -= (leg.price + back.price) * leg.amount * self.fee;
+                }
+                None => open.push(leg.clone()),
+            }
+        }
+        position.profit += profit;
+        if !open.is_empty() {
+            // try again next tick
+            warn!("pairs exit incomplete, {:?} still open", open);
+            self.position = Some(Position {
+                legs: open,
+                ..position
+            });
+            return;
+        }
+        let (reason, z, profit) = (position.reason, position.exit_z, position.profit);
+        info!("pairs {} at z {}, profit: {}", reason, z, profit);
+        notify::send(
+            if reason == "stop" {
+                EventKind::Stop
+            } else {
+                EventKind::Fill
+            },
+            &format!("pairs {}/{} {}", self.y, self.x, reason),
+            &format!("z {:.2}, profit {}", z, profit),
+        );
+        self.total_profit += profit;
+        metrics::set_gauge(
+            "rsquant_total_profit",
+            "Realised profit",
+            &[],
+            self.total_profit,
+        );
+        self.history.push(Record {
+            direction: position.direction,
+            entry_z: position.entry_z,
+            exit_z: z,
+            profit: profit,
+            reason: reason,
+        });
+    }
+
+    fn load_symbols(&mut self) {
+        match self.client.get_symbols() {
+            Ok(symbols) => {
+                for info in symbols {
+                    if info.symbol == self.x || info.symbol == self.y {
+                        self.info.insert(info.symbol.clone(), info);
+                    }
+                }
+            }
+            Err(err) => {
+                warn!("get_symbols error: {:?}", err);
+                return;
+            }
+        }
+        for symbol in &[&self.x, &self.y] {
+            if !self.info.contains_key(*symbol) {
+                warn!("pairs symbol {} not found", symbol);
+            }
+        }
+    }
+}
+
+impl Strategy for Pairs {
+    fn new(config_path: &str) -> Box<dyn Strategy> {
+        let file = fs::File::open(config_path).expect("file should open read only");
+        let config: Value = serde_json::from_reader(file).expect("file should be proper json");
+        let client = exchange::binance(&config);
+        Self::from_config(&config, Box::new(client))
+    }
+
+    fn from_config(config: &Value, client: Box<dyn Client>) -> Box<dyn Strategy> {
+        Box::new(Pairs {
+            config: config.clone(),
+            client: client,
+            info: HashMap::new(),
+            position: None,
+            cooldown: false,
+            last: None,
+            history: vec![],
+            total_profit: 0f64,
+
+            x: config["x"].as_str().unwrap().to_uppercase(),
+            y: config["y"].as_str().unwrap().to_uppercase(),
+            period: config["period"].as_str().unwrap().into(),
+            lookback: config["lookback"].as_u64().unwrap() as u16,
+            entry_z: config["entry_z"].as_f64().unwrap(),
+            exit_z: config["exit_z"].as_f64().unwrap_or(0.5),
+            stop_z: config["stop_z"].as_f64().unwrap_or(f64::MAX),
+            value: config["value"].as_f64().unwrap(),
+            fee: config["fee"].as_f64().unwrap_or(0.001),
+        })
+    }
+
+    fn init(&mut self) {
+        self.load_symbols();
+    }
+
+    fn on_tick(&mut self) {
+        // init couldn't load them, keep trying
+        if self.info.len() < 2 {
+            self.load_symbols();
+        }
+        // the legs an exit left open go back first, before any signal
+        if let Some(position) = &self.position {
+            if !position.reason.is_empty() {
+                let (z, reason) = (position.exit_z, position.reason.clone());
+                self.exit(z, &reason);
+                return;
+            }
+        }
+        let (z, beta) = match self.signal() {
+            Some(signal) => signal,
+            None => return,
+        };
+        self.last = Some((z, beta));
+        metrics::set_gauge("rsquant_pairs_zscore", "Z-score of the pair spread", &[], z);
+        match &self.position {
+            Some(position) => {
+                // the spread kept diverging in the direction traded
+                if z * position.direction as f64 >= self.stop_z {
+                    self.exit(z, "stop");
+                    self.cooldown = true;
+                } else if z.abs() <= self.exit_z || z * (position.direction as f64) < 0f64 {
+                    self.exit(z, "exit");
+                }
+            }
+            None => {
+                if self.cooldown && z.abs() < self.entry_z {
+                    self.cooldown = false;
+                }
+                if !self.cooldown && z.abs() >= self.entry_z && z.abs() < self.stop_z {
+                    self.enter(z, beta);
+                }
+            }
+        }
+    }
+
+    fn run_forever(&mut self) {
+        let config = self.config.clone();
+        runner::run(self, &config);
+    }
+
+    fn name(&self) -> String {
+        "pairs".into()
+    }
+
+    fn stringify(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn state(&self) -> Value {
+        json!({
+            "strategy": self.name(),
+            "zscore": self.last.map(|l| l.0),
+            "beta": self.last.map(|l| l.1),
+            "position": self.position,
+            "cooldown": self.cooldown,
+            "history": self.history,
+            "total_profit": self.total_profit,
+        })
+    }
+
+    fn restore(&mut self, state: &Value) {
+        if state["strategy"] != "pairs" {
+            return;
+        }
+        self.position = serde_json::from_value(state["position"].clone()).unwrap_or(None);
+        self.cooldown = state["cooldown"].as_bool().unwrap_or(false);
+        if let Ok(history) = serde_json::from_value(state["history"].clone()) {
+            self.history = history;
+        }
+        self.total_profit = state["total_profit"].as_f64().unwrap_or(0f64);
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "z: {:?}, position: {}, trades: {}, total_profit: {}",
+            self.last.map(|l| l.0),
+            self.position.is_some(),
+            self.history.len(),
+            self.total_profit
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backtest::data::{
+            fixture::{bars, replay, sim},
+            symbol_info,
+        },
+        exchange::SimExchange,
+    };
+    use rsex::traits::SpotRest;
+
+    #[test]
+    fn test_stats() {
+        let xs = [1f64, 2f64, 3f64, 4f64];
+        let ys = [3f64, 5f64, 7f64, 9f64];
+        assert_eq!(hedge_ratio(&xs, &ys), Some((2f64, 1f64)));
+        assert_eq!(hedge_ratio(&[1f64; 4], &ys), None);
+        let z = zscore(&[1f64, -1f64, 1f64, -1f64, 3f64]).unwrap();
+        assert!((z - 2.4 / 2.8f64.sqrt()).abs() < 1e-9);
+    }
+
+    // y follows x to the power `beta`, times `shock` from bar 40 on, with
+    // the robot restored from `saved`
+    fn run(beta: f64, shock: &[f64], saved: &Value) -> (SimExchange, Value) {
+        let hourly = |closes: Vec<f64>| bars(&closes, 0, 3_600_000);
+        let xs: Vec<f64> = (0..40 + shock.len())
+            .map(|i| 100f64 + 5f64 * (i as f64).sin())
+            .collect();
+        let ys: Vec<f64> = xs
+            .iter()
+            .enumerate()
+            .map(|(i, x)| {
+                let noise = 1f64 + 0.002 * (i as f64 * 1.7).cos();
+                let shock = if i >= 40 { shock[i - 40] } else { 1f64 };
+                50f64 * (x / 100f64).powf(beta) * noise * shock
+            })
+            .collect();
+        let data = vec![
+            (symbol_info("BTC", "USDT"), hourly(xs)),
+            (symbol_info("ETH", "USDT"), hourly(ys)),
+        ];
+        let exchange = sim(
+            data,
+            &[("BTC", 1f64), ("ETH", 2f64), ("USDT", 1000f64)],
+            0f64,
+        );
+        let config = json!({
+            "x": "btcusdt",
+            "y": "ethusdt",
+            "period": "1h",
+            "lookback": 30,
+            "entry_z": 2,
+            "exit_z": 0.5,
+            "stop_z": 4,
+            "value": 50,
+            "fee": 0,
+        });
+        assert!(Pairs::validate(&config).is_empty());
+        let mut robot = Pairs::from_config(&config, Box::new(exchange.clone()));
+        robot.restore(saved);
+        replay(&[&exchange], &mut *robot);
+        (exchange, robot.state())
+    }
+
+    #[test]
+    fn test_pairs() {
+        // eth jumps 3% against btc and comes back
+        let shock = [1f64, 1f64, 1.03, 1.03, 1f64, 1f64, 1f64];
+        let (exchange, state) = run(1f64, &shock, &Value::Null);
+        let history = state["history"].as_array().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["direction"], 1);
+        assert_eq!(history[0]["reason"], "exit");
+        assert!(state["total_profit"].as_f64().unwrap() > 0f64);
+        assert!(state["position"].is_null());
+        // never sold more eth than held
+        assert!(exchange.get_balance("ETH").unwrap().free > 0f64);
+
+        // keeps running away
+        let (_, state) = run(1f64, &[1f64, 1.03, 1.03, 1.2, 1.2, 1.2], &Value::Null);
+        let history = state["history"].as_array().unwrap();
+        assert_eq!(history[0]["reason"], "stop");
+        assert_eq!(history.len(), 1);
+        assert!(state["total_profit"].as_f64().unwrap() < 0f64);
+
+        // an exit that left the eth leg open buys it back on the first
+        // tick, before there are bars for a z-score, and books it once
+        // with the profit of the legs closed before
+        let saved = json!({"strategy": "pairs", "position": {"direction": 1, "entry_z": 2.5,
+            "legs": [{"symbol": "ETHUSDT", "side": "SELL", "amount": 0.5, "price": 60}],
+            "reason": "exit", "exit_z": 0.4, "profit": 1}});
+        let (_, state) = run(1f64, &[], &saved);
+        let history = state["history"].as_array().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["exit_z"], 0.4);
+        assert!((history[0]["profit"].as_f64().unwrap() - 6f64).abs() < 0.5);
+        assert!(state["position"].is_null());
+    }
+
+    #[test]
+    fn test_hedge_ratio() {
+        let value = |leg: &Value| leg["amount"].as_f64().unwrap() * leg["price"].as_f64().unwrap();
+        // eth moves twice as much as btc: 50 of eth sold, 100 of btc bought
+        let (_, state) = run(2f64, &[1f64, 1.03, 1.03], &Value::Null);
+        assert!((state["beta"].as_f64().unwrap() - 2f64).abs() < 0.05);
+        let position = &state["position"];
+        assert_eq!(position["direction"], 1);
+        assert_eq!(position["legs"][0]["symbol"], "ETHUSDT");
+        assert!((value(&position["legs"][0]) - 50f64).abs() < 0.5);
+        assert!((value(&position["legs"][1]) - 100f64).abs() < 3f64);
+
+        // 50 of btc sold, 25 of eth bought
+        let (_, state) = run(2f64, &[1f64, 0.97, 0.97], &Value::Null);
+        let position = &state["position"];
+        assert_eq!(position["direction"], -1);
+        assert_eq!(position["legs"][0]["symbol"], "BTCUSDT");
+        assert!((value(&position["legs"][0]) - 50f64).abs() < 0.5);
+        assert!((value(&position["legs"][1]) - 25f64).abs() < 1f64);
+    }
+}
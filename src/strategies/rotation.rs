@@ -0,0 +1,542 @@
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, fs};
+use rsex::{
+    constant::{ORDER_ACTION_BUY, ORDER_ACTION_SELL, ORDER_TYPE_MARKET},
+    models::{Kline, SymbolInfo},
+};
+use crate::{
+    exchange, metrics,
+    notify::{self, EventKind},
+    runner,
+    strategies::send_order,
+    traits::{Client, Strategy},
+    universe::Universe,
+    utils::{check_keys, period_ms, round_to},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rank {
+    pub symbol: String,
+    pub score: f64,
+    pub volatility: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Rebalance {
+    tick: u64,
+    equity: f64,
+    // target weight per symbol
+    targets: Vec<(String, f64)>,
+    turnover: f64,
+}
+
+// score of the last `klines`: the total return, or with `adjusted` the
+// mean log return over its standard deviation. Also returns the
+// volatility (standard deviation of log returns)
+pub fn momentum(klines: &[Kline], adjusted: bool) -> Option<(f64, f64)> {
+    if klines.len() < 3 || klines.iter().any(|k| k.close <= 0f64) {
+        return None;
+    }
+    let returns: Vec<f64> = klines
+        .windows(2)
+        .map(|w| (w[1].close / w[0].close).ln())
+        .collect();
+    let n = returns.len() as f64;
+    let mean = returns.iter().sum::<f64>() / n;
+    let variance = returns.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / (n - 1f64);
+    let volatility = variance.sqrt();
+    let score = if adjusted {
+        if volatility <= 0f64 {
+            return None;
+        }
+        mean / volatility
+    } else {
+        klines[klines.len() - 1].close / klines[0].close - 1f64
+    };
+    Some((score, volatility))
+}
+
+// weights summing to 1: equal, or with `risk_parity` proportional to the
+// inverse volatility
+pub fn weights(ranks: &[Rank], risk_parity: bool) -> Vec<f64> {
+    let raw: Vec<f64> = ranks
+        .iter()
+        .map(|rank| {
+            if risk_parity && rank.volatility > 0f64 {
+                1f64 / rank.volatility
+            } else {
+                1f64
+            }
+        })
+        .collect();
+    let total: f64 = raw.iter().sum();
+    raw.iter().map(|w| w / total).collect()
+}
+
+// holds the `top` watched symbols by momentum and rebalances every
+// `rebalance_ticks` ticks, trading at most `max_turnover` of the equity
+// each time. Only coins of the watch list and coins it bought are managed
+#[derive(Debug)]
+pub struct Rotation {
+    config: Value,
+    client: Box<dyn Client>,
+    // every symbol of the quote by base coin, the watch list is a subset
+    symbols: HashMap<String, SymbolInfo>,
+    watch: Vec<SymbolInfo>,
+    universe: Universe,
+    // base coins bought by the strategy
+    held: Vec<String>,
+    ranks: Vec<Rank>,
+    history: Vec<Rebalance>,
+    ticks: u64,
+
+    quote: String,
+    period: String,
+    lookback: u16,
+    adjusted: bool,
+    top: usize,
+    risk_parity: bool,
+    min_score: f64,
+    invest: f64,
+    rebalance_ticks: u64,
+    max_turnover: f64,
+    min_trade: f64,
+    fee: f64,
+}
+
+impl Rotation {
+    pub fn validate(config: &Value) -> Vec<String> {
+        let mut errors = check_keys(config, &["quote", "period"], &["lookback", "top"]);
+        if config["period"].as_str().and_then(period_ms).is_none() {
+            errors.push("period: expect a kline period like \"1d\"".into());
+        }
+        if config["lookback"].as_u64().unwrap_or(0) < 2 {
+            errors.push("lookback: expect at least 2".into());
+        }
+        if config["top"].as_u64().unwrap_or(0) == 0 {
+            errors.push("top: expect at least 1".into());
+        }
+        if !["return", "sharpe"].contains(&config["score"].as_str().unwrap_or("return")) {
+            errors.push("score: expect return or sharpe".into());
+        }
+        if !["equal", "risk_parity"].contains(&config["weights"].as_str().unwrap_or("equal")) {
+            errors.push("weights: expect equal or risk_parity".into());
+        }
+        for key in &["invest", "max_turnover"] {
+            let v = config[key].as_f64().unwrap_or(1f64);
+            if v <= 0f64 || v > 1f64 {
+                errors.push(format!("{}: expect 0 < {} <= 1", key, key));
+            }
+        }
+        errors
+    }
+
+    fn refresh_watch(&mut self) {
+        let symbols = match self.client.get_symbols() {
+            Ok(symbols) => symbols,
+            Err(err) => {
+                warn!("get_symbols error: {:?}", err);
+                return;
+            }
+        };
+        self.symbols = symbols
+            .into_iter()
+            .filter(|info| info.quote.eq_ignore_ascii_case(&self.quote))
+            .map(|info| (info.base.to_uppercase(), info))
+            .collect();
+        let mut candidates: Vec<SymbolInfo> = self.symbols.values().cloned().collect();
+        candidates.sort_by(|a, b| a.symbol.cmp(&b.symbol));
+        // the volume is already in the quote currency
+        self.watch = self.universe.select(&*self.client, candidates, |_, v| v);
+        info!("rotation watch list: {} symbols", self.watch.len());
+    }
+
+    fn rank(&self) -> (Vec<Rank>, HashMap<String, f64>) {
+        let mut ranks = vec![];
+        let mut prices = HashMap::new();
+        for info in &self.watch {
+            let klines = match self
+                .client
+                .get_kline(&info.symbol, &self.period, self.lookback + 1)
+            {
+                Ok(klines) => klines,
+                Err(err) => {
+                    warn!("{} get_kline error: {:?}, skipped", info.symbol, err);
+                    continue;
+                }
+            };
+            if let Some(last) = klines.last() {
+                prices.insert(info.base.to_uppercase(), last.close);
+            }
+            if klines.len() < self.lookback as usize + 1 {
+                continue;
+            }
+            if let Some((score, volatility)) = momentum(&klines, self.adjusted) {
+                ranks.push(Rank {
+                    symbol: info.symbol.clone(),
+                    score: score,
+                    volatility: volatility,
+                });
+            }
+        }
+        ranks.sort_by(|a, b| {
+            b.score
+                .partial_cmp(&a.score)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        (ranks, prices)
+    }
+
+    fn price(&self, base: &str, prices: &HashMap<String, f64>) -> Option<f64> {
+        if let Some(price) = prices.get(base) {
+            return Some(*price);
+        }
+        let info = self.symbols.get(base)?;
+        match self.client.get_ticker(&info.symbol) {
+            Ok(ticker) => Some(ticker.bid.price),
+            Err(err) => {
+                warn!("get_ticker {} error: {:?}", info.symbol, err);
+                None
+            }
+        }
+    }
+
+    fn trade(&self, base: &str, value: f64, price: f64) -> bool {
+        let info = match self.symbols.get(base) {
+            Some(info) => info,
+            None => return false,
+        };
+        let side = if value > 0f64 {
+            ORDER_ACTION_BUY
+        } else {
+            ORDER_ACTION_SELL
+        };
+        let amount = round_to(value.abs() / price, info.amount_precision as u32);
+        if amount <= 0f64 {
+            return false;
+        }
+        let ret = send_order(
+            &*self.client,
+            "rotation",
+            &info.symbol,
+            price,
+            amount,
+            side,
+            ORDER_TYPE_MARKET,
+        );
+        if ret.is_ok() {
+            notify::send(
+                EventKind::Fill,
+                &format!("{} rotation {}", info.symbol, side.to_lowercase()),
+                &format!("{} {} at {}", side, amount, price),
+            );
+        }
+        ret.is_ok()
+    }
+
+    fn rebalance(&mut self) {
+        let (ranks, prices) = self.rank();
+        let selected: Vec<Rank> = ranks
+            .iter()
+            .filter(|rank| rank.score > self.min_score)
+            .take(self.top)
+            .cloned()
+            .collect();
+        let weights = weights(&selected, self.risk_parity);
+        self.ranks = ranks;
+
+        let balances = match self.client.get_all_balances() {
+            Ok(balances) => balances,
+            Err(err) => {
+                warn!("get_all_balances error: {:?}", err);
+                return;
+            }
+        };
+        let mut cash = 0f64;
+        // base coin -> (free amount, value)
+        let mut holdings: HashMap<String, (f64, f64)> = HashMap::new();
+        for balance in &balances {
+            let asset = balance.asset.to_uppercase();
+            let amount = balance.free + balance.locked;
+            if asset.eq_ignore_ascii_case(&self.quote) {
+                cash = amount;
+                continue;
+            }
+            let managed = self.held.contains(&asset)
+                || self
+                    .watch
+                    .iter()
+                    .any(|info| info.base.eq_ignore_ascii_case(&asset));
+            if !managed || amount <= 0f64 {
+                continue;
+            }
+            if let Some(price) = self.price(&asset, &prices) {
+                holdings.insert(asset, (balance.free, amount * price));
+            }
+        }
+        let equity = cash + holdings.values().map(|(_, value)| value).sum::<f64>();
+        if equity <= 0f64 {
+            return;
+        }
+
+        let mut targets: HashMap<String, f64> =
+            holdings.keys().map(|base| (base.clone(), 0f64)).collect();
+        for (rank, weight) in selected.iter().zip(&weights) {
+            if let Some((base, _)) = self
+                .symbols
+                .iter()
+                .find(|(_, info)| info.symbol == rank.symbol)
+            {
+                targets.insert(base.clone(), weight * self.invest * equity);
+            }
+        }
+        let mut deltas: Vec<(String, f64)> = targets
+            .iter()
+            .map(|(base, target)| {
+                let current = holdings.get(base).map(|h| h.1).unwrap_or(0f64);
+                (base.clone(), target - current)
+            })
+            .filter(|(_, delta)| delta.abs() >= self.min_trade)
+            .collect();
+        let turnover: f64 = deltas.iter().map(|(_, delta)| delta.abs()).sum();
+        let cap = self.max_turnover * equity;
+        if turnover > cap {
+            info!("rotation turnover {} capped at {}", turnover, cap);
+            for (_, delta) in deltas.iter_mut() {
+                *delta *= cap / turnover;
+            }
+        }
+        // sell first to fund the buys
+        deltas.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+        let mut traded = 0f64;
+        for (base, delta) in &deltas {
+            let price = match self.price(base, &prices) {
+                Some(price) if price > 0f64 => price,
+                _ => continue,
+            };
+            let mut value = *delta;
+            if value < 0f64 {
+                let free = holdings.get(base).map(|h| h.0).unwrap_or(0f64);
+                value = value.max(-free * price);
+            } else {
+                let free = self
+                    .client
+                    .get_balance(&self.quote)
+                    .map(|b| b.free)
+                    .unwrap_or(0f64);
+                value = value.min(free / (1f64 + self.fee));
+            }
+            if value.abs() >= self.min_trade && self.trade(base, value, price) {
+                traded += value.abs();
+                if value > 0f64 && !self.held.contains(base) {
+                    self.held.push(base.clone());
+                }
+            }
+        }
+        let targets: Vec<(String, f64)> = selected
+            .iter()
+            .zip(&weights)
+            .map(|(rank, weight)| (rank.symbol.clone(), *weight))
+            .collect();
+        info!("rotation rebalanced to {:?}, turnover {}", targets, traded);
+        self.history.push(Rebalance {
+            tick: self.ticks,
+            equity: equity,
+            targets: targets,
+            turnover: traded,
+        });
+        metrics::set_gauge(
+            "rsquant_rotation_equity",
+            "Equity managed by the rotation",
+            &[],
+            equity,
+        );
+    }
+}
+
+impl Strategy for Rotation {
+    fn new(config_path: &str) -> Box<dyn Strategy> {
+        let file = fs::File::open(config_path).expect("file should open read only");
+        let config: Value = serde_json::from_reader(file).expect("file should be proper json");
+        let client = exchange::binance(&config);
+        Self::from_config(&config, Box::new(client))
+    }
+
+    fn from_config(config: &Value, client: Box<dyn Client>) -> Box<dyn Strategy> {
+        Box::new(Rotation {
+            config: config.clone(),
+            client: client,
+            symbols: HashMap::new(),
+            watch: vec![],
+            universe: Universe::from_config(&config["universe"], &config["ignore"]),
+            held: vec![],
+            ranks: vec![],
+            history: vec![],
+            ticks: 0,
+
+            quote: config["quote"].as_str().unwrap().to_uppercase(),
+            period: config["period"].as_str().unwrap().into(),
+            lookback: config["lookback"].as_u64().unwrap() as u16,
+            adjusted: config["score"].as_str() == Some("sharpe"),
+            top: config["top"].as_u64().unwrap() as usize,
+            risk_parity: config["weights"].as_str() == Some("risk_parity"),
+            min_score: config["min_score"].as_f64().unwrap_or(0f64),
+            invest: config["invest"].as_f64().unwrap_or(1f64),
+            rebalance_ticks: config["rebalance_ticks"].as_u64().unwrap_or(1).max(1),
+            max_turnover: config["max_turnover"].as_f64().unwrap_or(1f64),
+            min_trade: config["min_trade"].as_f64().unwrap_or(10f64),
+            fee: config["fee"].as_f64().unwrap_or(0.001),
+        })
+    }
+
+    fn init(&mut self) {
+        self.refresh_watch();
+    }
+
+    fn on_tick(&mut self) {
+        if self.ticks % self.rebalance_ticks == 0 {
+            if self.ticks > 0 && self.universe.due(self.ticks) {
+                self.refresh_watch();
+            }
+            self.rebalance();
+        }
+        self.ticks += 1;
+    }
+
+    fn run_forever(&mut self) {
+        let config = self.config.clone();
+        runner::run(self, &config);
+    }
+
+    fn name(&self) -> String {
+        "rotation".into()
+    }
+
+    fn stringify(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn state(&self) -> Value {
+        json!({
+            "strategy": self.name(),
+            "ticks": self.ticks,
+            "held": self.held,
+            "ranks": self.ranks,
+            "history": self.history,
+        })
+    }
+
+    fn restore(&mut self, state: &Value) {
+        if state["strategy"] != "rotation" {
+            return;
+        }
+        self.ticks = state["ticks"].as_u64().unwrap_or(0);
+        self.held = serde_json::from_value(state["held"].clone()).unwrap_or_default();
+        if let Ok(history) = serde_json::from_value(state["history"].clone()) {
+            self.history = history;
+        }
+    }
+
+    fn summary(&self) -> String {
+        let top: Vec<&str> = self
+            .ranks
+            .iter()
+            .take(self.top)
+            .map(|rank| rank.symbol.as_str())
+            .collect();
+        format!("top: {:?}, rebalances: {}", top, self.history.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backtest::data::{
+            fixture::{bars, replay, sim},
+            symbol_info,
+        },
+        exchange::SimExchange,
+    };
+    use rsex::traits::SpotRest;
+    use std::sync::Arc;
+
+    fn klines(closes: &[f64]) -> Arc<Vec<Kline>> {
+        bars(closes, 0, 3_600_000)
+    }
+
+    #[test]
+    fn test_momentum() {
+        let rising = klines(&[10f64, 11f64, 12f64, 13f64]);
+        let (score, volatility) = momentum(&rising, false).unwrap();
+        assert!((score - 0.3).abs() < 1e-9);
+        assert!(volatility > 0f64);
+        assert!(momentum(&rising, true).unwrap().0 > 0f64);
+        assert_eq!(momentum(&rising[..2], false), None);
+
+        let ranks = vec![
+            Rank {
+                symbol: "A".into(),
+                score: 1f64,
+                volatility: 0.1,
+            },
+            Rank {
+                symbol: "B".into(),
+                score: 1f64,
+                volatility: 0.3,
+            },
+        ];
+        assert_eq!(weights(&ranks, false), vec![0.5, 0.5]);
+        let parity = weights(&ranks, true);
+        assert!((parity[0] - 0.75).abs() < 1e-9 && (parity[1] - 0.25).abs() < 1e-9);
+    }
+
+    fn run(config: &Value) -> (SimExchange, Value) {
+        let aaa = [
+            10f64, 11f64, 12f64, 13f64, 14f64, 15f64, 14f64, 13f64, 12f64, 11f64, 10f64,
+        ];
+        let bbb = [
+            10f64, 10f64, 10f64, 10f64, 10f64, 10f64, 11f64, 12f64, 13f64, 14f64, 15f64,
+        ];
+        let data = vec![
+            (symbol_info("AAA", "USDT"), klines(&aaa)),
+            (symbol_info("BBB", "USDT"), klines(&bbb)),
+        ];
+        let exchange = sim(data, &[("USDT", 1000f64)], 0f64);
+        let mut robot = Rotation::from_config(config, Box::new(exchange.clone()));
+        replay(&[&exchange], &mut *robot);
+        (exchange, robot.state())
+    }
+
+    #[test]
+    fn test_rotation() {
+        let mut config = json!({
+            "quote": "usdt",
+            "period": "1h",
+            "lookback": 3,
+            "top": 1,
+            "min_trade": 1,
+            "fee": 0,
+        });
+        assert!(Rotation::validate(&config).is_empty());
+        // rotated out of AAA once it turned down, into BBB
+        let (exchange, _) = run(&config);
+        assert_eq!(exchange.get_balance("AAA").unwrap().free, 0f64);
+        assert!(exchange.get_balance("BBB").unwrap().free * 15f64 > 900f64);
+
+        // half the equity at most every 4 ticks, still partly in AAA
+        config["max_turnover"] = 0.5.into();
+        config["rebalance_ticks"] = 4.into();
+        let (exchange, state) = run(&config);
+        assert!(exchange.get_balance("AAA").unwrap().free > 0f64);
+        assert!(exchange.get_balance("BBB").unwrap().free > 0f64);
+        let history = state["history"].as_array().unwrap();
+        assert_eq!(history.len(), 3);
+        for rebalance in history {
+            let equity = rebalance["equity"].as_f64().unwrap();
+            assert!(rebalance["turnover"].as_f64().unwrap() <= 0.5 * equity + 1e-6);
+        }
+    }
+}
@@ -0,0 +1,487 @@
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, fs};
+use rsex::{
+    constant::{ORDER_ACTION_BUY, ORDER_ACTION_SELL, ORDER_TYPE_MARKET},
+    models::SymbolInfo,
+};
+use crate::{
+    exchange, metrics,
+    notify::{self, EventKind},
+    rules::{self, Expr},
+    runner,
+    strategies::send_order,
+    traits::{Client, Strategy},
+    utils::{check_keys, period_ms, round_to},
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Position {
+    symbol: String,
+    amount: f64,
+    price: f64,
+    high: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    symbol: String,
+    buy_price: f64,
+    sell_price: f64,
+    amount: f64,
+    profit: f64,
+    reason: String,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Size {
+    // quote currency per entry
+    Value(f64),
+    // share of the free quote balance
+    Percent(f64),
+}
+
+// the "indicators" map, names may refer to each other in any order
+fn parse_indicators(config: &Value) -> Result<HashMap<String, Expr>, String> {
+    let mut pending: Vec<(String, String)> = match config.as_object() {
+        Some(map) => map
+            .iter()
+            .map(|(name, text)| match text.as_str() {
+                Some(text) => Ok((name.clone(), text.to_string())),
+                None => Err(format!("indicators.{}: expect string", name)),
+            })
+            .collect::<Result<_, _>>()?,
+        None => vec![],
+    };
+    let mut names = HashMap::new();
+    while !pending.is_empty() {
+        let before = pending.len();
+        let mut errors = vec![];
+        pending.retain(|(name, text)| match rules::parse(text, &names) {
+            Ok(expr) => {
+                names.insert(name.clone(), expr);
+                false
+            }
+            Err(err) => {
+                errors.push(format!("indicators.{}: {}", name, err));
+                true
+            }
+        });
+        if pending.len() == before {
+            return Err(errors.join(", "));
+        }
+    }
+    Ok(names)
+}
+
+// entry and exit rules
+pub fn parse(config: &Value) -> Result<(Expr, Option<Expr>), String> {
+    let names = parse_indicators(&config["indicators"])?;
+    let entry = match config["entry"].as_str() {
+        Some(text) => rules::parse(text, &names).map_err(|err| format!("entry: {}", err))?,
+        None => return Err("entry: expect string".into()),
+    };
+    let exit = match config["exit"].as_str() {
+        Some(text) => Some(rules::parse(text, &names).map_err(|err| format!("exit: {}", err))?),
+        None => None,
+    };
+    Ok((entry, exit))
+}
+
+// buys `size` of a symbol when the `entry` rule holds on the latest bar,
+// sells on the `exit` rule or the `stoploss`, `take_profit` and
+// `trailing` stops, whichever comes first
+#[derive(Debug)]
+pub struct RuleBased {
+    config: Value,
+    client: Box<dyn Client>,
+    info: HashMap<String, SymbolInfo>,
+    positions: Vec<Position>,
+    history: Vec<Record>,
+    total_profit: f64,
+
+    symbols: Vec<String>,
+    period: String,
+    bars: u16,
+    entry: Expr,
+    exit: Option<Expr>,
+    size: Size,
+    stoploss: Option<f64>,
+    take_profit: Option<f64>,
+    trailing: Option<f64>,
+    fee: f64,
+}
+
+impl RuleBased {
+    pub fn validate(config: &Value) -> Vec<String> {
+        let mut errors = check_keys(config, &["period", "entry"], &[]);
+        match config["symbols"].as_array() {
+            Some(symbols) if !symbols.is_empty() && symbols.iter().all(|s| s.is_string()) => {}
+            _ => errors.push("symbols: expect a list of symbols".into()),
+        }
+        if config["period"].as_str().and_then(period_ms).is_none() {
+            errors.push("period: expect a kline period like \"1h\"".into());
+        }
+        if let Err(err) = parse(config) {
+            errors.push(err);
+        }
+        let size = &config["size"];
+        match size["type"].as_str().unwrap_or("value") {
+            "value" | "percent" => {}
+            _ => errors.push("size.type: expect value or percent".into()),
+        }
+        let value = size["value"].as_f64().unwrap_or(0f64);
+        if value <= 0f64 || (size["type"] == "percent" && value > 1f64) {
+            errors.push("size.value: expect > 0, at most 1 for percent".into());
+        }
+        for key in &["stoploss", "take_profit", "trailing"] {
+            if !config[key].is_null() && config[key].as_f64().unwrap_or(0f64) <= 0f64 {
+                errors.push(format!("{}: expect > 0", key));
+            }
+        }
+        errors
+    }
+
+    fn order(&self, symbol: &str, side: &str, amount: f64, price: f64) -> Option<f64> {
+        let info = self.info.get(symbol)?;
+        let amount = round_to(amount, info.amount_precision as u32);
+        if amount <= 0f64 || amount < info.min_amount || amount * price < info.min_value {
+            return None;
+        }
+        send_order(
+            &*self.client,
+            "rules",
+            symbol,
+            price,
+            amount,
+            side,
+            ORDER_TYPE_MARKET,
+        )
+        .ok()?;
+        Some(amount)
+    }
+
+    fn enter(&mut self, symbol: &str, ask: f64) {
+        let quote = match self.info.get(symbol) {
+            Some(info) => info.quote.clone(),
+            None => return,
+        };
+        let value = match self.size {
+            Size::Value(value) => value,
+            Size::Percent(share) => match self.client.get_balance(&quote) {
+                Ok(balance) => balance.free * share,
+                Err(err) => {
+                    warn!("get_balance error: {:?}", err);
+                    return;
+                }
+            },
+        };
+        let amount = value / (ask * (1f64 + self.fee));
+        if let Some(amount) = self.order(symbol, ORDER_ACTION_BUY, amount, ask) {
+            notify::send(
+                EventKind::Fill,
+                &format!("{} entry", symbol),
+                &format!("buy {} at {}", amount, ask),
+            );
+            self.positions.push(Position {
+                symbol: symbol.into(),
+                amount: amount,
+                price: ask,
+                high: ask,
+            });
+        }
+    }
+
+    // true when the position was sold
+    fn exit(&mut self, pos: &Position, bid: f64, reason: &str) -> bool {
+        let amount = match self.order(&pos.symbol, ORDER_ACTION_SELL, pos.amount, bid) {
+            Some(amount) => amount,
+            None => return false,
+        };
+        let profit = (bid * (1f64 - self.fee) - pos.price * (1f64 + self.fee)) * amount;
+        info!(
+            "rules {} {} at {}, profit: {}",
+            pos.symbol, reason, bid, profit
+        );
+        let kind = if reason == "exit" {
+            EventKind::Fill
+        } else {
+            EventKind::Stop
+        };
+        notify::send(
+            kind,
+            &format!("{} {}", pos.symbol, reason),
+            &format!("sell {} at {}, profit {}", amount, bid, profit),
+        );
+        self.total_profit += profit;
+        metrics::set_gauge(
+            "rsquant_total_profit",
+            "Realised profit",
+            &[],
+            self.total_profit,
+        );
+        self.history.push(Record {
+            symbol: pos.symbol.clone(),
+            buy_price: pos.price,
+            sell_price: bid,
+            amount: amount,
+            profit: profit,
+            reason: reason.into(),
+        });
+        true
+    }
+
+    fn check(&mut self, symbol: &str) {
+        let ticker = match self.client.get_ticker(symbol) {
+            Ok(ticker) => ticker,
+            Err(err) => {
+                warn!("{} get_ticker error: {:?}", symbol, err);
+                return;
+            }
+        };
+        // one more for the bar still open, left out below
+        let limit = (self.bars + 1).min(1000);
+        let mut klines = match self.client.get_kline(symbol, &self.period, limit) {
+            Ok(klines) => klines,
+            Err(err) => {
+                warn!("{} get_kline error: {:?}", symbol, err);
+                return;
+            }
+        };
+        // the exchange returns the bar still open last, the rules only see
+        // closed ones. Its clock rather than ours, so a backtest sees the
+        // same bars as a live run
+        let period = period_ms(&self.period).unwrap_or(0);
+        if let Some(kline) = klines.last() {
+            if kline.timestamp + period > ticker.timestamp {
+                klines.pop();
+            }
+        }
+        if klines.is_empty() {
+            return;
+        }
+        let last = klines.len() - 1;
+        let holds = |expr: &Expr| expr.eval(&klines, last).map(|v| v != 0f64).unwrap_or(false);
+
+        let index = self.positions.iter().position(|pos| pos.symbol == symbol);
+        let index = match index {
+            Some(index) => index,
+            None => {
+                if holds(&self.entry) {
+                    self.enter(symbol, ticker.ask.price);
+                }
+                return;
+            }
+        };
+        let bid = ticker.bid.price;
+        let pos = &mut self.positions[index];
+        pos.high = pos.high.max(bid);
+        let change = bid / pos.price - 1f64;
+        let reason = if self.stoploss.map(|s| change <= -s).unwrap_or(false) {
+            Some("stoploss")
+        } else if self.take_profit.map(|t| change >= t).unwrap_or(false) {
+            Some("take_profit")
+        } else if self
+            .trailing
+            .map(|t| bid <= pos.high * (1f64 - t))
+            .unwrap_or(false)
+        {
+            Some("trailing")
+        } else if self.exit.as_ref().map(holds).unwrap_or(false) {
+            Some("exit")
+        } else {
+            None
+        };
+        if let Some(reason) = reason {
+            let pos = pos.clone();
+            if self.exit(&pos, bid, reason) {
+                self.positions.remove(index);
+            }
+        }
+    }
+
+    fn load_symbols(&mut self) {
+        match self.client.get_symbols() {
+            Ok(symbols) => {
+                for info in symbols {
+                    if self.symbols.contains(&info.symbol) {
+                        self.info.insert(info.symbol.clone(), info);
+                    }
+                }
+            }
+            Err(err) => {
+                warn!("get_symbols error: {:?}", err);
+                return;
+            }
+        }
+        for symbol in &self.symbols {
+            if !self.info.contains_key(symbol) {
+                warn!("rules symbol {} not found", symbol);
+            }
+        }
+    }
+}
+
+impl Strategy for RuleBased {
+    fn new(config_path: &str) -> Box<dyn Strategy> {
+        let file = fs::File::open(config_path).expect("file should open read only");
+        let config: Value = serde_json::from_reader(file).expect("file should be proper json");
+        let client = exchange::binance(&config);
+        Self::from_config(&config, Box::new(client))
+    }
+
+    fn from_config(config: &Value, client: Box<dyn Client>) -> Box<dyn Strategy> {
+        let (entry, exit) = parse(config).expect("rules should parse");
+        let lookback = entry
+            .lookback()
+            .max(exit.as_ref().map(|e| e.lookback()).unwrap_or(0));
+        let size = match config["size"]["type"].as_str() {
+            Some("percent") => Size::Percent(config["size"]["value"].as_f64().unwrap()),
+            _ => Size::Value(config["size"]["value"].as_f64().unwrap()),
+        };
+        Box::new(RuleBased {
+            config: config.clone(),
+            client: client,
+            info: HashMap::new(),
+            positions: vec![],
+            history: vec![],
+            total_profit: 0f64,
+
+            symbols: config["symbols"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .filter_map(|s| s.as_str())
+                .map(|s| s.to_uppercase())
+                .collect(),
+            period: config["period"].as_str().unwrap().into(),
+            bars: config["bars"].as_u64().unwrap_or(lookback as u64).min(1000) as u16,
+            entry: entry,
+            exit: exit,
+            size: size,
+            stoploss: config["stoploss"].as_f64(),
+            take_profit: config["take_profit"].as_f64(),
+            trailing: config["trailing"].as_f64(),
+            fee: config["fee"].as_f64().unwrap_or(0.001),
+        })
+    }
+
+    fn init(&mut self) {
+        self.load_symbols();
+    }
+
+    fn on_tick(&mut self) {
+        // init couldn't load them, keep trying
+        if self.info.len() < self.symbols.len() {
+            self.load_symbols();
+        }
+        for symbol in self.symbols.clone() {
+            self.check(&symbol);
+        }
+    }
+
+    fn run_forever(&mut self) {
+        let config = self.config.clone();
+        runner::run(self, &config);
+    }
+
+    fn name(&self) -> String {
+        "rules".into()
+    }
+
+    fn stringify(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn state(&self) -> Value {
+        json!({
+            "strategy": self.name(),
+            "positions": self.positions,
+            "history": self.history,
+            "total_profit": self.total_profit,
+        })
+    }
+
+    fn restore(&mut self, state: &Value) {
+        if state["strategy"] != "rules" {
+            return;
+        }
+        if let Ok(positions) = serde_json::from_value(state["positions"].clone()) {
+            self.positions = positions;
+        }
+        if let Ok(history) = serde_json::from_value(state["history"].clone()) {
+            self.history = history;
+        }
+        self.total_profit = state["total_profit"].as_f64().unwrap_or(0f64);
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "positions: {}, trades: {}, total_profit: {}",
+            self.positions.len(),
+            self.history.len(),
+            self.total_profit
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backtest::data::{
+        fixture::{bars, replay, sim},
+        symbol_info,
+    };
+
+    // hourly bars
+    fn run(closes: &[f64], config: &Value) -> Value {
+        let data = vec![(symbol_info("ETH", "USDT"), bars(closes, 0, 3_600_000))];
+        let exchange = sim(data, &[("USDT", 1000f64)], 0f64);
+        let mut robot = RuleBased::from_config(config, Box::new(exchange.clone()));
+        replay(&[&exchange], &mut *robot);
+        robot.state()
+    }
+
+    #[test]
+    fn test_rules() {
+        let mut config = json!({
+            "symbols": ["ethusdt"],
+            "period": "1h",
+            "indicators": {"trend": "sma(3)", "above": "close > trend"},
+            "entry": "close crosses_above trend",
+            "exit": "not above",
+            "size": {"type": "value", "value": 100},
+            "fee": 0,
+        });
+        assert!(RuleBased::validate(&config).is_empty());
+        let closes = [
+            10f64, 9f64, 8f64, 7f64, 6f64, 5f64, 6f64, 7f64, 8f64, 9f64, 10f64, 9f64, 8f64,
+        ];
+        // crosses at 6, bought on the next bar at 7. Closes under sma(3)
+        // at 9, sold on the next bar at 8
+        let state = run(&closes, &config);
+        let history = state["history"].as_array().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["buy_price"], 7f64);
+        assert_eq!(history[0]["sell_price"], 8f64);
+        assert_eq!(history[0]["reason"], "exit");
+        assert!((state["total_profit"].as_f64().unwrap() - 100f64 / 7f64).abs() < 1e-6);
+
+        config["take_profit"] = 0.3.into();
+        let state = run(&closes, &config);
+        assert_eq!(state["history"][0]["reason"], "take_profit");
+        assert_eq!(state["history"][0]["sell_price"], 10f64);
+
+        // the last bar crosses the sma, but is still open
+        let closes = [10f64, 9f64, 8f64, 7f64, 6f64, 5f64, 7f64];
+        let state = run(&closes, &config);
+        assert!(state["positions"].as_array().unwrap().is_empty());
+
+        config["entry"] = "foo > 1".into();
+        config["indicators"]["loop"] = "loop + 1".into();
+        let errors = RuleBased::validate(&config);
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("indicators.loop"));
+    }
+}
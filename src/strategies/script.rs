@@ -0,0 +1,635 @@
+use log::{info, warn};
+use rhai::{
+    module_resolvers::DummyModuleResolver, Array, CallFnOptions, Dynamic, Engine, EvalAltResult,
+    Map, Scope, AST,
+};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    cell::{Cell, RefCell},
+    fs,
+    rc::Rc,
+    time::{Duration, Instant, SystemTime},
+};
+use rsex::constant::{ORDER_ACTION_BUY, ORDER_ACTION_SELL, ORDER_TYPE_LIMIT, ORDER_TYPE_MARKET};
+use crate::{
+    exchange, indicators,
+    notify::{self, EventKind},
+    runner,
+    strategies::send_order,
+    traits::{Client, Strategy},
+    utils::check_keys,
+};
+
+type RhaiResult<T> = Result<T, Box<EvalAltResult>>;
+
+// orders the script sent, kept in the state file
+const MAX_ORDERS: usize = 1000;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ScriptOrder {
+    symbol: String,
+    side: String,
+    order_type: String,
+    price: f64,
+    amount: f64,
+    order_id: String,
+}
+
+pub fn to_json(value: &Dynamic) -> Value {
+    if value.is_unit() {
+        Value::Null
+    } else if let Ok(v) = value.as_bool() {
+        v.into()
+    } else if let Ok(v) = value.as_int() {
+        v.into()
+    } else if let Ok(v) = value.as_float() {
+        v.into()
+    } else if value.is_string() {
+        value.clone().into_string().unwrap_or_default().into()
+    } else if value.is_array() {
+        let array = value.clone().into_array().unwrap_or_default();
+        Value::Array(array.iter().map(to_json).collect())
+    } else if let Ok(map) = value.as_map_ref() {
+        Value::Object(
+            map.iter()
+                .map(|(k, v)| (k.to_string(), to_json(v)))
+                .collect(),
+        )
+    } else {
+        value.to_string().into()
+    }
+}
+
+pub fn from_json(value: &Value) -> Dynamic {
+    match value {
+        Value::Null => Dynamic::UNIT,
+        Value::Bool(v) => (*v).into(),
+        Value::Number(v) => match v.as_i64() {
+            Some(v) => v.into(),
+            None => v.as_f64().unwrap_or(0f64).into(),
+        },
+        Value::String(v) => v.clone().into(),
+        Value::Array(v) => v.iter().map(from_json).collect::<Array>().into(),
+        Value::Object(v) => v
+            .iter()
+            .map(|(k, v)| (k.as_str().into(), from_json(v)))
+            .collect::<Map>()
+            .into(),
+    }
+}
+
+fn numbers(values: &Array) -> Vec<f64> {
+    values
+        .iter()
+        .filter_map(|v| {
+            v.as_float()
+                .ok()
+                .or_else(|| v.as_int().ok().map(|v| v as f64))
+        })
+        .collect()
+}
+
+fn optional(value: Option<f64>) -> Dynamic {
+    value.map(Dynamic::from).unwrap_or(Dynamic::UNIT)
+}
+
+// the functions a script can call, every api error fails the tick as
+// does an order past `max_orders` in one tick
+fn bindings(
+    engine: &mut Engine,
+    client: Rc<dyn Client>,
+    orders: Rc<RefCell<Vec<ScriptOrder>>>,
+    sent: Rc<Cell<u64>>,
+    max_orders: u64,
+) {
+    let c = client.clone();
+    engine.register_fn("ticker", move |symbol: &str| -> RhaiResult<Map> {
+        let ticker = c
+            .get_ticker(symbol)
+            .map_err(|err| format!("ticker {}: {:?}", symbol, err))?;
+        let mut map = Map::new();
+        map.insert("bid".into(), ticker.bid.price.into());
+        map.insert("ask".into(), ticker.ask.price.into());
+        map.insert("time".into(), (ticker.timestamp as i64).into());
+        Ok(map)
+    });
+    let c = client.clone();
+    engine.register_fn(
+        "klines",
+        move |symbol: &str, period: &str, limit: i64| -> RhaiResult<Array> {
+            let klines = c
+                .get_kline(symbol, period, limit.clamp(1, 1000) as u16)
+                .map_err(|err| format!("klines {}: {:?}", symbol, err))?;
+            Ok(klines
+                .iter()
+                .map(|k| {
+                    let mut map = Map::new();
+                    map.insert("time".into(), (k.timestamp as i64).into());
+                    map.insert("open".into(), k.open.into());
+                    map.insert("high".into(), k.high.into());
+                    map.insert("low".into(), k.low.into());
+                    map.insert("close".into(), k.close.into());
+                    map.insert("volume".into(), k.volume.into());
+                    map.into()
+                })
+                .collect())
+        },
+    );
+    let c = client.clone();
+    engine.register_fn(
+        "closes",
+        move |symbol: &str, period: &str, limit: i64| -> RhaiResult<Array> {
+            let klines = c
+                .get_kline(symbol, period, limit.clamp(1, 1000) as u16)
+                .map_err(|err| format!("closes {}: {:?}", symbol, err))?;
+            Ok(klines.iter().map(|k| k.close.into()).collect())
+        },
+    );
+    engine.register_fn("sma", |values: Array, n: i64| {
+        optional(indicators::sma(&numbers(&values), n.max(0) as usize))
+    });
+    engine.register_fn("ema", |values: Array, n: i64| {
+        optional(indicators::ema(&numbers(&values), n.max(0) as usize))
+    });
+    engine.register_fn("rsi", |values: Array, n: i64| {
+        optional(indicators::rsi(&numbers(&values), n.max(0) as usize))
+    });
+    let c = client.clone();
+    engine.register_fn("balance", move |asset: &str| -> RhaiResult<f64> {
+        let balance = c
+            .get_balance(asset)
+            .map_err(|err| format!("balance {}: {:?}", asset, err))?;
+        Ok(balance.free)
+    });
+
+    let order = {
+        let client = client.clone();
+        move |symbol: &str,
+              side: &str,
+              order_type: &str,
+              price: f64,
+              amount: f64|
+              -> RhaiResult<String> {
+            if sent.get() >= max_orders {
+                return Err(format!("more than {} orders in one tick", max_orders).into());
+            }
+            sent.set(sent.get() + 1);
+            let symbol = symbol.to_uppercase();
+            let price = if order_type == ORDER_TYPE_MARKET {
+                let ticker = client
+                    .get_ticker(&symbol)
+                    .map_err(|err| format!("ticker {}: {:?}", symbol, err))?;
+                if side == ORDER_ACTION_BUY {
+                    ticker.ask.price
+                } else {
+                    ticker.bid.price
+                }
+            } else {
+                price
+            };
+            let order_id = send_order(&*client, "script", &symbol, price, amount, side, order_type)
+                .map_err(|err| format!("{} {} {} at {}: {:?}", symbol, side, amount, price, err))?;
+            let mut orders = orders.borrow_mut();
+            orders.push(ScriptOrder {
+                symbol: symbol,
+                side: side.into(),
+                order_type: order_type.into(),
+                price: price,
+                amount: amount,
+                order_id: order_id.clone(),
+            });
+            let excess = orders.len().saturating_sub(MAX_ORDERS);
+            orders.drain(..excess);
+            Ok(order_id)
+        }
+    };
+    let o = order.clone();
+    engine.register_fn("buy", move |symbol: &str, amount: f64| {
+        o(symbol, ORDER_ACTION_BUY, ORDER_TYPE_MARKET, 0f64, amount)
+    });
+    let o = order.clone();
+    engine.register_fn("sell", move |symbol: &str, amount: f64| {
+        o(symbol, ORDER_ACTION_SELL, ORDER_TYPE_MARKET, 0f64, amount)
+    });
+    let o = order.clone();
+    engine.register_fn("buy_limit", move |symbol: &str, price: f64, amount: f64| {
+        o(symbol, ORDER_ACTION_BUY, ORDER_TYPE_LIMIT, price, amount)
+    });
+    let o = order;
+    engine.register_fn(
+        "sell_limit",
+        move |symbol: &str, price: f64, amount: f64| {
+            o(symbol, ORDER_ACTION_SELL, ORDER_TYPE_LIMIT, price, amount)
+        },
+    );
+    let c = client.clone();
+    engine.register_fn("cancel", move |order_id: &str| -> bool {
+        match c.cancel(order_id) {
+            Ok(_) => true,
+            Err(err) => {
+                warn!("script cancel {} error: {:?}", order_id, err);
+                false
+            }
+        }
+    });
+    let c = client.clone();
+    engine.register_fn(
+        "order_status",
+        move |order_id: &str| -> RhaiResult<String> {
+            let order = c
+                .get_order(order_id)
+                .map_err(|err| format!("order {}: {:?}", order_id, err))?;
+            Ok(order.status)
+        },
+    );
+    let c = client;
+    engine.register_fn("open_orders", move |symbol: &str| -> RhaiResult<Array> {
+        let orders = c
+            .get_open_orders(symbol)
+            .map_err(|err| format!("open_orders {}: {:?}", symbol, err))?;
+        Ok(orders
+            .iter()
+            .map(|order| {
+                let mut map = Map::new();
+                map.insert("id".into(), order.order_id.clone().into());
+                map.insert("side".into(), order.side.clone().into());
+                map.insert("price".into(), order.price.into());
+                map.insert("amount".into(), order.amount.into());
+                map.insert("filled".into(), order.filled.into());
+                map.insert("status".into(), order.status.clone().into());
+                map.into()
+            })
+            .collect())
+    });
+    engine.register_fn("log", |message: &str| info!("script: {}", message));
+    engine.on_print(|message| info!("script: {}", message));
+}
+
+// runs `init()` once and `on_tick()` on every tick of a Rhai script,
+// both with `this` bound to a map kept across ticks, restarts and
+// reloads, `init()` is tried again on the next tick until it succeeds. The
+// script is compiled again whenever the file changes, a broken version
+// keeps the last good one running. Scripts can't load modules or eval
+// code, are stopped after `max_ms` or `max_operations` and can send up to
+// `max_orders` orders a tick
+#[derive(Debug)]
+pub struct Script {
+    config: Value,
+    engine: Engine,
+    ast: Option<AST>,
+    this: Dynamic,
+    started: Rc<Cell<Instant>>,
+    // orders sent this tick
+    sent: Rc<Cell<u64>>,
+    orders: Rc<RefCell<Vec<ScriptOrder>>>,
+    // modification time and size of the loaded file
+    version: Option<(SystemTime, u64)>,
+    initialized: bool,
+    errors: u64,
+
+    path: String,
+}
+
+impl Script {
+    pub fn validate(config: &Value) -> Vec<String> {
+        let mut errors = check_keys(config, &["script"], &[]);
+        if let Some(path) = config["script"].as_str() {
+            match fs::read_to_string(path) {
+                Ok(source) => {
+                    let mut engine = Engine::new();
+                    engine.disable_symbol("eval");
+                    if let Err(err) = engine.compile(&source) {
+                        errors.push(format!("script: {}", err));
+                    }
+                }
+                Err(err) => errors.push(format!("script: {}", err)),
+            }
+        }
+        errors
+    }
+
+    fn build_engine(
+        config: &Value,
+        client: Rc<dyn Client>,
+        orders: Rc<RefCell<Vec<ScriptOrder>>>,
+        started: Rc<Cell<Instant>>,
+        sent: Rc<Cell<u64>>,
+    ) -> Engine {
+        let mut engine = Engine::new();
+        engine.set_module_resolver(DummyModuleResolver::new());
+        engine.disable_symbol("eval");
+        engine.set_max_operations(config["max_operations"].as_u64().unwrap_or(1_000_000));
+        engine.set_max_call_levels(32);
+        engine.set_max_expr_depths(64, 32);
+        engine.set_max_string_size(1 << 20);
+        engine.set_max_array_size(100_000);
+        engine.set_max_map_size(10_000);
+        let limit = Duration::from_millis(config["max_ms"].as_u64().unwrap_or(500));
+        engine.on_progress(move |_| {
+            if started.get().elapsed() > limit {
+                Some("time limit exceeded".into())
+            } else {
+                None
+            }
+        });
+        let max_orders = config["max_orders"].as_u64().unwrap_or(10);
+        bindings(&mut engine, client, orders, sent, max_orders);
+        engine
+    }
+
+    // compile the file again if it changed since the last load
+    fn reload(&mut self) {
+        let version = fs::metadata(&self.path).and_then(|m| Ok((m.modified()?, m.len())));
+        let version = match version {
+            Ok(version) => version,
+            Err(err) => {
+                warn!("script {} error: {}", self.path, err);
+                return;
+            }
+        };
+        if self.version == Some(version) {
+            return;
+        }
+        self.version = Some(version);
+        let compiled = fs::read_to_string(&self.path)
+            .map_err(|err| err.to_string())
+            .and_then(|source| self.engine.compile(&source).map_err(|err| err.to_string()));
+        match compiled {
+            Ok(ast) => {
+                info!(
+                    "script {} {}",
+                    self.path,
+                    if self.ast.is_some() {
+                        "reloaded"
+                    } else {
+                        "loaded"
+                    }
+                );
+                self.ast = Some(ast);
+            }
+            Err(err) => {
+                warn!(
+                    "script {} compile error: {}, keep running the last version",
+                    self.path, err
+                );
+                notify::send(EventKind::Error, "script compile error", &err);
+            }
+        }
+    }
+
+    // false when the script failed
+    fn call(&mut self, name: &str) -> bool {
+        let ast = match &self.ast {
+            Some(ast) => ast,
+            None => return true,
+        };
+        if !ast.iter_functions().any(|f| f.name == name) {
+            return true;
+        }
+        self.started.set(Instant::now());
+        let mut options = CallFnOptions::new().bind_this_ptr(&mut self.this);
+        options.eval_ast = false;
+        let ret =
+            self.engine
+                .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), ast, name, ());
+        if let Err(err) = ret {
+            self.errors += 1;
+            warn!("script {}() error: {}", name, err);
+            notify::send(
+                EventKind::Error,
+                &format!("script {}() error", name),
+                &err.to_string(),
+            );
+            return false;
+        }
+        true
+    }
+}
+
+impl Strategy for Script {
+    fn new(config_path: &str) -> Box<dyn Strategy> {
+        let file = fs::File::open(config_path).expect("file should open read only");
+        let config: Value = serde_json::from_reader(file).expect("file should be proper json");
+        let client = exchange::binance(&config);
+        Self::from_config(&config, Box::new(client))
+    }
+
+    fn from_config(config: &Value, client: Box<dyn Client>) -> Box<dyn Strategy> {
+        let orders = Rc::new(RefCell::new(vec![]));
+        let started = Rc::new(Cell::new(Instant::now()));
+        let sent = Rc::new(Cell::new(0));
+        let engine = Self::build_engine(
+            config,
+            Rc::from(client),
+            orders.clone(),
+            started.clone(),
+            sent.clone(),
+        );
+        Box::new(Script {
+            config: config.clone(),
+            engine: engine,
+            ast: None,
+            this: Map::new().into(),
+            started: started,
+            sent: sent,
+            orders: orders,
+            version: None,
+            initialized: false,
+            errors: 0,
+
+            path: config["script"].as_str().unwrap().into(),
+        })
+    }
+
+    fn init(&mut self) {
+        self.reload();
+    }
+
+    fn on_tick(&mut self) {
+        self.reload();
+        self.sent.set(0);
+        // after restore, so a saved state reaches init(). on_tick() waits
+        // for an init() that failed to succeed
+        if !self.initialized && self.ast.is_some() {
+            self.initialized = self.call("init");
+            if !self.initialized {
+                return;
+            }
+        }
+        self.call("on_tick");
+    }
+
+    fn run_forever(&mut self) {
+        let config = self.config.clone();
+        runner::run(self, &config);
+    }
+
+    fn name(&self) -> String {
+        "script".into()
+    }
+
+    fn stringify(&self) -> String {
+        format!(
+            "script: {}, loaded: {}, errors: {}",
+            self.path,
+            self.ast.is_some(),
+            self.errors
+        )
+    }
+
+    fn state(&self) -> Value {
+        json!({
+            "strategy": self.name(),
+            "script": self.path,
+            "state": to_json(&self.this),
+            "orders": *self.orders.borrow(),
+            "errors": self.errors,
+        })
+    }
+
+    fn restore(&mut self, state: &Value) {
+        if state["strategy"] != "script" {
+            return;
+        }
+        if state["state"].is_object() {
+            self.this = from_json(&state["state"]);
+        }
+        if let Ok(orders) = serde_json::from_value(state["orders"].clone()) {
+            *self.orders.borrow_mut() = orders;
+        }
+    }
+
+    fn summary(&self) -> String {
+        format!(
+            "script: {}, orders: {}, errors: {}, state: {}",
+            self.path,
+            self.orders.borrow().len(),
+            self.errors,
+            to_json(&self.this)
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backtest::data::{
+            fixture::{bars, sim},
+            symbol_info,
+        },
+        exchange::SimExchange,
+    };
+    use rsex::traits::SpotRest;
+
+    fn exchange() -> SimExchange {
+        let closes = [100f64, 101f64, 102f64, 103f64, 104f64];
+        let data = vec![(symbol_info("ETH", "USDT"), bars(&closes, 0, 3_600_000))];
+        sim(data, &[("USDT", 1000f64)], 0f64)
+    }
+
+    fn script(name: &str, source: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("rsquant_{}_{}.rhai", name, std::process::id()));
+        fs::write(&path, source).unwrap();
+        path.to_string_lossy().into()
+    }
+
+    #[test]
+    fn test_script() {
+        let path = script(
+            "test",
+            r#"
+            fn init() { this.ticks = 0; }
+            fn on_tick() {
+                this.ticks += 1;
+                let c = closes("ETHUSDT", "1h", 3);
+                if c.len() == 3 && sma(c, 3) > 101.0 && this.order == () {
+                    this.order = buy("ETHUSDT", 0.5);
+                }
+            }
+            "#,
+        );
+        let config = json!({"script": path});
+        assert!(Script::validate(&config).is_empty());
+        let exchange = exchange();
+        let mut robot = Script::from_config(&config, Box::new(exchange.clone()));
+        let timestamps = exchange.timestamps();
+        for timestamp in &timestamps[..3] {
+            exchange.advance(*timestamp);
+            robot.on_tick();
+        }
+        // sma(100, 101, 102) isn't over 101 yet
+        assert_eq!(exchange.get_balance("ETH").unwrap().free, 0f64);
+
+        // a broken version keeps the old one running
+        fs::write(&path, "fn on_tick( {").unwrap();
+        exchange.advance(timestamps[3]);
+        robot.on_tick();
+        let state = robot.state();
+        assert_eq!(state["state"]["ticks"], 4);
+        assert_eq!(state["orders"].as_array().unwrap().len(), 1);
+        assert_eq!(exchange.get_balance("ETH").unwrap().free, 0.5);
+
+        // reloaded, the state carries over
+        fs::write(&path, "fn on_tick() { this.ticks -= 10; }").unwrap();
+        exchange.advance(timestamps[4]);
+        robot.on_tick();
+        assert_eq!(robot.state()["state"]["ticks"], -6);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_sandbox() {
+        let path = script("loop", "fn on_tick() { this.n = 0; loop { this.n += 1; } }");
+        let config = json!({"script": path, "max_ms": 50, "max_operations": 0});
+        let mut robot = Script::from_config(&config, Box::new(exchange()));
+        let start = Instant::now();
+        robot.on_tick();
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(robot.state()["errors"], 1);
+
+        // an order loop stops at max_orders, the next tick gets as many
+        fs::write(&path, "fn on_tick() { loop { buy(\"ETHUSDT\", 0.01); } }").unwrap();
+        let config = json!({"script": path, "max_orders": 3});
+        let exchange = exchange();
+        exchange.advance(0);
+        let mut robot = Script::from_config(&config, Box::new(exchange.clone()));
+        robot.on_tick();
+        assert_eq!(robot.state()["orders"].as_array().unwrap().len(), 3);
+        robot.on_tick();
+        assert_eq!(robot.state()["orders"].as_array().unwrap().len(), 6);
+        assert_eq!(robot.state()["errors"], 2);
+
+        fs::write(&path, "fn on_tick() { eval(\"1\"); }").unwrap();
+        let errors = Script::validate(&config);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn test_init_retry() {
+        let path = script(
+            "init",
+            r#"
+            fn init() {
+                if this.inits == () { this.inits = 0; }
+                this.inits += 1;
+                if this.inits == 1 { throw "not ready"; }
+                this.ticks = 0;
+            }
+            fn on_tick() { this.ticks += 1; }
+            "#,
+        );
+        let mut robot = Script::from_config(&json!({"script": path}), Box::new(exchange()));
+        robot.on_tick();
+        robot.on_tick();
+        fs::remove_file(&path).unwrap();
+        // init failed once and ran again, on_tick only after it succeeded
+        let state = robot.state();
+        assert_eq!(state["errors"], 1);
+        assert_eq!(state["state"]["inits"], 2);
+        assert_eq!(state["state"]["ticks"], 1);
+    }
+}
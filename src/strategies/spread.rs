@@ -0,0 +1,501 @@
+use log::{info, warn};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::fs;
+use rsex::{
+    constant::{ORDER_ACTION_BUY, ORDER_ACTION_SELL, ORDER_TYPE_LIMIT},
+    models::{SymbolInfo, Ticker},
+};
+use crate::{
+    exchange, metrics,
+    notify::{self, EventKind},
+    runner,
+    strategies::send_order,
+    traits::{Client, Strategy, Venues},
+    utils::{check_keys, round_to},
+};
+
+#[derive(Debug)]
+struct Venue {
+    name: String,
+    client: Box<dyn Client>,
+    fee: f64,
+    // free balances as of the last tick
+    base: f64,
+    quote: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    buy: String,
+    sell: String,
+    buy_price: f64,
+    sell_price: f64,
+    amount: f64,
+    profit: f64,
+    status: String,
+}
+
+// return of buying at `ask` on one venue and selling at `bid` on another,
+// net of both fees
+pub fn net_spread(ask: f64, buy_fee: f64, bid: f64, sell_fee: f64) -> f64 {
+    bid * (1f64 - sell_fee) / (ask * (1f64 + buy_fee)) - 1f64
+}
+
+// watches `symbol` on every venue and, when one venue's bid beats
+// another's ask by more than the fees plus `threshold`, sells there and
+// buys on the cheaper venue at once. The size is capped by the base held
+// on the selling venue and the quote on the buying one, so the combined
+// inventory stays put while it drifts between venues
+#[derive(Debug)]
+pub struct Spread {
+    config: Value,
+    venues: Vec<Venue>,
+    info: Option<SymbolInfo>,
+    // combined base over all venues at the first tick
+    inventory: Option<f64>,
+    history: Vec<Record>,
+    total_profit: f64,
+
+    symbol: String,
+    amount: f64,
+    threshold: f64,
+}
+
+impl Spread {
+    pub fn validate(config: &Value) -> Vec<String> {
+        let mut errors = check_keys(config, &["symbol"], &["amount", "threshold"]);
+        match config["venues"].as_object() {
+            Some(venues) if venues.len() >= 2 => {
+                for (name, venue) in venues {
+                    for err in check_keys(venue, &["host", "apikey", "secret_key"], &[]) {
+                        errors.push(format!("venues.{}.{}", name, err));
+                    }
+                }
+            }
+            _ => errors.push("venues: expect at least two venues".into()),
+        }
+        errors
+    }
+
+    fn refresh_balances(&mut self) {
+        let info = match &self.info {
+            Some(info) => info,
+            None => return,
+        };
+        for venue in &mut self.venues {
+            match (
+                venue.client.get_balance(&info.base),
+                venue.client.get_balance(&info.quote),
+            ) {
+                (Ok(base), Ok(quote)) => {
+                    venue.base = base.free;
+                    venue.quote = quote.free;
+                }
+                (Err(err), _) | (_, Err(err)) => {
+                    warn!("{} get_balance error: {:?}", venue.name, err)
+                }
+            }
+        }
+        let total: f64 = self.venues.iter().map(|venue| venue.base).sum();
+        self.inventory.get_or_insert(total);
+        for venue in &self.venues {
+            metrics::set_gauge(
+                "rsquant_venue_inventory",
+                "Base currency held per venue",
+                &[
+                    ("venue", venue.name.as_str()),
+                    ("symbol", self.symbol.as_str()),
+                ],
+                venue.base,
+            );
+        }
+    }
+
+    // amount executed, the rest of an order that doesn't fill at once is
+    // cancelled
+    fn send(&self, venue: &Venue, side: &str, price: f64, amount: f64) -> f64 {
+        let label = format!("spread {}", venue.name);
+        let ret = send_order(
+            &*venue.client,
+            &label,
+            &self.symbol,
+            price,
+            amount,
+            side,
+            ORDER_TYPE_LIMIT,
+        );
+        let order_id = match ret {
+            Ok(order_id) => order_id,
+            Err(_) => return 0f64,
+        };
+        let filled = match venue.client.get_order(&order_id) {
+            Ok(order) if order.status == "FILLED" => return order.filled,
+            Ok(order) => order.filled,
+            Err(err) => {
+                warn!("{} get_order {} error: {:?}", venue.name, order_id, err);
+                0f64
+            }
+        };
+        warn!(
+            "{} order {} filled {} of {}, cancel",
+            venue.name, order_id, filled, amount
+        );
+        if let Err(err) = venue.client.cancel(&order_id) {
+            warn!("cancel {} error: {:?}", order_id, err);
+        }
+        // what filled until the cancel went through
+        venue
+            .client
+            .get_order(&order_id)
+            .map(|order| order.filled)
+            .unwrap_or(filled)
+    }
+
+    fn trade(&mut self, buy: usize, sell: usize, ask: &Ticker, bid: &Ticker) {
+        let info = match &self.info {
+            Some(info) => info.clone(),
+            None => return,
+        };
+        let (buyer, seller) = (&self.venues[buy], &self.venues[sell]);
+        let (ask_price, bid_price) = (ask.ask.price, bid.bid.price);
+        let amount = self
+            .amount
+            .min(ask.ask.amount)
+            .min(bid.bid.amount)
+            .min(seller.base)
+            .min(buyer.quote / (ask_price * (1f64 + buyer.fee)));
+        let amount = round_to(amount, info.amount_precision as u32);
+        if amount <= 0f64 || amount < info.min_amount || amount * ask_price < info.min_value {
+            return;
+        }
+        // sell the coin we hold first, then buy back what was sold cheaper
+        let mut status = "done";
+        let amount = self.send(seller, ORDER_ACTION_SELL, bid_price, amount);
+        if amount <= 0f64 {
+            return;
+        }
+        let bought = self.send(buyer, ORDER_ACTION_BUY, ask_price, amount);
+        if bought < amount {
+            status = "unhedged";
+            warn!(
+                "{} sold {} on {} but bought {} on {}",
+                self.symbol, amount, seller.name, bought, buyer.name
+            );
+            notify::send(
+                EventKind::Error,
+                &format!("{} spread leg failed", self.symbol),
+                &format!(
+                    "sold {} on {}, bought {} on {}",
+                    amount, seller.name, bought, buyer.name
+                ),
+            );
+        }
+        // the hedged part
+        let profit = bought * (bid_price * (1f64 - seller.fee) - ask_price * (1f64 + buyer.fee));
+        info!(
+            "{} buy {} on {} at {}, sell on {} at {}, profit: {}",
+            self.symbol, amount, buyer.name, ask_price, seller.name, bid_price, profit
+        );
+        if status == "done" {
+            notify::send(
+                EventKind::Fill,
+                &format!("{} spread traded", self.symbol),
+                &format!(
+                    "{} {} -> {}, profit {}",
+                    amount, buyer.name, seller.name, profit
+                ),
+            );
+        }
+        self.total_profit += profit;
+        metrics::set_gauge(
+            "rsquant_total_profit",
+            "Realised profit",
+            &[],
+            self.total_profit,
+        );
+        self.history.push(Record {
+            buy: buyer.name.clone(),
+            sell: seller.name.clone(),
+            buy_price: ask_price,
+            sell_price: bid_price,
+            amount: amount,
+            profit: profit,
+            status: status.into(),
+        });
+    }
+
+    // the symbol's lot size on every venue, left unset until all of them
+    // answered
+    fn load_symbol(&mut self) {
+        let mut merged: Option<SymbolInfo> = None;
+        for venue in &self.venues {
+            let symbols = match venue.client.get_symbols() {
+                Ok(symbols) => symbols,
+                Err(err) => {
+                    warn!("{} get_symbols error: {:?}", venue.name, err);
+                    return;
+                }
+            };
+            let info = symbols
+                .into_iter()
+                .find(|info| info.symbol.eq_ignore_ascii_case(&self.symbol));
+            match info {
+                // the coarsest lot size fits every venue
+                Some(info) => match &mut merged {
+                    Some(current) => {
+                        current.amount_precision =
+                            current.amount_precision.min(info.amount_precision);
+                        current.min_amount = current.min_amount.max(info.min_amount);
+                        current.min_value = current.min_value.max(info.min_value);
+                    }
+                    None => merged = Some(info),
+                },
+                None => warn!("{} not listed on {}", self.symbol, venue.name),
+            }
+        }
+        self.info = merged;
+    }
+}
+
+impl Strategy for Spread {
+    fn new(config_path: &str) -> Box<dyn Strategy> {
+        let file = fs::File::open(config_path).expect("file should open read only");
+        let config: Value = serde_json::from_reader(file).expect("file should be proper json");
+        let venues = exchange::venues(&config)
+            .into_iter()
+            .map(|(name, client)| (name, Box::new(client) as Box<dyn Client>))
+            .collect();
+        Self::from_venues(&config, venues)
+    }
+
+    // a single client trades with itself and never finds a spread
+    fn from_config(config: &Value, client: Box<dyn Client>) -> Box<dyn Strategy> {
+        Self::from_venues(config, vec![("default".into(), client)])
+    }
+
+    fn from_venues(config: &Value, venues: Venues) -> Box<dyn Strategy> {
+        let venues = venues
+            .into_iter()
+            .map(|(name, client)| {
+                let venue = &config["venues"][&name];
+                Venue {
+                    fee: venue["fee"]
+                        .as_f64()
+                        .or(config["fee"].as_f64())
+                        .unwrap_or(0.001),
+                    name: name,
+                    client: client,
+                    base: 0f64,
+                    quote: 0f64,
+                }
+            })
+            .collect();
+        Box::new(Spread {
+            config: config.clone(),
+            venues: venues,
+            info: None,
+            inventory: None,
+            history: vec![],
+            total_profit: 0f64,
+
+            symbol: config["symbol"].as_str().unwrap().to_uppercase(),
+            amount: config["amount"].as_f64().unwrap(),
+            threshold: config["threshold"].as_f64().unwrap(),
+        })
+    }
+
+    fn init(&mut self) {
+        if self.venues.len() < 2 {
+            warn!(
+                "spread needs at least two venues, got {}",
+                self.venues.len()
+            );
+        }
+        self.load_symbol();
+    }
+
+    fn on_tick(&mut self) {
+        // init couldn't load it from every venue, keep trying
+        if self.info.is_none() {
+            self.load_symbol();
+        }
+        self.refresh_balances();
+        let tickers: Vec<Option<Ticker>> = self
+            .venues
+            .iter()
+            .map(|venue| match venue.client.get_ticker(&self.symbol) {
+                Ok(ticker) => Some(ticker),
+                Err(err) => {
+                    warn!("{} get_ticker error: {:?}", venue.name, err);
+                    None
+                }
+            })
+            .collect();
+        let mut best: Option<(usize, usize, f64)> = None;
+        for (buy, ask) in tickers.iter().enumerate() {
+            for (sell, bid) in tickers.iter().enumerate() {
+                let (ask, bid) = match (ask, bid) {
+                    (Some(ask), Some(bid)) if buy != sell => (ask, bid),
+                    _ => continue,
+                };
+                if self.venues[sell].base <= 0f64 {
+                    continue;
+                }
+                let spread = net_spread(
+                    ask.ask.price,
+                    self.venues[buy].fee,
+                    bid.bid.price,
+                    self.venues[sell].fee,
+                );
+                if spread > self.threshold && best.map(|b| spread > b.2).unwrap_or(true) {
+                    best = Some((buy, sell, spread));
+                }
+            }
+        }
+        if let Some((buy, sell, spread)) = best {
+            info!(
+                "{} spread {} between {} and {}",
+                self.symbol, spread, self.venues[buy].name, self.venues[sell].name
+            );
+            let (ask, bid) = (
+                tickers[buy].clone().unwrap(),
+                tickers[sell].clone().unwrap(),
+            );
+            self.trade(buy, sell, &ask, &bid);
+        }
+    }
+
+    fn run_forever(&mut self) {
+        let config = self.config.clone();
+        runner::run(self, &config);
+    }
+
+    fn name(&self) -> String {
+        "spread".into()
+    }
+
+    fn stringify(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn state(&self) -> Value {
+        let venues: Vec<Value> = self
+            .venues
+            .iter()
+            .map(|venue| json!({"name": venue.name, "base": venue.base, "quote": venue.quote}))
+            .collect();
+        let total: f64 = self.venues.iter().map(|venue| venue.base).sum();
+        json!({
+            "strategy": self.name(),
+            "venues": venues,
+            "inventory": total,
+            "baseline": self.inventory,
+            "drift": total - self.inventory.unwrap_or(total),
+            "history": self.history,
+            "total_profit": self.total_profit,
+        })
+    }
+
+    fn restore(&mut self, state: &Value) {
+        if state["strategy"] != "spread" {
+            return;
+        }
+        if let Ok(history) = serde_json::from_value(state["history"].clone()) {
+            self.history = history;
+        }
+        self.total_profit = state["total_profit"].as_f64().unwrap_or(0f64);
+        // state files without a baseline still carry the drift from it
+        self.inventory = state["baseline"]
+            .as_f64()
+            .or_else(|| Some(state["inventory"].as_f64()? - state["drift"].as_f64()?));
+    }
+
+    fn summary(&self) -> String {
+        let inventory: Vec<String> = self
+            .venues
+            .iter()
+            .map(|venue| format!("{}: {}", venue.name, venue.base))
+            .collect();
+        format!(
+            "trades: {}, inventory: {}, total_profit: {}",
+            self.history.len(),
+            inventory.join(", "),
+            self.total_profit
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backtest::data::{
+            fixture::{bars, replay, sim},
+            symbol_info,
+        },
+        exchange::SimExchange,
+    };
+    use rsex::{constant::ORDER_TYPE_MARKET, traits::SpotRest};
+
+    fn venue(closes: &[f64]) -> SimExchange {
+        let data = vec![(symbol_info("ETH", "USDT"), bars(closes, 0, 60_000))];
+        sim(data, &[("ETH", 1f64), ("USDT", 1000f64)], 0.001)
+    }
+
+    #[test]
+    fn test_spread() {
+        let a = venue(&[100f64, 100f64, 100f64]);
+        let b = venue(&[101.5, 101.5, 100f64]);
+        let config = json!({
+            "symbol": "ETHUSDT",
+            "venues": {
+                "a": {"host": "https://a", "apikey": "", "secret_key": ""},
+                "b": {"host": "https://b", "apikey": "", "secret_key": ""},
+            },
+            "amount": 0.6,
+            "threshold": 0.002,
+            "fee": 0.001,
+        });
+        assert!(Spread::validate(&config).is_empty());
+        let venues: Venues = vec![
+            ("a".into(), Box::new(a.clone())),
+            ("b".into(), Box::new(b.clone())),
+        ];
+        let mut robot = Spread::from_venues(&config, venues);
+        replay(&[&a, &b], &mut *robot);
+
+        // the second trade only sells what b has left
+        let state = robot.state();
+        let history = state["history"].as_array().unwrap();
+        let amounts: Vec<f64> = history
+            .iter()
+            .map(|r| r["amount"].as_f64().unwrap())
+            .collect();
+        assert_eq!(amounts, vec![0.6, 0.4]);
+        assert_eq!(history[0]["buy"], "a");
+        assert_eq!(history[0]["sell"], "b");
+        assert_eq!(b.get_balance("ETH").unwrap().free, 0f64);
+        assert!((a.get_balance("ETH").unwrap().free - 2f64).abs() < 1e-9);
+        assert!((state["drift"].as_f64().unwrap()).abs() < 1e-9);
+
+        let profit = (101.5 * 0.999 - 100f64 * 1.001) * 1f64;
+        assert!((state["total_profit"].as_f64().unwrap() - profit).abs() < 1e-9);
+        let usdt = a.get_balance("USDT").unwrap().free + b.get_balance("USDT").unwrap().free;
+        assert!((usdt - 2000f64 - profit).abs() < 1e-6);
+
+        // restarted after 0.5 left the venues, the drift is still measured
+        // from the first baseline
+        a.create_order("ETHUSDT", 0f64, 0.5, ORDER_ACTION_SELL, ORDER_TYPE_MARKET)
+            .unwrap();
+        let venues: Venues = vec![
+            ("a".into(), Box::new(a.clone())),
+            ("b".into(), Box::new(b.clone())),
+        ];
+        let mut robot = Spread::from_venues(&config, venues);
+        robot.restore(&state);
+        robot.init();
+        robot.on_tick();
+        assert!((robot.state()["drift"].as_f64().unwrap() + 0.5).abs() < 1e-9);
+    }
+}
@@ -0,0 +1,618 @@
+use log::{debug, info, warn};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{collections::HashMap, fs};
+use rsex::{
+    constant::{ORDER_ACTION_BUY, ORDER_ACTION_SELL, ORDER_TYPE_LIMIT, ORDER_TYPE_MARKET},
+    models::{SymbolInfo, Ticker},
+};
+use crate::{
+    exchange, metrics,
+    notify::{self, EventKind},
+    runner,
+    strategies::send_order,
+    traits::{Client, Strategy},
+    utils::{check_keys, round_to},
+};
+
+// one conversion of a cycle, `from` is spent and `to` received
+#[derive(Debug, Clone)]
+struct Edge {
+    info: SymbolInfo,
+    side: &'static str,
+    from: String,
+    to: String,
+}
+
+// a leg priced at the touch, `depth` is the base amount quoted there
+#[derive(Debug, Clone)]
+pub struct Leg {
+    pub side: &'static str,
+    pub price: f64,
+    pub depth: f64,
+    pub amount_precision: u8,
+    pub min_amount: f64,
+    pub min_value: f64,
+}
+
+// what an order of `amount` base spends, in the asset it converts from
+fn spent(side: &str, price: f64, amount: f64) -> f64 {
+    if side == ORDER_ACTION_BUY {
+        amount * price
+    } else {
+        amount
+    }
+}
+
+// what an order of `amount` base receives: the fee comes out of the asset
+// received, the base of a buy as binance does
+fn received(side: &str, price: f64, amount: f64, fee: f64) -> f64 {
+    if side == ORDER_ACTION_BUY {
+        amount * (1f64 - fee)
+    } else {
+        amount * price * (1f64 - fee)
+    }
+}
+
+// base amount of every order and what the cycle ends with, starting from
+// `start` of the first `from` asset. None when an order breaks the lot
+// size rules
+pub fn simulate(legs: &[Leg], start: f64, fee: f64) -> Option<(Vec<f64>, f64)> {
+    let mut have = start;
+    let mut amounts = vec![];
+    for leg in legs {
+        let amount = if leg.side == ORDER_ACTION_BUY {
+            have / leg.price
+        } else {
+            have
+        };
+        let amount = round_to(amount, leg.amount_precision as u32);
+        if amount <= 0f64 || amount < leg.min_amount || amount * leg.price < leg.min_value {
+            return None;
+        }
+        amounts.push(amount);
+        have = received(leg.side, leg.price, amount, fee);
+    }
+    Some((amounts, have))
+}
+
+// the start amount scaled down until every order fits the quoted depth
+pub fn size(legs: &[Leg], start: f64, fee: f64) -> Option<(Vec<f64>, f64, f64)> {
+    let mut start = start;
+    for _ in 0..legs.len() {
+        let (amounts, end) = simulate(legs, start, fee)?;
+        let fill = amounts
+            .iter()
+            .zip(legs)
+            .map(|(amount, leg)| (leg.depth / amount).min(1f64))
+            .fold(1f64, f64::min);
+        if fill >= 1f64 {
+            return Some((amounts, start, end));
+        }
+        start *= fill;
+    }
+    None
+}
+
+struct Candidate {
+    index: usize,
+    legs: Vec<Leg>,
+    amounts: Vec<f64>,
+    start: f64,
+    ratio: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Record {
+    cycle: String,
+    start: f64,
+    end: f64,
+    profit: f64,
+    status: String,
+}
+
+// evaluates every start -> A -> B -> start cycle on each tick and trades
+// the best one clearing `min_profit` after fees, one order at a time, each
+// leg sized on what the previous one received. A leg that doesn't fill at
+// once is cancelled, the cycle goes on with its executed part and what is
+// left over is sold back into `start` at market
+#[derive(Debug)]
+pub struct Triangular {
+    config: Value,
+    client: Box<dyn Client>,
+    cycles: Vec<Vec<Edge>>,
+    history: Vec<Record>,
+    total_profit: f64,
+
+    start: String,
+    assets: Vec<String>,
+    amount: f64,
+    min_profit: f64,
+    fee: f64,
+}
+
+impl Triangular {
+    pub fn validate(config: &Value) -> Vec<String> {
+        let mut errors = check_keys(config, &["start"], &["amount"]);
+        if config["amount"].as_f64().unwrap_or(0f64) <= 0f64 {
+            errors.push("amount: expect > 0".into());
+        }
+        if !config["assets"].is_null() && !config["assets"].is_array() {
+            errors.push("assets: expect a list of coins".into());
+        }
+        errors
+    }
+
+    fn build_cycles(&self, symbols: &[SymbolInfo]) -> Vec<Vec<Edge>> {
+        let allowed = |asset: &str| {
+            asset == self.start || self.assets.is_empty() || self.assets.iter().any(|a| a == asset)
+        };
+        // every conversion out of an asset
+        let mut graph: HashMap<String, Vec<Edge>> = HashMap::new();
+        for info in symbols {
+            let base = info.base.to_uppercase();
+            let quote = info.quote.to_uppercase();
+            if !allowed(&base) || !allowed(&quote) {
+                continue;
+            }
+            graph.entry(quote.clone()).or_default().push(Edge {
+                info: info.clone(),
+                side: ORDER_ACTION_BUY,
+                from: quote.clone(),
+                to: base.clone(),
+            });
+            graph.entry(base.clone()).or_default().push(Edge {
+                info: info.clone(),
+                side: ORDER_ACTION_SELL,
+                from: base,
+                to: quote,
+            });
+        }
+        let none = vec![];
+        let mut cycles = vec![];
+        for first in graph.get(&self.start).unwrap_or(&none) {
+            for second in graph.get(&first.to).unwrap_or(&none) {
+                if second.to == self.start {
+                    continue;
+                }
+                for third in graph.get(&second.to).unwrap_or(&none) {
+                    if third.to == self.start {
+                        cycles.push(vec![first.clone(), second.clone(), third.clone()]);
+                    }
+                }
+            }
+        }
+        cycles
+    }
+
+    fn name_of(cycle: &[Edge]) -> String {
+        let mut assets: Vec<&str> = cycle.iter().map(|edge| edge.from.as_str()).collect();
+        assets.push(cycle[0].from.as_str());
+        assets.join(">")
+    }
+
+    fn legs(cycle: &[Edge], tickers: &HashMap<String, Ticker>) -> Option<Vec<Leg>> {
+        cycle
+            .iter()
+            .map(|edge| {
+                let ticker = tickers.get(&edge.info.symbol)?;
+                let (price, depth) = if edge.side == ORDER_ACTION_BUY {
+                    (ticker.ask.price, ticker.ask.amount)
+                } else {
+                    (ticker.bid.price, ticker.bid.amount)
+                };
+                if price <= 0f64 {
+                    return None;
+                }
+                Some(Leg {
+                    side: edge.side,
+                    price: price,
+                    depth: depth,
+                    amount_precision: edge.info.amount_precision,
+                    min_amount: edge.info.min_amount,
+                    min_value: edge.info.min_value,
+                })
+            })
+            .collect()
+    }
+
+    fn tickers(&self) -> HashMap<String, Ticker> {
+        let mut tickers = HashMap::new();
+        for edge in self.cycles.iter().flatten() {
+            if tickers.contains_key(&edge.info.symbol) {
+                continue;
+            }
+            match self.client.get_ticker(&edge.info.symbol) {
+                Ok(ticker) => {
+                    tickers.insert(edge.info.symbol.clone(), ticker);
+                }
+                Err(err) => debug!("get_ticker {} error: {:?}", edge.info.symbol, err),
+            }
+        }
+        tickers
+    }
+
+    // `have` of `asset`, no more than its free balance
+    fn held(&self, asset: &str, have: f64) -> f64 {
+        match self.client.get_balance(asset) {
+            Ok(balance) => have.min(balance.free),
+            Err(err) => {
+                warn!("get_balance {} error: {:?}", asset, err);
+                have
+            }
+        }
+    }
+
+    // base amount executed, the rest of an order that doesn't fill at once
+    // is cancelled
+    fn send(&self, edge: &Edge, price: f64, amount: f64, order_type: &str) -> f64 {
+        let ret = send_order(
+            &*self.client,
+            "triangular",
+            &edge.info.symbol,
+            price,
+            amount,
+            edge.side,
+            order_type,
+        );
+        let order_id = match ret {
+            Ok(order_id) => order_id,
+            Err(_) => return 0f64,
+        };
+        let filled = match self.client.get_order(&order_id) {
+            Ok(order) if order.status == "FILLED" => return order.filled,
+            Ok(order) => order.filled,
+            Err(err) => {
+                warn!("get_order {} error: {:?}", order_id, err);
+                0f64
+            }
+        };
+        warn!("order {} filled {} of {}, cancel", order_id, filled, amount);
+        if let Err(err) = self.client.cancel(&order_id) {
+            warn!("cancel {} error: {:?}", order_id, err);
+        }
+        // what filled until the cancel went through
+        self.client
+            .get_order(&order_id)
+            .map(|order| order.filled)
+            .unwrap_or(filled)
+    }
+
+    // sell `amount` of `held` back into the start asset, what it got
+    fn unwind(&self, cycle: &[Edge], held: &str, amount: f64) -> f64 {
+        let linked = |edge: &&Edge| {
+            (edge.from == held && edge.to == self.start)
+                || (edge.from == self.start && edge.to == held)
+        };
+        let edge = match cycle.iter().find(linked) {
+            Some(edge) => edge,
+            None => return 0f64,
+        };
+        let back = Edge {
+            info: edge.info.clone(),
+            side: if edge.info.base.eq_ignore_ascii_case(held) {
+                ORDER_ACTION_SELL
+            } else {
+                ORDER_ACTION_BUY
+            },
+            from: held.into(),
+            to: self.start.clone(),
+        };
+        let amount = self.held(held, amount);
+        let ticker = match self.client.get_ticker(&back.info.symbol) {
+            Ok(ticker) => ticker,
+            Err(err) => {
+                warn!("unwind get_ticker {} error: {:?}", back.info.symbol, err);
+                return 0f64;
+            }
+        };
+        // a buy gets `amount` of quote worth of the start coin
+        let (price, base) = if back.side == ORDER_ACTION_SELL {
+            (ticker.bid.price, amount)
+        } else {
+            (ticker.ask.price, amount / ticker.ask.price)
+        };
+        let base = round_to(base, back.info.amount_precision as u32);
+        let filled = if base > 0f64 {
+            self.send(&back, price, base, ORDER_TYPE_MARKET)
+        } else {
+            0f64
+        };
+        if filled < base || base <= 0f64 {
+            warn!(
+                "unwind {} {} filled {}, holding {}",
+                back.info.symbol, base, filled, held
+            );
+        }
+        received(back.side, price, filled, self.fee)
+    }
+
+    fn execute(&mut self, cycle: &[Edge], legs: &[Leg], amounts: &[f64], start: f64) {
+        let name = Self::name_of(cycle);
+        let mut start = start;
+        // what the last leg received and what came back from leftovers
+        let mut have = 0f64;
+        let mut unwound = 0f64;
+        for (i, (edge, leg)) in cycle.iter().zip(legs).enumerate() {
+            let amount = if i == 0 {
+                amounts[0]
+            } else {
+                let have = self.held(&edge.from, have);
+                let amount = if leg.side == ORDER_ACTION_BUY {
+                    have / leg.price
+                } else {
+                    have
+                };
+                round_to(amount, leg.amount_precision as u32)
+            };
+            let filled = if amount > 0f64 {
+                self.send(edge, leg.price, amount, ORDER_TYPE_LIMIT)
+            } else {
+                0f64
+            };
+            // the opportunity was gone before anything was bought, nothing
+            // to unwind or report
+            if filled <= 0f64 && i == 0 {
+                debug!(
+                    "{} first leg {} not filled, skipped",
+                    name, edge.info.symbol
+                );
+                return;
+            }
+            if filled <= 0f64 {
+                let end = unwound + self.unwind(cycle, &edge.from, have);
+                let profit = end - start;
+                warn!("{} aborted at leg {}, profit: {}", name, i + 1, profit);
+                notify::send(
+                    EventKind::Error,
+                    &format!("{} aborted", name),
+                    &format!(
+                        "leg {} {} failed, unwound with {} {}",
+                        i + 1,
+                        edge.info.symbol,
+                        profit,
+                        self.start
+                    ),
+                );
+                self.record(name, start, end, profit, "aborted");
+                return;
+            }
+            if i == 0 {
+                // a partial first leg starts the cycle with less
+                start = spent(leg.side, leg.price, filled);
+            } else if filled < amount {
+                let left = have - spent(leg.side, leg.price, filled);
+                unwound += self.unwind(cycle, &edge.from, left);
+            }
+            have = received(leg.side, leg.price, filled, self.fee);
+        }
+        let end = have + unwound;
+        let profit = end - start;
+        info!("{} done, {} -> {}, profit: {}", name, start, end, profit);
+        notify::send(
+            EventKind::Fill,
+            &format!("{} done", name),
+            &format!("{} {} -> {}, profit {}", start, self.start, end, profit),
+        );
+        self.record(name, start, end, profit, "done");
+    }
+
+    fn record(&mut self, cycle: String, start: f64, end: f64, profit: f64, status: &str) {
+        self.total_profit += profit;
+        metrics::set_gauge(
+            "rsquant_total_profit",
+            "Realised profit",
+            &[],
+            self.total_profit,
+        );
+        self.history.push(Record {
+            cycle: cycle,
+            start: start,
+            end: end,
+            profit: profit,
+            status: status.into(),
+        });
+    }
+
+    fn load_cycles(&mut self) {
+        match self.client.get_symbols() {
+            Ok(symbols) => self.cycles = self.build_cycles(&symbols),
+            Err(err) => {
+                warn!("get_symbols error: {:?}", err);
+                return;
+            }
+        }
+        info!(
+            "{} triangular cycles from {}",
+            self.cycles.len(),
+            self.start
+        );
+    }
+}
+
+impl Strategy for Triangular {
+    fn new(config_path: &str) -> Box<dyn Strategy> {
+        let file = fs::File::open(config_path).expect("file should open read only");
+        let config: Value = serde_json::from_reader(file).expect("file should be proper json");
+        let client = exchange::binance(&config);
+        Self::from_config(&config, Box::new(client))
+    }
+
+    fn from_config(config: &Value, client: Box<dyn Client>) -> Box<dyn Strategy> {
+        Box::new(Triangular {
+            config: config.clone(),
+            client: client,
+            cycles: vec![],
+            history: vec![],
+            total_profit: 0f64,
+
+            start: config["start"].as_str().unwrap().to_uppercase(),
+            assets: config["assets"]
+                .as_array()
+                .map(|assets| {
+                    assets
+                        .iter()
+                        .filter_map(|a| a.as_str())
+                        .map(|a| a.to_uppercase())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            amount: config["amount"].as_f64().unwrap(),
+            min_profit: config["min_profit"].as_f64().unwrap_or(0.001),
+            fee: config["fee"].as_f64().unwrap_or(0.001),
+        })
+    }
+
+    fn init(&mut self) {
+        self.load_cycles();
+    }
+
+    fn on_tick(&mut self) {
+        // init couldn't load the symbols, keep trying
+        if self.cycles.is_empty() {
+            self.load_cycles();
+        }
+        let tickers = self.tickers();
+        let mut best: Option<Candidate> = None;
+        for (i, cycle) in self.cycles.iter().enumerate() {
+            let legs = match Self::legs(cycle, &tickers) {
+                Some(legs) => legs,
+                None => continue,
+            };
+            let (amounts, start, end) = match size(&legs, self.amount, self.fee) {
+                Some(sized) => sized,
+                None => continue,
+            };
+            let ratio = end / start - 1f64;
+            debug!("{}: {}", Self::name_of(cycle), ratio);
+            if ratio > self.min_profit && best.as_ref().map(|b| ratio > b.ratio).unwrap_or(true) {
+                best = Some(Candidate {
+                    index: i,
+                    legs: legs,
+                    amounts: amounts,
+                    start: start,
+                    ratio: ratio,
+                });
+            }
+        }
+        if let Some(best) = best {
+            let cycle = self.cycles[best.index].clone();
+            info!("{} expected return {}", Self::name_of(&cycle), best.ratio);
+            self.execute(&cycle, &best.legs, &best.amounts, best.start);
+        }
+    }
+
+    fn run_forever(&mut self) {
+        let config = self.config.clone();
+        runner::run(self, &config);
+    }
+
+    fn name(&self) -> String {
+        "triangular".into()
+    }
+
+    fn stringify(&self) -> String {
+        format!("{:?}", self)
+    }
+
+    fn state(&self) -> Value {
+        json!({
+            "strategy": self.name(),
+            "history": self.history,
+            "total_profit": self.total_profit,
+        })
+    }
+
+    fn restore(&mut self, state: &Value) {
+        if state["strategy"] != "triangular" {
+            return;
+        }
+        if let Ok(history) = serde_json::from_value(state["history"].clone()) {
+            self.history = history;
+        }
+        self.total_profit = state["total_profit"].as_f64().unwrap_or(0f64);
+    }
+
+    fn summary(&self) -> String {
+        let aborted = self
+            .history
+            .iter()
+            .filter(|r| r.status == "aborted")
+            .count();
+        format!(
+            "cycles traded: {}, aborted: {}, total_profit: {} {}",
+            self.history.len(),
+            aborted,
+            self.total_profit,
+            self.start
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::backtest::data::{
+        fixture::{bars, replay, sim},
+        symbol_info,
+    };
+    use rsex::traits::SpotRest;
+
+    #[test]
+    fn test_simulate() {
+        let leg = |side, price| Leg {
+            side: side,
+            price: price,
+            depth: f64::MAX,
+            amount_precision: 3,
+            min_amount: 0f64,
+            min_value: 0f64,
+        };
+        let legs = vec![leg(ORDER_ACTION_BUY, 10f64), leg(ORDER_ACTION_SELL, 20f64)];
+        let (amounts, end) = simulate(&legs, 100f64, 0f64).unwrap();
+        assert_eq!(amounts, vec![10f64, 10f64]);
+        assert_eq!(end, 200f64);
+        // rounded down to the lot size
+        // the fee comes out of the base bought, sold on
+        let (amounts, end) = simulate(&legs, 100f64, 0.001).unwrap();
+        assert_eq!(amounts, vec![10f64, 9.99]);
+        assert!((end - 9.99 * 20f64 * 0.999).abs() < 1e-9);
+        // below min_value
+        let mut small = legs.clone();
+        small[1].min_value = 500f64;
+        assert!(simulate(&small, 100f64, 0f64).is_none());
+        // half the depth, half the start
+        let mut shallow = legs;
+        shallow[1].depth = 5f64;
+        let (amounts, start, _) = size(&shallow, 100f64, 0f64).unwrap();
+        assert_eq!((amounts[1], start), (5f64, 50f64));
+    }
+
+    #[test]
+    fn test_triangular() {
+        let klines = |closes: &[f64]| bars(closes, 0, 60_000);
+        // 1 USDT -> 0.0001 BTC -> 0.002 ETH -> 1.02 USDT, then no gap
+        let data = vec![
+            (symbol_info("BTC", "USDT"), klines(&[10000f64, 10000f64])),
+            (symbol_info("ETH", "BTC"), klines(&[0.05, 0.05])),
+            (symbol_info("ETH", "USDT"), klines(&[510f64, 500f64])),
+        ];
+        let exchange = sim(data, &[("USDT", 1000f64)], 0.001);
+        let config = json!({"start": "usdt", "amount": 100, "min_profit": 0.005, "fee": 0.001});
+        assert!(Triangular::validate(&config).is_empty());
+        let mut robot = Triangular::from_config(&config, Box::new(exchange.clone()));
+        replay(&[&exchange], &mut *robot);
+        let state = robot.state();
+        let history = state["history"].as_array().unwrap();
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0]["cycle"], "USDT>BTC>ETH>USDT");
+        assert_eq!(history[0]["status"], "done");
+        let profit = state["total_profit"].as_f64().unwrap();
+        assert!(profit > 1.5 && profit < 2f64);
+        // the simulator takes the 0.1 usdt fee of the first buy in usdt
+        // rather than in btc, where the cycle leaves it as dust
+        let usdt = exchange.get_balance("USDT").unwrap().free;
+        assert!((usdt - 1000f64 - profit + 0.1).abs() < 0.01);
+        assert!(exchange.get_balance("BTC").unwrap().free < 0.0001);
+    }
+}
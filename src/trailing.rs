@@ -0,0 +1,274 @@
+use serde_json::Value;
+
+use crate::utils::{period_ms, round_to};
+
+// where a position stands: current and peak profit as ratios of the entry
+// price, and the ATR as a ratio of the entry price when known
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Trail {
+    pub profit: f64,
+    pub high: f64,
+    pub atr: Option<f64>,
+}
+
+// withdraw curve of a trailing stop: the share of the peak profit to keep,
+// the position is sold once profit <= high * ratio
+//
+// "trailing" config:
+//   {"type": "formula"}                                 // (10x - 10a + 1) / 10x
+//   {"type": "step", "steps": [[0.3, 0.5], [0.5, 0.7]]}  // [profit, ratio]
+//   {"type": "linear", "from": [0.3, 0.5], "to": [1.0, 0.9]}
+//   {"type": "percent", "trail": 0.1}                   // 10% below the high
+//   {"type": "chandelier", "multiplier": 3, "period": "1h", "length": 22, "trail": 0.1}
+// formula, step and linear take the strategy's start_threshold and
+// withdraw_ratio, chandelier keeps the high minus multiplier ATRs and
+// falls back to `trail` percent without klines
+#[derive(Debug, Clone)]
+pub enum Curve {
+    Formula {
+        start_threshold: f64,
+        withdraw_ratio: f64,
+    },
+    Step {
+        steps: Vec<(f64, f64)>,
+        withdraw_ratio: f64,
+    },
+    Linear {
+        from: (f64, f64),
+        to: (f64, f64),
+        withdraw_ratio: f64,
+    },
+    Percent {
+        trail: f64,
+    },
+    Chandelier {
+        multiplier: f64,
+        period: String,
+        length: usize,
+        trail: f64,
+    },
+}
+
+fn point(config: &Value, name: &str) -> Result<(f64, f64), String> {
+    match (config[0].as_f64(), config[1].as_f64()) {
+        (Some(profit), Some(ratio)) => Ok((profit, ratio)),
+        _ => Err(format!("trailing.{}: expect [profit, ratio]", name)),
+    }
+}
+
+impl Curve {
+    pub fn from_config(
+        config: &Value,
+        start_threshold: f64,
+        withdraw_ratio: f64,
+    ) -> Result<Self, String> {
+        match config["type"].as_str().unwrap_or("formula") {
+            "formula" => Ok(Curve::Formula {
+                start_threshold: start_threshold,
+                withdraw_ratio: withdraw_ratio,
+            }),
+            "step" => {
+                let mut steps = vec![];
+                for step in config["steps"].as_array().cloned().unwrap_or_default() {
+                    steps.push(point(&step, "steps")?);
+                }
+                if steps.is_empty() {
+                    return Err("trailing.steps: expect [[profit, ratio], ..]".into());
+                }
+                steps.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+                Ok(Curve::Step {
+                    steps: steps,
+                    withdraw_ratio: withdraw_ratio,
+                })
+            }
+            "linear" => {
+                let from = point(&config["from"], "from")?;
+                let to = point(&config["to"], "to")?;
+                if from.0 >= to.0 {
+                    return Err("trailing: from profit should be below to profit".into());
+                }
+                Ok(Curve::Linear {
+                    from: from,
+                    to: to,
+                    withdraw_ratio: withdraw_ratio,
+                })
+            }
+            "percent" => Ok(Curve::Percent {
+                trail: trail(config)?,
+            }),
+            "chandelier" => {
+                let period = config["period"].as_str().unwrap_or("1h");
+                if period_ms(period).is_none() {
+                    return Err(format!("trailing.period: invalid period {:?}", period));
+                }
+                Ok(Curve::Chandelier {
+                    multiplier: config["multiplier"].as_f64().unwrap_or(3f64),
+                    period: period.into(),
+                    length: config["length"].as_u64().unwrap_or(22) as usize,
+                    trail: trail(config)?,
+                })
+            }
+            other => Err(format!("unknown trailing type: {}", other)),
+        }
+    }
+
+    // kline period and length the ATR should be computed over
+    pub fn atr(&self) -> Option<(&str, usize)> {
+        match self {
+            Curve::Chandelier { period, length, .. } => Some((period, *length)),
+            _ => None,
+        }
+    }
+
+    pub fn ratio(&self, t: &Trail) -> f64 {
+        match self {
+            Curve::Formula {
+                start_threshold,
+                withdraw_ratio,
+            } => formula(t.profit, *start_threshold, *withdraw_ratio),
+            Curve::Step {
+                steps,
+                withdraw_ratio,
+            } => step(t.profit, steps, *withdraw_ratio),
+            Curve::Linear {
+                from,
+                to,
+                withdraw_ratio,
+            } => linear(t.profit, *from, *to, *withdraw_ratio),
+            Curve::Percent { trail } => percent(t.high, *trail),
+            Curve::Chandelier {
+                multiplier, trail, ..
+            } => match t.atr {
+                Some(atr) => chandelier(t.high, atr, *multiplier),
+                None => percent(t.high, *trail),
+            },
+        }
+    }
+}
+
+fn trail(config: &Value) -> Result<f64, String> {
+    match config["trail"].as_f64().unwrap_or(0.1) {
+        trail if trail > 0f64 && trail < 1f64 => Ok(trail),
+        trail => Err(format!("trailing.trail should be in (0, 1): {}", trail)),
+    }
+}
+
+// y = (10*x-10*a+1)/10*x, in steps of 10% profit
+pub fn formula(profit: f64, start_threshold: f64, withdraw_ratio: f64) -> f64 {
+    if profit < start_threshold {
+        return withdraw_ratio;
+    }
+    (round_to(profit * 10f64, 0) - round_to(start_threshold * 10f64, 0) + 1f64)
+        / round_to(profit * 10f64, 0)
+}
+
+// ratio of the highest step reached
+pub fn step(profit: f64, steps: &[(f64, f64)], withdraw_ratio: f64) -> f64 {
+    steps
+        .iter()
+        .rev()
+        .find(|(threshold, _)| profit >= *threshold)
+        .map(|(_, ratio)| *ratio)
+        .unwrap_or(withdraw_ratio)
+}
+
+// interpolated between two points, flat beyond `to`
+pub fn linear(profit: f64, from: (f64, f64), to: (f64, f64), withdraw_ratio: f64) -> f64 {
+    if profit < from.0 {
+        return withdraw_ratio;
+    }
+    if profit >= to.0 {
+        return to.1;
+    }
+    from.1 + (to.1 - from.1) * (profit - from.0) / (to.0 - from.0)
+}
+
+// sell `trail` below the highest price
+pub fn percent(high: f64, trail: f64) -> f64 {
+    if high <= 0f64 {
+        return 0f64;
+    }
+    ((1f64 + high) * (1f64 - trail) - 1f64) / high
+}
+
+// sell `multiplier` ATRs below the highest price
+pub fn chandelier(high: f64, atr: f64, multiplier: f64) -> f64 {
+    if high <= 0f64 {
+        return 0f64;
+    }
+    (high - multiplier * atr) / high
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn test_curves() {
+        // the original move_stoploss curve
+        assert_eq!(formula(0.2, 0.3, 0.5), 0.5);
+        assert!(close(formula(0.5, 0.3, 0.5), 0.6));
+        assert!(close(formula(1.0, 0.3, 0.5), 0.8));
+
+        let steps = [(0.3, 0.5), (0.6, 0.7)];
+        assert_eq!(step(0.1, &steps, 0.4), 0.4);
+        assert_eq!(step(0.4, &steps, 0.4), 0.5);
+        assert_eq!(step(0.9, &steps, 0.4), 0.7);
+
+        assert_eq!(linear(0.1, (0.3, 0.5), (1.0, 0.9), 0.4), 0.4);
+        assert!(close(linear(0.65, (0.3, 0.5), (1.0, 0.9), 0.4), 0.7));
+        assert_eq!(linear(2.0, (0.3, 0.5), (1.0, 0.9), 0.4), 0.9);
+
+        // high at +50%, 10% below it is +35%
+        assert!(close(percent(0.5, 0.1), 0.7));
+        // high at +50%, 2 ATRs of 5% below it is +40%
+        assert!(close(chandelier(0.5, 0.05, 2f64), 0.8));
+    }
+
+    #[test]
+    fn test_from_config() {
+        let curve = Curve::from_config(&Value::Null, 0.3, 0.5).unwrap();
+        let t = Trail {
+            profit: 0.5,
+            high: 0.6,
+            atr: None,
+        };
+        assert!(close(curve.ratio(&t), 0.6));
+        assert!(curve.atr().is_none());
+
+        let curve =
+            Curve::from_config(&json!({"type": "chandelier", "multiplier": 2}), 0.3, 0.5).unwrap();
+        assert_eq!(curve.atr(), Some(("1h", 22)));
+        assert!(close(
+            curve.ratio(&Trail {
+                atr: Some(0.05),
+                ..t
+            }),
+            (0.6 - 0.1) / 0.6
+        ));
+        // no klines, 10% trailing
+        assert!(close(curve.ratio(&t), percent(0.6, 0.1)));
+
+        let curve = Curve::from_config(
+            &json!({"type": "step", "steps": [[0.6, 0.7], [0.3, 0.5]]}),
+            0.3,
+            0.5,
+        )
+        .unwrap();
+        assert_eq!(curve.ratio(&t), 0.5);
+
+        assert!(Curve::from_config(
+            &json!({"type": "linear", "from": [1, 0.5], "to": [0.5, 0.9]}),
+            0.3,
+            0.5
+        )
+        .is_err());
+        assert!(Curve::from_config(&json!({"type": "percent", "trail": 1.5}), 0.3, 0.5).is_err());
+        assert!(Curve::from_config(&json!({"type": "x"}), 0.3, 0.5).is_err());
+    }
+}
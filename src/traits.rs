@@ -1,9 +1,42 @@
+use rsex::traits::SpotRest;
+use serde_json::Value;
+use std::fmt::Debug;
+
+pub trait Client: SpotRest + Debug {}
+
+impl<T: SpotRest + Debug> Client for T {}
+
+// named clients of a strategy trading on several exchanges
+pub type Venues = Vec<(String, Box<dyn Client>)>;
+
 pub trait Strategy {
     fn new(config_path: &str) -> Box<dyn Strategy>
     where
         Self: Sized;
+    fn from_config(config: &Value, client: Box<dyn Client>) -> Box<dyn Strategy>
+    where
+        Self: Sized;
+    // one client per configured venue, strategies on a single exchange
+    // take the first
+    fn from_venues(config: &Value, venues: Venues) -> Box<dyn Strategy>
+    where
+        Self: Sized,
+    {
+        let (_, client) = venues.into_iter().next().expect("at least one venue");
+        Self::from_config(config, client)
+    }
+    fn init(&mut self);
+    fn on_tick(&mut self);
     fn run_forever(&mut self);
 
     fn name(&self) -> String;
     fn stringify(&self) -> String;
+    fn state(&self) -> Value {
+        Value::Null
+    }
+    fn restore(&mut self, _state: &Value) {}
+    fn cancel_orders(&mut self) {}
+    fn summary(&self) -> String {
+        self.stringify()
+    }
 }
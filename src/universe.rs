@@ -0,0 +1,185 @@
+use log::{info, warn};
+use rsex::models::{Kline, SymbolInfo};
+use serde_json::Value;
+
+use crate::traits::Client;
+
+const DAY_MS: u64 = 86_400_000;
+
+// "universe" config of a strategy:
+//   {"whitelist": ["eth", "xrpbtc"], "min_volume": 1000000,
+//    "active_only": true, "refresh_ticks": 60}
+// whitelist entries match a base coin or a whole symbol, min_volume is
+// the 24h volume in the strategy's reporting currency. Volume checks use
+// the last 24 hourly klines, a symbol without trades in the 24h before
+// the newest kline seen (halted or delisted) is dropped when any volume
+// check is on. refresh_ticks = 0 selects only once
+#[derive(Debug, Clone, Default)]
+pub struct Universe {
+    whitelist: Vec<String>,
+    ignore: Vec<String>,
+    min_volume: f64,
+    active_only: bool,
+    refresh_ticks: u64,
+}
+
+impl Universe {
+    // `ignore` is the strategy's list of excluded base coins
+    pub fn from_config(config: &Value, ignore: &Value) -> Self {
+        let lowercase = |list: &Value| -> Vec<String> {
+            list.as_array()
+                .map(|list| {
+                    list.iter()
+                        .filter_map(|v| v.as_str())
+                        .map(|v| v.to_lowercase())
+                        .collect()
+                })
+                .unwrap_or_default()
+        };
+        Universe {
+            whitelist: lowercase(&config["whitelist"]),
+            ignore: lowercase(ignore),
+            min_volume: config["min_volume"].as_f64().unwrap_or(0f64),
+            active_only: config["active_only"].as_bool().unwrap_or(false),
+            refresh_ticks: config["refresh_ticks"].as_u64().unwrap_or(0),
+        }
+    }
+
+    pub fn due(&self, tick: u64) -> bool {
+        self.refresh_ticks > 0 && tick > 0 && tick % self.refresh_ticks == 0
+    }
+
+    fn listed(&self, info: &SymbolInfo) -> bool {
+        let base = info.base.to_lowercase();
+        let symbol = info.symbol.to_lowercase();
+        if self.ignore.contains(&base) {
+            return false;
+        }
+        self.whitelist.is_empty()
+            || self.whitelist.contains(&base)
+            || self.whitelist.contains(&symbol)
+    }
+
+    // `value(info, amount)` converts an amount of the symbol's quote into
+    // the reporting currency
+    pub fn select<F>(
+        &self,
+        client: &dyn Client,
+        symbols: Vec<SymbolInfo>,
+        value: F,
+    ) -> Vec<SymbolInfo>
+    where
+        F: Fn(&SymbolInfo, f64) -> f64,
+    {
+        let symbols: Vec<SymbolInfo> = symbols
+            .into_iter()
+            .filter(|info| self.listed(info))
+            .collect();
+        if self.min_volume <= 0f64 && !self.active_only {
+            return symbols;
+        }
+        let mut candidates: Vec<(SymbolInfo, Vec<Kline>)> = vec![];
+        for info in symbols {
+            match client.get_kline(&info.symbol, "1h", 24) {
+                Ok(klines) => candidates.push((info, klines)),
+                Err(err) => warn!("{} get_kline error: {:?}, skipped", info.symbol, err),
+            }
+        }
+        let newest = candidates
+            .iter()
+            .filter_map(|(_, klines)| klines.last())
+            .map(|kline| kline.timestamp)
+            .max()
+            .unwrap_or(0);
+        let since = newest.saturating_sub(DAY_MS);
+        candidates
+            .into_iter()
+            .filter(|(info, klines)| {
+                let volume: f64 = klines
+                    .iter()
+                    .filter(|kline| kline.timestamp > since)
+                    .map(|kline| kline.volume * kline.close)
+                    .sum();
+                let volume = value(info, volume);
+                let keep = volume > 0f64 && volume >= self.min_volume;
+                if !keep {
+                    info!(
+                        "{} 24h volume {} below {}, skipped",
+                        info.symbol, volume, self.min_volume
+                    );
+                }
+                keep
+            })
+            .map(|(info, _)| info)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        backtest::data::{fixture::bars, symbol_info},
+        exchange::SimExchange,
+    };
+    use serde_json::json;
+    use std::{collections::HashMap, sync::Arc};
+
+    fn klines(hours: usize, volume: f64) -> Arc<Vec<Kline>> {
+        let bars = bars(&vec![10f64; hours], 0, 3_600_000);
+        Arc::new(
+            bars.iter()
+                .map(|kline| Kline {
+                    volume: volume,
+                    ..kline.clone()
+                })
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_select() {
+        let data = vec![
+            (symbol_info("ETH", "USDT"), klines(48, 100f64)),
+            (symbol_info("XRP", "USDT"), klines(48, 1f64)),
+            (symbol_info("DOGE", "USDT"), klines(48, 0f64)),
+            // no bars in the last day
+            (symbol_info("OLD", "USDT"), klines(10, 100f64)),
+        ];
+        let exchange = SimExchange::new(data.clone(), &HashMap::new(), 0f64, 0f64);
+        exchange.advance(47 * 3_600_000);
+        let symbols: Vec<SymbolInfo> = data.into_iter().map(|(info, _)| info).collect();
+        let names = |selected: Vec<SymbolInfo>| -> Vec<String> {
+            selected.into_iter().map(|info| info.symbol).collect()
+        };
+
+        let universe = Universe::from_config(&json!({}), &json!(["doge"]));
+        assert_eq!(
+            names(universe.select(&exchange, symbols.clone(), |_, v| v)),
+            vec!["ETHUSDT", "XRPUSDT", "OLDUSDT"]
+        );
+        assert!(!universe.due(10));
+
+        let universe = Universe::from_config(&json!({"active_only": true}), &json!([]));
+        assert_eq!(
+            names(universe.select(&exchange, symbols.clone(), |_, v| v)),
+            vec!["ETHUSDT", "XRPUSDT"]
+        );
+
+        // eth trades 24 * 100 * 10 = 24000 a day
+        let universe = Universe::from_config(
+            &json!({
+                "min_volume": 10000,
+                "whitelist": ["eth", "xrpusdt", "old"],
+                "refresh_ticks": 5,
+            }),
+            &json!([]),
+        );
+        assert_eq!(
+            names(universe.select(&exchange, symbols, |_, v| v)),
+            vec!["ETHUSDT"]
+        );
+        assert!(universe.due(10));
+        assert!(!universe.due(11));
+    }
+}
@@ -1,3 +1,5 @@
+use serde_json::Value;
+
 pub fn round_to(v: f64, len: u32) -> f64 {
     (v * 10i32.pow(len) as f64).floor() / 10i32.pow(len) as f64
 }
@@ -8,8 +10,45 @@ pub fn round_same(a: f64, b: f64) -> f64 {
     if v.len() == 1 {
         return b.floor();
     }
-    let len = v[1].len();
-    (b * 10i32.pow(len as u32) as f64).floor() / 10i32.pow(len as u32) as f64
+    // float noise like 0.00010250000000000001 would overflow an integer
+    // power, prices never need more than 10 decimals
+    let len = v[1].len().min(10);
+    let m = 10f64.powi(len as i32);
+    (b * m).floor() / m
+}
+
+// binance style kline period, e.g. "15m", "1h", "1d", in milliseconds
+pub fn period_ms(period: &str) -> Option<u64> {
+    if period.len() < 2 {
+        return None;
+    }
+    let (n, unit) = period.split_at(period.len() - 1);
+    let n: u64 = n.parse().ok()?;
+    let unit = match unit {
+        "s" => 1_000,
+        "m" => 60_000,
+        "h" => 3_600_000,
+        "d" => 86_400_000,
+        "w" => 7 * 86_400_000,
+        _ => return None,
+    };
+    Some(n * unit)
+}
+
+// names of `keys` that are missing from `config` or have the wrong type
+pub fn check_keys(config: &Value, strings: &[&str], numbers: &[&str]) -> Vec<String> {
+    let mut errors = vec![];
+    for key in strings {
+        if !config[key].is_string() {
+            errors.push(format!("{}: expect string", key));
+        }
+    }
+    for key in numbers {
+        if !config[key].is_number() {
+            errors.push(format!("{}: expect number", key));
+        }
+    }
+    errors
 }
 
 #[cfg(test)]
@@ -23,4 +62,13 @@ mod test {
         let ret = round_same(a, b);
         println!("ret: {:?}", ret);
     }
+
+    #[test]
+    fn test_period_ms() {
+        assert_eq!(period_ms("1m"), Some(60_000));
+        assert_eq!(period_ms("4h"), Some(4 * 3_600_000));
+        assert_eq!(period_ms("1d"), Some(86_400_000));
+        assert_eq!(period_ms("1M"), None);
+        assert_eq!(period_ms("h"), None);
+    }
 }